@@ -0,0 +1,7 @@
+-- Remove the processing failure columns
+ALTER TABLE videos
+    DROP COLUMN processing_error,
+    DROP COLUMN processing_error_message;
+
+-- Drop the processing error enum type
+DROP TYPE processing_error;
@@ -0,0 +1,17 @@
+-- Create enum type for classified processing failures
+CREATE TYPE processing_error AS ENUM (
+    'unsupported_format',
+    'unreadable_source',
+    'no_video_stream',
+    'invalid_dimensions',
+    'invalid_duration',
+    'corrupt_source',
+    'encoding_failed',
+    'storage_failed',
+    'internal'
+);
+
+-- Add the failure code and a human-readable explanation to videos
+ALTER TABLE videos
+    ADD COLUMN processing_error processing_error,
+    ADD COLUMN processing_error_message TEXT;
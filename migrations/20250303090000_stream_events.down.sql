@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS idx_videos_stream_id;
+
+ALTER TABLE videos
+    DROP COLUMN stream_id,
+    DROP COLUMN chapters_path;
+
+DROP TABLE IF EXISTS stream_events;
@@ -0,0 +1,18 @@
+-- Events received for a stream while it was live, used to build VOD chapters
+CREATE TABLE IF NOT EXISTS stream_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid (),
+    stream_id UUID NOT NULL REFERENCES streams (id) ON DELETE CASCADE,
+    event_type VARCHAR(100) NOT NULL,
+    payload JSONB NOT NULL,
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW ()
+);
+
+CREATE INDEX idx_stream_events_stream_id_occurred_at ON stream_events (stream_id, occurred_at);
+
+-- Link videos to the stream they are a recording of, along with the generated chapters track
+ALTER TABLE videos
+    ADD COLUMN stream_id UUID REFERENCES streams (id) ON DELETE SET NULL,
+    ADD COLUMN chapters_path VARCHAR(255);
+
+CREATE INDEX idx_videos_stream_id ON videos (stream_id);
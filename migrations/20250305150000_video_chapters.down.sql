@@ -0,0 +1,5 @@
+DROP TRIGGER IF EXISTS update_video_chapters_updated_at ON video_chapters;
+
+DROP TABLE IF EXISTS video_chapters;
+
+DROP TYPE IF EXISTS chapter_source;
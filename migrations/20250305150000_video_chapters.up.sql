@@ -0,0 +1,24 @@
+-- Create enum type for where a chapter came from
+CREATE TYPE chapter_source AS ENUM ('stream', 'scene', 'manual');
+
+-- Chapter markers for a video, proposed chapters are published once accepted
+CREATE TABLE video_chapters (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    video_id TEXT NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    start_seconds DOUBLE PRECISION NOT NULL,
+    end_seconds DOUBLE PRECISION NOT NULL,
+    title VARCHAR(200) NOT NULL,
+    source chapter_source NOT NULL,
+    accepted_at TIMESTAMPTZ DEFAULT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(video_id, position)
+);
+
+CREATE INDEX idx_video_chapters_video_id ON video_chapters(video_id);
+
+CREATE TRIGGER update_video_chapters_updated_at
+    BEFORE UPDATE ON video_chapters
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,4 @@
+ALTER TABLE videos
+    DROP COLUMN waveform_path,
+    DROP COLUMN preview_mp4_path,
+    DROP COLUMN preview_webp_path;
@@ -0,0 +1,5 @@
+-- Hover previews and waveform peaks are stored next to the HLS output
+ALTER TABLE videos
+    ADD COLUMN preview_webp_path VARCHAR(255),
+    ADD COLUMN preview_mp4_path VARCHAR(255),
+    ADD COLUMN waveform_path VARCHAR(255);
@@ -0,0 +1,3 @@
+-- Track the multipart upload of a video until it completes, so it can be aborted
+ALTER TABLE videos
+    ADD COLUMN upload_id VARCHAR(255);
@@ -0,0 +1,12 @@
+-- Remove storage tracking from videos
+ALTER TABLE videos
+    DROP COLUMN processed_size_bytes,
+    DROP COLUMN raw_size_bytes,
+    DROP COLUMN declared_size_bytes;
+
+-- Remove per-user quotas
+ALTER TABLE users
+    DROP COLUMN storage_quota_bytes;
+
+-- Remove role quotas
+DROP TABLE role_storage_quotas;
@@ -0,0 +1,22 @@
+-- Storage quota of each role in bytes, NULL means unlimited
+CREATE TABLE role_storage_quotas (
+    role user_role PRIMARY KEY,
+    quota_bytes BIGINT
+);
+
+-- Admins are unlimited, creators get 100 GiB and viewers 5 GiB
+INSERT INTO role_storage_quotas (role, quota_bytes) VALUES
+    ('admin', NULL),
+    ('creator', 107374182400),
+    ('viewer', 5368709120);
+
+-- Per-user quota that takes priority over the one of their role
+ALTER TABLE users
+    ADD COLUMN storage_quota_bytes BIGINT;
+
+-- Track what each video takes up in storage
+-- The declared size reserves space while the raw upload is still in progress
+ALTER TABLE videos
+    ADD COLUMN declared_size_bytes BIGINT,
+    ADD COLUMN raw_size_bytes BIGINT,
+    ADD COLUMN processed_size_bytes BIGINT;
@@ -0,0 +1,7 @@
+-- Remove content hash tracking from videos
+DROP INDEX videos_source_video_id_idx;
+DROP INDEX videos_user_id_content_sha256_idx;
+
+ALTER TABLE videos
+    DROP COLUMN source_video_id,
+    DROP COLUMN content_sha256;
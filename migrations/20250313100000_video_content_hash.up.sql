@@ -0,0 +1,8 @@
+-- Track the SHA-256 of each videos raw source so re-uploads can be spotted
+-- Videos that reuse the renditions of another point at the video whose storage they share
+ALTER TABLE videos
+    ADD COLUMN content_sha256 CHAR(64),
+    ADD COLUMN source_video_id TEXT;
+
+CREATE INDEX videos_user_id_content_sha256_idx ON videos(user_id, content_sha256);
+CREATE INDEX videos_source_video_id_idx ON videos(source_video_id);
@@ -0,0 +1,8 @@
+-- Remove raw master tiering
+ALTER TABLE videos
+    DROP COLUMN raw_accessed_at,
+    DROP COLUMN raw_cold_path,
+    DROP COLUMN raw_storage_tier;
+
+DROP TABLE role_storage_lifecycles;
+DROP TYPE storage_tier;
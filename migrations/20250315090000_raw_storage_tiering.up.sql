@@ -0,0 +1,22 @@
+-- Raw masters are either in the hot upload storage or moved to cold storage
+CREATE TYPE storage_tier AS ENUM ('hot', 'cold');
+
+-- Days without reprocessing after which each roles raw masters move to cold storage
+-- NULL keeps them hot forever
+CREATE TABLE role_storage_lifecycles (
+    role user_role PRIMARY KEY,
+    raw_cold_after_days INTEGER
+);
+
+-- Admins keep everything hot, creators go cold after 30 days and viewers after 7
+INSERT INTO role_storage_lifecycles (role, raw_cold_after_days) VALUES
+    ('admin', NULL),
+    ('creator', 30),
+    ('viewer', 7);
+
+-- Track which tier each raw master is in, and where it is while it's cold
+-- The hot path stays in raw_video_path so the source can be restored to it
+ALTER TABLE videos
+    ADD COLUMN raw_storage_tier storage_tier NOT NULL DEFAULT 'hot',
+    ADD COLUMN raw_cold_path TEXT,
+    ADD COLUMN raw_accessed_at TIMESTAMPTZ;
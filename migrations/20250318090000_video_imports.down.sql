@@ -0,0 +1,28 @@
+-- Remove the import source column
+ALTER TABLE videos
+    DROP COLUMN import_url;
+
+-- Enum values can't be dropped, so recreate the type without the import failure
+UPDATE videos
+SET processing_error = 'internal'
+WHERE processing_error = 'import_failed';
+
+ALTER TYPE processing_error RENAME TO processing_error_old;
+
+CREATE TYPE processing_error AS ENUM (
+    'unsupported_format',
+    'unreadable_source',
+    'no_video_stream',
+    'invalid_dimensions',
+    'invalid_duration',
+    'corrupt_source',
+    'encoding_failed',
+    'storage_failed',
+    'internal'
+);
+
+ALTER TABLE videos
+    ALTER COLUMN processing_error TYPE processing_error
+    USING processing_error::TEXT::processing_error;
+
+DROP TYPE processing_error_old;
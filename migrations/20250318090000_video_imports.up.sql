@@ -0,0 +1,6 @@
+-- Classify failures to fetch a video imported from another host
+ALTER TYPE processing_error ADD VALUE 'import_failed';
+
+-- Remember where imported videos were fetched from
+ALTER TABLE videos
+    ADD COLUMN import_url TEXT;
@@ -0,0 +1,20 @@
+-- Remove the description column
+ALTER TABLE videos
+    DROP COLUMN description;
+
+-- Enum values can't be dropped, so recreate the type with unlisted videos made private
+UPDATE videos
+SET privacy_status = 'private'
+WHERE privacy_status = 'unlisted';
+
+ALTER TYPE privacy_status RENAME TO privacy_status_old;
+
+CREATE TYPE privacy_status AS ENUM ('private', 'public');
+
+ALTER TABLE videos
+    ALTER COLUMN privacy_status DROP DEFAULT,
+    ALTER COLUMN privacy_status TYPE privacy_status
+    USING privacy_status::TEXT::privacy_status,
+    ALTER COLUMN privacy_status SET DEFAULT 'public';
+
+DROP TYPE privacy_status_old;
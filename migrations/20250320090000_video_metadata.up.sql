@@ -0,0 +1,6 @@
+-- Let videos be watched by anyone with the link without being listed
+ALTER TYPE privacy_status ADD VALUE 'unlisted';
+
+-- Add a description creators can edit after upload
+ALTER TABLE videos
+    ADD COLUMN description TEXT;
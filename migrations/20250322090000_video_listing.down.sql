@@ -0,0 +1,6 @@
+-- Remove the listing index
+DROP INDEX IF EXISTS idx_videos_created_at_id;
+
+-- Remove the duration column
+ALTER TABLE videos
+    DROP COLUMN duration_seconds;
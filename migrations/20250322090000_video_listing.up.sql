@@ -0,0 +1,6 @@
+-- Record how long videos are, so listings can be sorted by it
+ALTER TABLE videos
+    ADD COLUMN duration_seconds DOUBLE PRECISION;
+
+-- Keep cursor pagination through the newest and oldest videos fast
+CREATE INDEX idx_videos_created_at_id ON videos (created_at, id);
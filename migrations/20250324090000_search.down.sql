@@ -0,0 +1,10 @@
+-- Remove the search indexes
+DROP INDEX IF EXISTS idx_users_username_search;
+DROP INDEX IF EXISTS idx_videos_search_vector;
+
+-- Remove the search document and tags
+ALTER TABLE videos
+    DROP COLUMN search_vector,
+    DROP COLUMN tags;
+
+DROP FUNCTION video_search_document(TEXT, TEXT, TEXT[]);
@@ -0,0 +1,20 @@
+-- Build the searchable document of a video, titles weigh the most and descriptions the least
+CREATE FUNCTION video_search_document(title TEXT, description TEXT, tags TEXT[])
+RETURNS tsvector
+LANGUAGE SQL IMMUTABLE PARALLEL SAFE
+AS $$
+    SELECT setweight(to_tsvector('english', COALESCE(title, '')), 'A')
+        || setweight(to_tsvector('english', COALESCE(array_to_string(tags, ' '), '')), 'B')
+        || setweight(to_tsvector('english', COALESCE(description, '')), 'C')
+$$;
+
+-- Add tags and keep the search document of every video up to date
+ALTER TABLE videos
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN search_vector tsvector
+        GENERATED ALWAYS AS (video_search_document(title, description, tags)) STORED;
+
+CREATE INDEX idx_videos_search_vector ON videos USING GIN (search_vector);
+
+-- Usernames aren't words, so they're indexed without stemming
+CREATE INDEX idx_users_username_search ON users USING GIN (to_tsvector('simple', username));
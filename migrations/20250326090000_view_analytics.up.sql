@@ -0,0 +1,30 @@
+-- Playback sessions, kept up to date by heartbeats from the player
+CREATE TABLE view_sessions (
+    id UUID PRIMARY KEY,
+    video_id TEXT NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+    viewer_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- Who is watching, the user when signed in and the players session otherwise
+    viewer_key TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_heartbeat_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    watched_seconds DOUBLE PRECISION NOT NULL DEFAULT 0,
+    last_position_seconds DOUBLE PRECISION NOT NULL DEFAULT 0,
+    -- Which parts of the video were watched, as indexes into equal slices of its length
+    retention_buckets SMALLINT[] NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX idx_view_sessions_viewer ON view_sessions (video_id, viewer_key, last_heartbeat_at);
+CREATE INDEX idx_view_sessions_started_at ON view_sessions (started_at);
+
+-- Views and watch time per video per day, rolled up from the sessions
+CREATE TABLE video_daily_stats (
+    video_id TEXT NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    views BIGINT NOT NULL DEFAULT 0,
+    unique_viewers BIGINT NOT NULL DEFAULT 0,
+    watched_seconds DOUBLE PRECISION NOT NULL DEFAULT 0,
+    -- How many views reached each slice of the video
+    retention_counts BIGINT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (video_id, day)
+);
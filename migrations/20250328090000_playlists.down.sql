@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS playlist_items;
+
+DROP TRIGGER IF EXISTS update_playlists_updated_at ON playlists;
+
+DROP TABLE IF EXISTS playlists;
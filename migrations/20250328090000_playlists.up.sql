@@ -0,0 +1,31 @@
+-- Playlists group videos into an ordered series
+CREATE TABLE playlists (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    title VARCHAR(100) NOT NULL,
+    description TEXT,
+    privacy_status privacy_status NOT NULL DEFAULT 'public',
+    -- Storage key of the uploaded cover image
+    cover_image_path TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_playlists_user_id ON playlists(user_id);
+
+CREATE TRIGGER update_playlists_updated_at
+    BEFORE UPDATE ON playlists
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+-- The videos in a playlist, in order
+CREATE TABLE playlist_items (
+    playlist_id UUID NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+    video_id TEXT NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (playlist_id, video_id)
+);
+
+CREATE INDEX idx_playlist_items_position ON playlist_items(playlist_id, position);
+CREATE INDEX idx_playlist_items_video_id ON playlist_items(video_id);
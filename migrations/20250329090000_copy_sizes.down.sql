@@ -0,0 +1,4 @@
+UPDATE videos
+SET raw_size_bytes = NULL,
+    processed_size_bytes = NULL
+WHERE source_video_id IS NOT NULL;
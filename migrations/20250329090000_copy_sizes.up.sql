@@ -0,0 +1,9 @@
+-- Copies carry the sizes of the storage they share, so it's still charged for once the
+-- original is deleted
+UPDATE videos c
+SET raw_size_bytes = s.raw_size_bytes,
+    processed_size_bytes = s.processed_size_bytes
+FROM videos s
+WHERE c.source_video_id = s.id
+AND c.raw_size_bytes IS NULL
+AND c.processed_size_bytes IS NULL;
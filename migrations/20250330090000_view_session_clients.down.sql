@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_view_sessions_client;
+
+ALTER TABLE view_sessions
+    DROP COLUMN IF EXISTS client_key;
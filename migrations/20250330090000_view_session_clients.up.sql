@@ -0,0 +1,6 @@
+-- Where signed out viewers watched from, hashed, so they can't start views without limit
+ALTER TABLE view_sessions
+    ADD COLUMN client_key TEXT;
+
+CREATE INDEX idx_view_sessions_client ON view_sessions (client_key, started_at)
+    WHERE client_key IS NOT NULL;
@@ -1,9 +1,13 @@
-use super::config::Config;
+use super::{config::Config, signing::PlaybackSigner};
 use crate::{
-    db::connect_to_database, event::Stream, nats::create_nats_client, queue::Queue,
-    storage::s3::create_s3_client,
+    db::connect_to_database,
+    event::Stream,
+    nats::create_nats_client,
+    queue::Queue,
+    storage::{storage_from_env, tier::ColdStorage, Storage},
 };
 use sqlx::PgPool;
+use std::sync::Arc;
 
 /// Shared state available to the API
 pub struct AppState {
@@ -11,7 +15,9 @@ pub struct AppState {
     pub job_queue: Queue,
     pub event_stream: Stream,
     pub config: Config,
-    pub s3_client: aws_sdk_s3::Client,
+    pub storage: Arc<dyn Storage>,
+    pub cold_storage: ColdStorage,
+    pub playback_signer: PlaybackSigner,
 }
 
 impl AppState {
@@ -21,8 +27,12 @@ impl AppState {
             .await
             .expect("Could not connect to database");
 
-        // Create the S3 Client
-        let s3_client = create_s3_client().await;
+        // Connect to the configured storage backend
+        let storage = storage_from_env().await?;
+        let cold_storage = ColdStorage::from_env(storage.clone()).await?;
+
+        // Sign playback URLs with a secret from the environment
+        let playback_signer = PlaybackSigner::from_env();
 
         // Create a NATS client
         let nats_client = create_nats_client().await?;
@@ -42,7 +52,9 @@ impl AppState {
             db,
             job_queue,
             event_stream,
-            s3_client,
+            storage,
+            cold_storage,
+            playback_signer,
         })
     }
 }
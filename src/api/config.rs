@@ -1,18 +1,26 @@
+use crate::vod::upload::UploadLimits;
+
 pub const DEFAULT_PORT: &str = "3000"; // This is stored as a string to match environment vars
 
 /// Global Configuration for the API Server
 pub struct Config {
     pub port: String,
     pub upload_dir: Option<String>,
-    pub upload_bucket: Option<String>,
+    pub api_url: String,
+    pub upload_limits: UploadLimits,
+    /// Whether the API sits behind a proxy that says who clients are in X-Forwarded-For
+    pub trust_forwarded_for: bool,
 }
 
 impl Config {
     pub fn new() -> Self {
+        let port = Self::get_port();
         Config {
-            port: Self::get_port(),
+            api_url: Self::get_api_url(&port),
+            port,
             upload_dir: Self::get_upload_dir(),
-            upload_bucket: Self::get_upload_bucket(),
+            upload_limits: UploadLimits::from_env(),
+            trust_forwarded_for: Self::get_trust_forwarded_for(),
         }
     }
     /// Gets the port from environment variables
@@ -22,6 +30,18 @@ impl Config {
             .parse()
             .expect("PORT must be a number")
     }
+    /// Gets the public URL of the API from environment variables, used for building links
+    pub fn get_api_url(port: &str) -> String {
+        std::env::var("API_URL")
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|_| format!("http://localhost:{}", port))
+    }
+    /// Gets whether X-Forwarded-For can be trusted from environment variables
+    pub fn get_trust_forwarded_for() -> bool {
+        std::env::var("TRUST_FORWARDED_FOR")
+            .map(|v| v == "true")
+            .unwrap_or(false)
+    }
     /// Formats the host and port into an address for a TCPListener to bind to
     pub fn get_address(&self) -> String {
         format!("{}:{}", "0.0.0.0", &self.port)
@@ -36,8 +56,4 @@ impl Config {
             Err(_) => None,
         }
     }
-    /// Gets the Cloudflare R2 upload bucket to use from environment
-    pub fn get_upload_bucket() -> Option<String> {
-        std::env::var("UPLOAD_BUCKET").ok()
-    }
 }
@@ -3,4 +3,5 @@ pub mod config;
 pub mod jwt;
 pub mod middleware;
 pub mod routes;
+pub mod signing;
 pub mod twitch;
@@ -0,0 +1,378 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::Uuid;
+
+use crate::{
+    api::{app_state::AppState, routes::video::find_video},
+    db::{
+        analytics::{
+            retention_buckets, NewViewSession, VideoDailyStats, ViewSession, RETENTION_BUCKETS,
+        },
+        User,
+    },
+};
+
+/// Heartbeats closer together than this on the same session are turned away
+const MIN_HEARTBEAT_INTERVAL_SECONDS: f64 = 5.0;
+/// Most watch time a single heartbeat is credited with, so a player that went quiet isn't counted
+/// as watching the whole time
+const MAX_HEARTBEAT_CREDIT_SECONDS: f64 = 30.0;
+/// How long a viewer can go quiet before coming back to a video starts a new view
+const SESSION_IDLE_MINUTES: i64 = 30;
+/// Most views a signed in viewer can start in an hour
+const MAX_SESSIONS_PER_HOUR: i64 = 120;
+/// Most views signed out viewers can start from the same address in an hour, higher since
+/// everyone behind the same network shares it
+const MAX_ANONYMOUS_SESSIONS_PER_HOUR: i64 = 360;
+/// Days of stats returned unless asked for, and the most that can be asked for at once
+const DEFAULT_STATS_DAYS: i64 = 28;
+const MAX_STATS_DAYS: i64 = 366;
+
+#[derive(Deserialize, Debug)]
+pub struct HeartbeatRequest {
+    /// Picked by the player when playback starts, and sent with every heartbeat after
+    session_id: Uuid,
+    /// Where playback is in the video
+    position_seconds: f64,
+    /// Paused players keep their session alive without adding watch time
+    #[serde(default = "default_playing")]
+    playing: bool,
+}
+
+fn default_playing() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+pub struct HeartbeatResponse {
+    /// The session the heartbeat was counted in, which later heartbeats should use
+    session_id: Uuid,
+}
+
+/// Gets the address a request came from, the one the proxy saw when it's trusted to say
+/// The last address in X-Forwarded-For is the one added by the proxy, anything before it came
+/// from the client
+fn client_ip(trust_forwarded_for: bool, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .and_then(|ip| ip.trim().parse().ok());
+    match forwarded.filter(|_| trust_forwarded_for) {
+        Some(ip) => ip,
+        None => peer.ip(),
+    }
+}
+
+/// Hashes the address of a signed out viewer, so it isn't kept as is
+/// IPv6 clients usually get a whole /64 to themselves, so they're told apart by that instead
+fn client_key(ip: IpAddr) -> String {
+    let network = match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.to_string(),
+            None => {
+                let segments = ip.segments();
+                format!(
+                    "{:x}:{:x}:{:x}:{:x}::/64",
+                    segments[0], segments[1], segments[2], segments[3]
+                )
+            }
+        },
+    };
+    hex::encode(Sha256::digest(network.as_bytes()))
+}
+
+fn db_error(action: &str, video_id: &str, e: sqlx::Error) -> StatusCode {
+    tracing::error!("Error {} for video {}: {}", action, video_id, e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Records that a video is being watched, counting views and watch time
+/// Viewers coming back to a video soon after leaving it carry on their earlier view, and
+/// heartbeats sent faster than players do are turned away
+pub async fn record_heartbeat(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(video_id): Path<String>,
+    Json(request): Json<HeartbeatRequest>,
+) -> Result<Json<HeartbeatResponse>, StatusCode> {
+    let video = find_video(&state, &video_id).await?;
+    if !video.is_viewable_by(user.as_ref()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let position = request.position_seconds;
+    if !position.is_finite()
+        || position < 0.0
+        || video
+            .duration_seconds
+            .is_some_and(|duration| position > duration + 1.0)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let viewer_id = user.as_ref().map(|user| user.id);
+    // Signed in viewers are the same viewer on every device, anyone else is only known by
+    // their players session
+    let viewer_key = |session_id: Uuid| match viewer_id {
+        Some(user_id) => format!("user:{}", user_id),
+        None => format!("session:{}", session_id),
+    };
+    let now = Utc::now();
+
+    // Sessions are only carried on by the viewer and video they were started for
+    let requested = ViewSession::by_id(&state.db, request.session_id)
+        .await
+        .map_err(|e| db_error("finding view session", &video.id, e))?;
+    let session_taken = requested.is_some();
+    let session = match requested.filter(|session| {
+        session.video_id == video.id && session.viewer_key == viewer_key(session.id)
+    }) {
+        Some(session) => Some(session),
+        None if viewer_id.is_some() => ViewSession::latest_since(
+            &state.db,
+            &video.id,
+            &viewer_key(request.session_id),
+            now - Duration::minutes(SESSION_IDLE_MINUTES),
+        )
+        .await
+        .map_err(|e| db_error("finding recent view session", &video.id, e))?,
+        None => None,
+    };
+
+    let session = match session {
+        Some(session) => {
+            let elapsed = (now - session.last_heartbeat_at).num_milliseconds() as f64 / 1000.0;
+            if elapsed < MIN_HEARTBEAT_INTERVAL_SECONDS {
+                return Err(StatusCode::TOO_MANY_REQUESTS);
+            }
+            let watched = match request.playing {
+                true => elapsed.min(MAX_HEARTBEAT_CREDIT_SECONDS),
+                false => 0.0,
+            };
+            // Everything between the two positions was watched if playback got there on its own,
+            // allowing for players running faster than normal
+            let progress = position - session.last_position_seconds;
+            let played_through = request.playing && (0.0..=watched * 2.0 + 1.0).contains(&progress);
+            let from = match played_through {
+                true => session.last_position_seconds,
+                false => position,
+            };
+            let buckets = retention_buckets(from, position, video.duration_seconds);
+            ViewSession::record_heartbeat(
+                &state.db,
+                session.id,
+                session.last_heartbeat_at,
+                now,
+                watched,
+                position,
+                &buckets,
+            )
+            .await
+            .map_err(|e| db_error("recording heartbeat", &video.id, e))?
+            // Another heartbeat for the session got in first
+            .ok_or(StatusCode::TOO_MANY_REQUESTS)?
+        }
+        None => {
+            // Never take over a session id someone else is using
+            let id = match session_taken {
+                true => Uuid::new_v4(),
+                false => request.session_id,
+            };
+            let viewer_key = viewer_key(id);
+            // Signed out viewers can pick a new session every time, so they're limited by
+            // where they watch from instead
+            let client_key = viewer_id
+                .is_none()
+                .then(|| client_key(client_ip(state.config.trust_forwarded_for, peer, &headers)));
+            let since = now - Duration::hours(1);
+            let (started, limit) = match &client_key {
+                None => (
+                    ViewSession::count_started_since(&state.db, &viewer_key, since).await,
+                    MAX_SESSIONS_PER_HOUR,
+                ),
+                Some(client_key) => (
+                    ViewSession::count_started_by_client_since(&state.db, client_key, since).await,
+                    MAX_ANONYMOUS_SESSIONS_PER_HOUR,
+                ),
+            };
+            let started = started.map_err(|e| db_error("counting view sessions", &video.id, e))?;
+            if started >= limit {
+                tracing::warn!("Viewer {} is starting too many view sessions", viewer_key);
+                return Err(StatusCode::TOO_MANY_REQUESTS);
+            }
+            ViewSession::create(
+                &state.db,
+                NewViewSession {
+                    id,
+                    video_id: &video.id,
+                    viewer_id,
+                    viewer_key: &viewer_key,
+                    client_key: client_key.as_deref(),
+                    at: now,
+                    position_seconds: position,
+                    buckets: retention_buckets(position, position, video.duration_seconds),
+                },
+            )
+            .await
+            .map_err(|e| db_error("starting view session", &video.id, e))?
+        }
+    };
+
+    Ok(Json(HeartbeatResponse {
+        session_id: session.id,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VideoStatsQuery {
+    /// First day to include, defaults to four weeks before `to`
+    from: Option<NaiveDate>,
+    /// Last day to include, defaults to today
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+pub struct DailyStats {
+    day: NaiveDate,
+    views: i64,
+    unique_viewers: i64,
+    watched_seconds: f64,
+}
+
+#[derive(Serialize)]
+pub struct VideoStatsResponse {
+    video_id: String,
+    from: NaiveDate,
+    to: NaiveDate,
+    views: i64,
+    /// Unique viewers of each day added up, so viewers are counted once on every day they watched
+    viewer_days: i64,
+    watched_seconds: f64,
+    average_watch_seconds: f64,
+    /// Share of views that reached each equal slice of the video, from its start to its end
+    retention: Vec<f64>,
+    days: Vec<DailyStats>,
+}
+
+/// Gets the views, watch time and retention of a video for its owner
+/// Stats are rolled up periodically, so the latest views can take a while to show up
+pub async fn get_video_stats(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+    Query(query): Query<VideoStatsQuery>,
+) -> Result<Json<VideoStatsResponse>, StatusCode> {
+    let Some(user) = user else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let video = find_video(&state, &video_id).await?;
+    // Only the owner gets to see how their video is doing
+    if video.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to view stats of video {} owned by {}",
+            user.id,
+            video.id,
+            video.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query
+        .from
+        .unwrap_or(to - Duration::days(DEFAULT_STATS_DAYS - 1));
+    if from > to || (to - from).num_days() >= MAX_STATS_DAYS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let daily = VideoDailyStats::by_video_id(&state.db, &video.id, from, to)
+        .await
+        .map_err(|e| db_error("getting stats", &video.id, e))?;
+    let views: i64 = daily.iter().map(|day| day.views).sum();
+    let viewer_days = daily.iter().map(|day| day.unique_viewers).sum();
+    let watched_seconds: f64 = daily.iter().map(|day| day.watched_seconds).sum();
+    let mut reached = vec![0i64; RETENTION_BUCKETS];
+    for day in &daily {
+        for (total, count) in reached.iter_mut().zip(&day.retention_counts) {
+            *total += count;
+        }
+    }
+    let share = |count: f64| match views {
+        0 => 0.0,
+        views => count / views as f64,
+    };
+
+    Ok(Json(VideoStatsResponse {
+        video_id: video.id,
+        from,
+        to,
+        views,
+        viewer_days,
+        watched_seconds,
+        average_watch_seconds: share(watched_seconds),
+        retention: reached
+            .into_iter()
+            .map(|count| share(count as f64))
+            .collect(),
+        days: daily
+            .into_iter()
+            .map(|day| DailyStats {
+                day: day.day,
+                views: day.views,
+                unique_viewers: day.unique_viewers,
+                watched_seconds: day.watched_seconds,
+            })
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.2:50000".parse().unwrap()
+    }
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn client_ip_only_trusts_the_address_added_by_the_proxy() {
+        let headers = forwarded("203.0.113.9, 198.51.100.7");
+        assert_eq!(
+            client_ip(true, peer(), &headers),
+            "198.51.100.7".parse::<IpAddr>().unwrap()
+        );
+        // Without a proxy, clients could say they're anyone
+        assert_eq!(client_ip(false, peer(), &headers), peer().ip());
+        assert_eq!(client_ip(true, peer(), &HeaderMap::new()), peer().ip());
+        assert_eq!(client_ip(true, peer(), &forwarded("garbage")), peer().ip());
+    }
+
+    #[test]
+    fn client_keys_group_ipv6_clients_by_network() {
+        let key = |ip: &str| client_key(ip.parse().unwrap());
+        assert_eq!(key("2001:db8:1:2::1"), key("2001:db8:1:2:ffff::9"));
+        assert_ne!(key("2001:db8:1:2::1"), key("2001:db8:1:3::1"));
+        assert_eq!(key("::ffff:198.51.100.7"), key("198.51.100.7"));
+        assert_ne!(key("198.51.100.7"), key("198.51.100.8"));
+    }
+}
@@ -0,0 +1,293 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use std::sync::Arc;
+
+use crate::{
+    api::{app_state::AppState, routes::video::find_video},
+    db::{
+        chapters::{ChapterSource, NewChapter, VideoChapter},
+        ProcessingStatus, User, Video,
+    },
+    storage::PutOptions,
+    vod::chapters::{to_webvtt, Chapter, CHAPTERS_FILE},
+};
+
+/// Longest title a chapter can have
+const MAX_TITLE_LENGTH: usize = 200;
+
+#[derive(Serialize)]
+pub struct ChapterData {
+    id: Uuid,
+    start: f64,
+    end: f64,
+    title: String,
+    source: ChapterSource,
+    accepted: bool,
+}
+
+impl From<VideoChapter> for ChapterData {
+    fn from(chapter: VideoChapter) -> Self {
+        ChapterData {
+            id: chapter.id,
+            start: chapter.start_seconds,
+            end: chapter.end_seconds,
+            title: chapter.title,
+            source: chapter.source,
+            accepted: chapter.accepted_at.is_some(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ChaptersResponse {
+    chapters: Vec<ChapterData>,
+    chapters_path: Option<String>,
+}
+
+/// Gets the chapters of a video
+/// The owner also gets the proposals that have not been accepted yet
+pub async fn get_chapters(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+) -> Result<Json<ChaptersResponse>, StatusCode> {
+    let video = find_video(&state, &video_id).await?;
+    if !video.is_viewable_by(user.as_ref()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let is_owner = user.is_some_and(|user| user.id == video.user_id);
+
+    let chapters = if is_owner {
+        VideoChapter::by_video_id(&video.id, &state.db).await
+    } else {
+        VideoChapter::accepted_by_video_id(&video.id, &state.db).await
+    }
+    .map_err(|e| {
+        tracing::error!("Error getting chapters for video {}: {}", video.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(ChaptersResponse {
+        chapters: chapters.into_iter().map(ChapterData::from).collect(),
+        chapters_path: video.chapters_path,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ChapterUpdate {
+    /// The chapter being edited, if it already exists
+    id: Option<Uuid>,
+    start: f64,
+    title: String,
+    accepted: bool,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateChaptersRequest {
+    chapters: Vec<ChapterUpdate>,
+}
+
+/// Checks that chapters start at the beginning, are in order and have sensible titles
+fn validate_chapters(chapters: &[ChapterUpdate], duration: f64) -> Result<(), String> {
+    if let Some(first) = chapters.first() {
+        if first.start != 0.0 {
+            return Err("The first chapter must start at 0".to_string());
+        }
+    }
+    for pair in chapters.windows(2) {
+        if pair[1].start <= pair[0].start {
+            return Err("Chapters must be in order of their start times".to_string());
+        }
+    }
+    if let Some(last) = chapters.last() {
+        if last.start >= duration {
+            return Err("Chapters must start before the end of the video".to_string());
+        }
+    }
+    for chapter in chapters {
+        if !chapter.start.is_finite() {
+            return Err("Chapter start times must be numbers".to_string());
+        }
+        let length = chapter.title.trim().chars().count();
+        if length == 0 || length > MAX_TITLE_LENGTH {
+            return Err(format!(
+                "Chapter titles must be between 1 and {} characters",
+                MAX_TITLE_LENGTH
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the chapters of a video, and publishes the accepted ones if the video is processed
+/// Chapters can only be set once processing has found out how long the video is
+pub async fn update_chapters(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+    Json(request): Json<UpdateChaptersRequest>,
+) -> Result<Json<ChaptersResponse>, StatusCode> {
+    let Some(user) = user else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let video = find_video(&state, &video_id).await?;
+    if video.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to edit chapters of video {} owned by {}",
+            user.id,
+            video.id,
+            video.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // The last chapter runs to the end of the video, so its length has to be known
+    let duration = match (video.duration_seconds, request.chapters.is_empty()) {
+        (Some(duration), _) => duration,
+        (None, true) => 0.0,
+        (None, false) => {
+            tracing::debug!(
+                "Rejected chapters for video {} without a known duration",
+                video.id
+            );
+            return Err(StatusCode::CONFLICT);
+        }
+    };
+    let existing = VideoChapter::by_video_id(&video.id, &state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error getting chapters for video {}: {}", video.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    validate_chapters(&request.chapters, duration).map_err(|e| {
+        tracing::debug!("Rejected chapters for video {}: {}", video.id, e);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let new_chapters: Vec<NewChapter> = request
+        .chapters
+        .iter()
+        .enumerate()
+        .map(|(index, update)| {
+            let title = update.title.trim().to_string();
+            // Chapters keep where they came from unless the creator changed them
+            let source = existing
+                .iter()
+                .find(|chapter| Some(chapter.id) == update.id)
+                .filter(|chapter| chapter.start_seconds == update.start && chapter.title == title)
+                .map(|chapter| chapter.source)
+                .unwrap_or(ChapterSource::Manual);
+            let end = request
+                .chapters
+                .get(index + 1)
+                .map(|next| next.start)
+                .unwrap_or(duration);
+            NewChapter {
+                start_seconds: update.start,
+                end_seconds: end,
+                title,
+                source,
+                accepted: update.accepted,
+            }
+        })
+        .collect();
+
+    let saved = VideoChapter::replace_all(&video.id, &new_chapters, &state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error saving chapters for video {}: {}", video.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let chapters_path = if video.processing_status == ProcessingStatus::Completed {
+        publish_chapters(&state, &video, &saved).await?
+    } else {
+        // Unprocessed videos get their chapters published by the runner
+        video.chapters_path
+    };
+
+    Ok(Json(ChaptersResponse {
+        chapters: saved.into_iter().map(ChapterData::from).collect(),
+        chapters_path,
+    }))
+}
+
+/// Uploads the accepted chapters as a WebVTT track, returning its remote path
+async fn publish_chapters(
+    state: &AppState,
+    video: &Video,
+    chapters: &[VideoChapter],
+) -> Result<Option<String>, StatusCode> {
+    let accepted: Vec<Chapter> = chapters
+        .iter()
+        .filter(|chapter| chapter.accepted_at.is_some())
+        .map(Chapter::from)
+        .collect();
+
+    let chapters_path = if accepted.is_empty() {
+        None
+    } else {
+        // Chapters belong to this video alone, even when it shares renditions with another
+        let path = format!("{}/{}", video.own_storage_prefix(), CHAPTERS_FILE);
+        state
+            .storage
+            .put(
+                &path,
+                to_webvtt(&accepted).into(),
+                &PutOptions::for_key(&path),
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to upload chapters for video {}: {}", video.id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        Some(path)
+    };
+
+    Video::set_chapters_path(&state.db, &video.id, chapters_path.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Error saving chapters path for video {}: {}", video.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(chapters_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(start: f64) -> ChapterUpdate {
+        ChapterUpdate {
+            id: None,
+            start,
+            title: "Chapter".to_string(),
+            accepted: true,
+        }
+    }
+
+    #[test]
+    fn accepts_chapters_inside_the_video() {
+        let chapters = [chapter(0.0), chapter(30.0), chapter(90.0)];
+        assert!(validate_chapters(&chapters, 120.0).is_ok());
+        assert!(validate_chapters(&[], 0.0).is_ok());
+    }
+
+    #[test]
+    fn rejects_chapters_starting_at_or_after_the_end() {
+        assert!(validate_chapters(&[chapter(0.0), chapter(120.0)], 120.0).is_err());
+        // A lone chapter on a video of unknown length would have no length of its own
+        assert!(validate_chapters(&[chapter(0.0)], 0.0).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_order_chapters() {
+        assert!(validate_chapters(&[chapter(5.0)], 120.0).is_err());
+        assert!(validate_chapters(&[chapter(0.0), chapter(60.0), chapter(30.0)], 120.0).is_err());
+    }
+}
@@ -1,5 +1,10 @@
+pub mod analytics;
 pub mod auth;
+pub mod chapters;
 pub mod health;
+pub mod playback;
+pub mod playlist;
+pub mod search;
 pub mod upload;
 pub mod user;
 pub mod video;
@@ -0,0 +1,244 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Serialize;
+use std::{collections::HashMap, sync::Arc};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    api::{app_state::AppState, routes::video::find_video, signing::PlaybackToken},
+    db::{ProcessingStatus, User, Video},
+    error::StorageError,
+    storage::PutOptions,
+    vod::playlist::{
+        is_playlist_uri, is_segment_uri, playlist_uris, resolve_relative, rewrite_playlist,
+        PLAYLIST_CONTENT_TYPE,
+    },
+};
+
+#[derive(Serialize)]
+pub struct PlaybackResponse {
+    master_playlist_url: String,
+    chapters_url: Option<String>,
+    preview_webp_url: Option<String>,
+    preview_mp4_url: Option<String>,
+    waveform_url: Option<String>,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Gets the prefixes a videos files can be under, its own files first so they win over shared ones
+fn video_prefixes(video: &Video) -> Vec<String> {
+    let mut prefixes = vec![video.own_storage_prefix()];
+    if video.storage_prefix() != video.own_storage_prefix() {
+        prefixes.push(video.storage_prefix());
+    }
+    prefixes
+}
+
+/// Builds a signed URL for a file stored under one of the videos prefixes
+fn signed_url(state: &AppState, video: &Video, key: &str, token: &PlaybackToken) -> Option<String> {
+    let path = video_prefixes(video)
+        .iter()
+        .find_map(|prefix| key.strip_prefix(&format!("{}/", prefix)))?;
+    Some(format!(
+        "{}/playback/{}/{}?{}",
+        state.config.api_url,
+        video.id,
+        path,
+        token.to_query()
+    ))
+}
+
+/// Resolves playback for a video the caller is allowed to watch into short-lived signed URLs
+pub async fn get_playback(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+) -> Result<Json<PlaybackResponse>, StatusCode> {
+    let video = find_video(&state, &video_id).await?;
+    // Private videos look the same as missing ones to everyone but their owner
+    if !video.is_viewable_by(user.as_ref()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let master_playlist_path = match (&video.processing_status, &video.processed_video_path) {
+        (ProcessingStatus::Completed, Some(path)) => path,
+        _ => return Err(StatusCode::CONFLICT),
+    };
+
+    let token = state.playback_signer.issue(&video.id);
+    let sign = |key: &Option<String>| {
+        key.as_deref()
+            .and_then(|key| signed_url(&state, &video, key, &token))
+    };
+    let master_playlist_url =
+        signed_url(&state, &video, master_playlist_path, &token).ok_or_else(|| {
+            tracing::error!(
+                "Master playlist {} of video {} is outside its storage prefix",
+                master_playlist_path,
+                video.id
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(PlaybackResponse {
+        master_playlist_url,
+        chapters_url: sign(&video.chapters_path),
+        preview_webp_url: sign(&video.preview_webp_path),
+        preview_mp4_url: sign(&video.preview_mp4_path),
+        waveform_url: sign(&video.waveform_path),
+        expires_at: token.expires_at(),
+    }))
+}
+
+/// Checks whether a key is one of the files playback hands out
+/// That is the playlists and segments of the processed output and the videos own extras,
+/// never the raw source or anything else that happens to live under its prefixes
+fn is_playback_file(video: &Video, key: &str) -> bool {
+    if video.raw_video_path == key {
+        return false;
+    }
+    let extras = [
+        &video.chapters_path,
+        &video.preview_webp_path,
+        &video.preview_mp4_path,
+        &video.waveform_path,
+    ];
+    if extras.iter().any(|path| path.as_deref() == Some(key)) {
+        return true;
+    }
+    let Some(output_dir) = video
+        .processed_video_path
+        .as_deref()
+        .and_then(|path| path.rsplit_once('/'))
+        .map(|(dir, _)| dir)
+    else {
+        return false;
+    };
+    key.strip_prefix(output_dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+        && (is_playlist_uri(key) || is_segment_uri(key))
+}
+
+fn storage_status(key: &str, error: StorageError) -> StatusCode {
+    match error {
+        StorageError::NotFound(_) => StatusCode::NOT_FOUND,
+        e => {
+            tracing::error!("Error reading {} from storage: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Serves a playback file of a video to anyone holding a valid playback token for it
+/// Playlists are rewritten so every file they reference is signed as well
+pub async fn get_playback_file(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path((video_id, path)): Path<(String, String)>,
+    Query(token): Query<PlaybackToken>,
+) -> Result<Response, StatusCode> {
+    state
+        .playback_signer
+        .verify(&video_id, &token)
+        .map_err(|e| {
+            tracing::debug!("Rejected playback token for video {}: {:?}", video_id, e);
+            StatusCode::FORBIDDEN
+        })?;
+    // Normalizing the path keeps requests from escaping the videos prefix
+    let path = resolve_relative("", &path)
+        .filter(|path| !path.is_empty())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let video = find_video(&state, &video_id).await?;
+    // Tokens outlive changes to the video, so going private or back to processing cuts them off
+    if !video.is_viewable_by(user.as_ref())
+        || video.processing_status != ProcessingStatus::Completed
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let mut found = None;
+    for prefix in video_prefixes(&video) {
+        let key = format!("{}/{}", prefix, path);
+        if !is_playback_file(&video, &key) {
+            continue;
+        }
+        match state.storage.get(&key).await {
+            Ok(reader) => {
+                found = Some((prefix, key, reader));
+                break;
+            }
+            Err(StorageError::NotFound(_)) => continue,
+            Err(e) => return Err(storage_status(&key, e)),
+        }
+    }
+    let (prefix, key, mut reader) = found.ok_or(StatusCode::NOT_FOUND)?;
+
+    if !is_playlist_uri(&path) {
+        let content_type = PutOptions::for_key(&key)
+            .content_type
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        return Ok((
+            [
+                (header::CONTENT_TYPE, content_type),
+                (
+                    header::CACHE_CONTROL,
+                    format!("private, max-age={}", token.remaining().as_secs()),
+                ),
+            ],
+            Body::from_stream(ReaderStream::new(reader)),
+        )
+            .into_response());
+    }
+
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents).await.map_err(|e| {
+        tracing::error!("Error reading playlist {}: {}", key, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut replacements = HashMap::new();
+    for uri in playlist_uris(&contents) {
+        if replacements.contains_key(uri) {
+            continue;
+        }
+        // Nested playlists come back through here, relative to this one, to be rewritten too
+        if is_playlist_uri(uri) {
+            replacements.insert(uri.to_string(), format!("{}?{}", uri, token.to_query()));
+            continue;
+        }
+        // Absolute URIs are left alone
+        let Some(media_path) = resolve_relative(&path, uri) else {
+            continue;
+        };
+        let media_key = format!("{}/{}", prefix, media_path);
+        let signed = match state
+            .storage
+            .presign_get(&media_key, token.remaining())
+            .await
+        {
+            Ok(url) => url,
+            // Backends that can't presign have their media served through here instead
+            Err(StorageError::Unsupported(_)) => format!("{}?{}", uri, token.to_query()),
+            Err(e) => {
+                tracing::error!("Error signing {}: {}", media_key, e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        replacements.insert(uri.to_string(), signed);
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, PLAYLIST_CONTENT_TYPE.to_string()),
+            // Rewritten playlists carry tokens and must not be shared through caches
+            (header::CACHE_CONTROL, "private, no-store".to_string()),
+        ],
+        rewrite_playlist(&contents, &replacements),
+    )
+        .into_response())
+}
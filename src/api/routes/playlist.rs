@@ -0,0 +1,519 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    api::{
+        app_state::AppState,
+        routes::video::{find_video, SanitizedVideoData, MAX_DESCRIPTION_LENGTH, MAX_TITLE_LENGTH},
+    },
+    db::{playlists::Playlist, PrivacyStatus, ProcessingStatus, User},
+    error::StorageError,
+    storage::PutOptions,
+};
+
+/// Most videos a playlist can hold
+const MAX_PLAYLIST_ITEMS: i64 = 500;
+/// Most playlists returned at once, and how many are returned unless fewer are asked for
+const MAX_PAGE_SIZE: i64 = 100;
+const DEFAULT_PAGE_SIZE: i64 = 24;
+/// Largest cover image that can be uploaded
+pub const MAX_COVER_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Image formats a cover can be uploaded in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CoverFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl CoverFormat {
+    /// Works out the format from the start of the file, whatever the upload claims it is
+    fn sniff(contents: &[u8]) -> Option<Self> {
+        match contents {
+            [0xFF, 0xD8, 0xFF, ..] => Some(CoverFormat::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(CoverFormat::Png),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+                Some(CoverFormat::Webp)
+            }
+            _ => None,
+        }
+    }
+    fn extension(&self) -> &'static str {
+        match self {
+            CoverFormat::Jpeg => "jpg",
+            CoverFormat::Png => "png",
+            CoverFormat::Webp => "webp",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PlaylistData {
+    id: Uuid,
+    user_id: Uuid,
+    title: String,
+    description: Option<String>,
+    privacy_status: PrivacyStatus,
+    cover_image_url: Option<String>,
+    /// How many of the videos the caller can see, only in listings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item_count: Option<i64>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl PlaylistData {
+    fn new(state: &AppState, playlist: Playlist, item_count: Option<i64>) -> Self {
+        // The timestamp changes the URL whenever the cover might have, so caches don't hold on to
+        // an old one
+        let cover_image_url = playlist.cover_image_path.as_ref().map(|_| {
+            format!(
+                "{}/playlist/{}/cover?v={}",
+                state.config.api_url,
+                playlist.id,
+                playlist.updated_at.timestamp()
+            )
+        });
+        PlaylistData {
+            id: playlist.id,
+            user_id: playlist.user_id,
+            title: playlist.title,
+            description: playlist.description,
+            privacy_status: playlist.privacy_status,
+            cover_image_url,
+            item_count,
+            created_at: playlist.created_at,
+            updated_at: playlist.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PlaylistItem {
+    position: usize,
+    video: SanitizedVideoData,
+    /// The video to autoplay once this one ends, missing on the last playable one
+    next_video_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PlaylistResponse {
+    #[serde(flatten)]
+    playlist: PlaylistData,
+    items: Vec<PlaylistItem>,
+}
+
+#[derive(Serialize)]
+pub struct PlaylistsResponse {
+    playlists: Vec<PlaylistData>,
+    /// Fetches the next page, missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_offset: Option<i64>,
+}
+
+/// Finds a playlist, mapping a missing one to a 404
+async fn find_playlist(state: &AppState, playlist_id: Uuid) -> Result<Playlist, StatusCode> {
+    Playlist::by_id(&state.db, playlist_id).await.map_err(|e| {
+        if let sqlx::Error::RowNotFound = e {
+            StatusCode::NOT_FOUND
+        } else {
+            tracing::error!("Error getting playlist {}: {}", playlist_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })
+}
+
+/// Finds a playlist the user is about to change, making sure they own it
+async fn find_owned_playlist(
+    state: &AppState,
+    user: Option<User>,
+    playlist_id: Uuid,
+) -> Result<(User, Playlist), StatusCode> {
+    let Some(user) = user else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let playlist = find_playlist(state, playlist_id).await?;
+    if playlist.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to edit playlist {} owned by {}",
+            user.id,
+            playlist.id,
+            playlist.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok((user, playlist))
+}
+
+fn db_error(action: &str, playlist_id: Uuid, e: sqlx::Error) -> StatusCode {
+    tracing::error!("Error {} playlist {}: {}", action, playlist_id, e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Checks a title and description, trimming them
+fn check_metadata<'a>(
+    title: Option<&'a str>,
+    description: Option<&'a str>,
+) -> Result<(Option<&'a str>, Option<&'a str>), StatusCode> {
+    let title = title.map(str::trim);
+    if title.is_some_and(|title| title.is_empty() || title.chars().count() > MAX_TITLE_LENGTH) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let description = description.map(str::trim);
+    if description.is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LENGTH) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok((title, description))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListPlaylistsQuery {
+    /// User name of the owner
+    owner: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Lists the playlists the caller can see, most recently changed first
+pub async fn get_playlists(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Query(query): Query<ListPlaylistsQuery>,
+) -> Result<Json<PlaylistsResponse>, StatusCode> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    // Fetch one extra to know whether there's another page
+    let mut playlists = Playlist::listed(
+        &state.db,
+        query.owner.as_deref(),
+        user.as_ref().map(|user| user.id),
+        limit + 1,
+        offset,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Error listing playlists: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let has_more = playlists.len() as i64 > limit;
+    playlists.truncate(limit as usize);
+
+    Ok(Json(PlaylistsResponse {
+        playlists: playlists
+            .into_iter()
+            .map(|summary| PlaylistData::new(&state, summary.playlist, Some(summary.item_count)))
+            .collect(),
+        next_offset: has_more.then_some(offset + limit),
+    }))
+}
+
+/// Gets a playlist with the videos in it that the caller can see
+/// Each video comes with the next one to autoplay, skipping those that can't be played yet
+pub async fn get_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+) -> Result<Json<PlaylistResponse>, StatusCode> {
+    let playlist = find_playlist(&state, playlist_id).await?;
+    // Private playlists look the same as missing ones to everyone but their owner
+    if !playlist.is_viewable_by(user.as_ref()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let videos: Vec<_> = Playlist::videos(&state.db, playlist.id)
+        .await
+        .map_err(|e| db_error("getting videos of", playlist.id, e))?
+        .into_iter()
+        .filter(|video| video.is_viewable_by(user.as_ref()))
+        .collect();
+
+    let mut next_video_id = None;
+    let mut items = Vec::with_capacity(videos.len());
+    // Walk backwards so every video knows the next playable one after it
+    for (position, video) in videos.into_iter().enumerate().rev() {
+        let playable = matches!(video.processing_status, ProcessingStatus::Completed);
+        let id = video.id.clone();
+        items.push(PlaylistItem {
+            position,
+            video: video.into(),
+            next_video_id: next_video_id.clone(),
+        });
+        if playable {
+            next_video_id = Some(id);
+        }
+    }
+    items.reverse();
+
+    Ok(Json(PlaylistResponse {
+        playlist: PlaylistData::new(&state, playlist, None),
+        items,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct CreatePlaylistRequest {
+    title: String,
+    description: Option<String>,
+    #[serde(default = "default_privacy")]
+    privacy_status: PrivacyStatus,
+}
+
+fn default_privacy() -> PrivacyStatus {
+    PrivacyStatus::Public
+}
+
+pub async fn create_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Json(request): Json<CreatePlaylistRequest>,
+) -> Result<(StatusCode, Json<PlaylistData>), StatusCode> {
+    let Some(user) = user else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let (title, description) =
+        check_metadata(Some(&request.title), request.description.as_deref())?;
+    let playlist = Playlist::create(
+        &state.db,
+        user.id,
+        title.unwrap_or_default(),
+        description,
+        request.privacy_status,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Error creating playlist for user {}: {}", user.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        StatusCode::CREATED,
+        Json(PlaylistData::new(&state, playlist, None)),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct UpdatePlaylistRequest {
+    title: Option<String>,
+    /// An empty description clears it
+    description: Option<String>,
+    privacy_status: Option<PrivacyStatus>,
+}
+
+/// Updates the title, description and privacy of a playlist, leaving out what isn't in the request
+pub async fn update_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+    Json(request): Json<UpdatePlaylistRequest>,
+) -> Result<Json<PlaylistData>, StatusCode> {
+    let (_, playlist) = find_owned_playlist(&state, user, playlist_id).await?;
+    let (title, description) =
+        check_metadata(request.title.as_deref(), request.description.as_deref())?;
+    let playlist = Playlist::update_metadata(
+        &state.db,
+        playlist.id,
+        title,
+        description,
+        request.privacy_status,
+    )
+    .await
+    .map_err(|e| db_error("updating", playlist.id, e))?;
+    Ok(Json(PlaylistData::new(&state, playlist, None)))
+}
+
+/// Deletes a playlist and its cover, the videos in it are left alone
+pub async fn delete_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    let (_, playlist) = find_owned_playlist(&state, user, playlist_id).await?;
+    if playlist.cover_image_path.is_some() {
+        // A cover left behind is only wasted space, so don't hold up the delete over it
+        if let Err(e) = state
+            .storage
+            .delete_prefix(&format!("{}/", playlist.storage_prefix()))
+            .await
+        {
+            tracing::error!("Error deleting files of playlist {}: {}", playlist.id, e);
+        }
+    }
+    Playlist::delete(&state.db, playlist.id)
+        .await
+        .map_err(|e| db_error("deleting", playlist.id, e))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct AddPlaylistItemRequest {
+    video_id: String,
+    /// Where to put the video, at the end without one
+    position: Option<i32>,
+}
+
+/// Adds a video the owner can watch to their playlist
+pub async fn add_playlist_item(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+    Json(request): Json<AddPlaylistItemRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let (user, playlist) = find_owned_playlist(&state, user, playlist_id).await?;
+    let video = find_video(&state, &request.video_id).await?;
+    if !video.is_viewable_by(Some(&user)) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let count = Playlist::item_count(&state.db, playlist.id)
+        .await
+        .map_err(|e| db_error("counting videos of", playlist.id, e))?;
+    if count >= MAX_PLAYLIST_ITEMS {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let added = Playlist::add_video(&state.db, playlist.id, &video.id, request.position)
+        .await
+        .map_err(|e| db_error("adding video to", playlist.id, e))?;
+    match added {
+        true => Ok(StatusCode::CREATED),
+        false => Err(StatusCode::CONFLICT),
+    }
+}
+
+/// Takes a video out of a playlist
+pub async fn remove_playlist_item(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path((playlist_id, video_id)): Path<(Uuid, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let (_, playlist) = find_owned_playlist(&state, user, playlist_id).await?;
+    let removed = Playlist::remove_video(&state.db, playlist.id, &video_id)
+        .await
+        .map_err(|e| db_error("removing video from", playlist.id, e))?;
+    match removed {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReorderPlaylistRequest {
+    /// Every video in the playlist, in the new order
+    video_ids: Vec<String>,
+}
+
+/// Puts the videos of a playlist in a new order
+pub async fn reorder_playlist_items(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+    Json(request): Json<ReorderPlaylistRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let (_, playlist) = find_owned_playlist(&state, user, playlist_id).await?;
+    let reordered = Playlist::reorder(&state.db, playlist.id, &request.video_ids)
+        .await
+        .map_err(|e| db_error("reordering", playlist.id, e))?;
+    match reordered {
+        true => Ok(StatusCode::NO_CONTENT),
+        // The playlist changed since the client last saw it, or the list is wrong
+        false => Err(StatusCode::CONFLICT),
+    }
+}
+
+/// Replaces the cover image of a playlist with the JPEG, PNG or WebP image in the body
+pub async fn upload_cover(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+    body: Bytes,
+) -> Result<Json<PlaylistData>, StatusCode> {
+    let (_, playlist) = find_owned_playlist(&state, user, playlist_id).await?;
+    let format = CoverFormat::sniff(&body).ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+    let key = format!("{}/cover.{}", playlist.storage_prefix(), format.extension());
+    state
+        .storage
+        .put(&key, body, &PutOptions::for_key(&key))
+        .await
+        .map_err(|e| {
+            tracing::error!("Error storing cover {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let updated = Playlist::set_cover_image_path(&state.db, playlist.id, Some(&key))
+        .await
+        .map_err(|e| db_error("saving cover of", playlist.id, e))?;
+    // Covers in another format are stored under another key
+    if let Some(old_key) = playlist.cover_image_path.filter(|old_key| *old_key != key) {
+        if let Err(e) = state.storage.delete_prefix(&old_key).await {
+            tracing::error!("Error deleting old cover {}: {}", old_key, e);
+        }
+    }
+    Ok(Json(PlaylistData::new(&state, updated, None)))
+}
+
+/// Removes the cover image of a playlist
+pub async fn delete_cover(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    let (_, playlist) = find_owned_playlist(&state, user, playlist_id).await?;
+    let Some(key) = &playlist.cover_image_path else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    Playlist::set_cover_image_path(&state.db, playlist.id, None)
+        .await
+        .map_err(|e| db_error("removing cover of", playlist.id, e))?;
+    if let Err(e) = state.storage.delete_prefix(key).await {
+        tracing::error!("Error deleting cover {}: {}", key, e);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serves the cover image of a playlist to anyone who can see the playlist
+pub async fn get_cover(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(playlist_id): Path<Uuid>,
+) -> Result<Response, StatusCode> {
+    let playlist = find_playlist(&state, playlist_id).await?;
+    if !playlist.is_viewable_by(user.as_ref()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let key = playlist.cover_image_path.ok_or(StatusCode::NOT_FOUND)?;
+    let reader = state.storage.get(&key).await.map_err(|e| match e {
+        StorageError::NotFound(_) => StatusCode::NOT_FOUND,
+        e => {
+            tracing::error!("Error reading cover {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+    let options = PutOptions::for_key(&key);
+    // Only public covers can be kept by shared caches
+    let cache_control = match playlist.privacy_status {
+        PrivacyStatus::Public => "public, max-age=86400",
+        _ => "private, max-age=86400",
+    };
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                options
+                    .content_type
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+            ),
+            (header::CACHE_CONTROL, cache_control.to_string()),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+        .into_response())
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::app_state::AppState,
+    db::{
+        search::{
+            search_channels, search_videos, ChannelSearchHit, VideoSearchHit, HIGHLIGHT_START,
+            HIGHLIGHT_STOP,
+        },
+        User,
+    },
+};
+
+/// Most results of each kind returned at once, and how many are returned unless fewer are asked for
+const MAX_SEARCH_LIMIT: i64 = 50;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+/// Longest search that's run, anything longer is almost certainly not typed by a person
+const MAX_QUERY_LENGTH: usize = 200;
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchType {
+    #[default]
+    All,
+    Videos,
+    Channels,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    q: String,
+    #[serde(default, rename = "type")]
+    search_type: SearchType,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    videos: Vec<VideoSearchHit>,
+    channels: Vec<ChannelSearchHit>,
+    /// Fetches the next page of whichever kinds still have more, missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_offset: Option<i64>,
+}
+
+/// Escapes text for HTML, then turns the match markers into <mark> tags
+/// Titles and descriptions are written by users, so nothing else they contain can become markup
+fn render_highlight(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            HIGHLIGHT_START => html.push_str("<mark>"),
+            HIGHLIGHT_STOP => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Searches videos and channels, best matches first
+/// Only videos the caller can see listed are searched, and matches are highlighted with
+/// <mark> tags in otherwise escaped text
+pub async fn search(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, StatusCode> {
+    let text = query.q.trim();
+    if text.is_empty() || text.chars().count() > MAX_QUERY_LENGTH {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let viewer_id = user.as_ref().map(|user| user.id);
+
+    // Fetch one extra of each to know whether there's another page
+    let mut videos = match query.search_type {
+        SearchType::All | SearchType::Videos => {
+            search_videos(&state.db, text, viewer_id, limit + 1, offset)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error searching videos for {:?}: {}", text, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+        }
+        SearchType::Channels => Vec::new(),
+    };
+    let mut channels = match query.search_type {
+        SearchType::All | SearchType::Channels => {
+            search_channels(&state.db, text, limit + 1, offset)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Error searching channels for {:?}: {}", text, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+        }
+        SearchType::Videos => Vec::new(),
+    };
+    let has_more = videos.len() as i64 > limit || channels.len() as i64 > limit;
+    videos.truncate(limit as usize);
+    channels.truncate(limit as usize);
+
+    for video in &mut videos {
+        video.title_highlight = render_highlight(&video.title_highlight);
+        video.snippet = video.snippet.as_deref().map(render_highlight);
+    }
+    for channel in &mut channels {
+        channel.username_highlight = render_highlight(&channel.username_highlight);
+    }
+
+    Ok(Json(SearchResponse {
+        videos,
+        channels,
+        next_offset: has_more.then_some(offset + limit),
+    }))
+}
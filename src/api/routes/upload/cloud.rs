@@ -1,31 +1,74 @@
 use std::sync::Arc;
 
-use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
 use axum::{extract::State, Extension, Json};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::{
-    api::app_state::AppState,
-    db::{User, Video},
+    api::{
+        app_state::AppState,
+        routes::upload::{dedup::parse_sha256, validate::rejection_status},
+    },
+    db::{
+        quotas::{fits_quota, record_raw_size, reserve_upload, storage_quota, StorageUsage},
+        streams::Stream,
+        User, Video,
+    },
+    error::StorageError,
     prelude::get_storage_dir,
+    queue::{hls_stream::VideoToStreamPayload, Job},
+    storage::UploadedPart,
+    vod::upload::{check_contents, check_format, read_head, UploadFormat},
 };
 
 #[derive(Deserialize)]
 pub struct InitUploadRequest {
     parts: i32,
+    /// Size of the whole file in bytes, checked against the uploaders storage quota
+    size: u64,
     key: String,
     content_type: String,
     title: Option<String>,
+    /// The stream this video is a recording of, used to build chapters from stream events
+    stream_id: Option<Uuid>,
+    /// Hex encoded SHA-256 of the file, if the uploader computed it
+    sha256: Option<String>,
 }
 
+/// How long presigned part URLs can be uploaded to
+const PART_URL_EXPIRY: std::time::Duration = std::time::Duration::from_secs(3600);
+
 #[derive(Serialize)]
 /// PartUrl of a multipart upload, containing the part_number and url itself
-struct PartUrl {
+pub(crate) struct PartUrl {
     part_number: i32,
     url: String,
 }
 
+/// Presigns an upload URL for each of the given parts
+pub(crate) async fn presign_part_urls(
+    state: &AppState,
+    key: &str,
+    upload_id: &str,
+    part_numbers: impl IntoIterator<Item = i32>,
+) -> Result<Vec<PartUrl>, StatusCode> {
+    let mut part_urls = Vec::new();
+    for part_number in part_numbers {
+        let url = state
+            .storage
+            .presign_upload_part(key, upload_id, part_number, PART_URL_EXPIRY)
+            .await
+            .map_err(|e| {
+                tracing::error!("Could not generate presigned url {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        part_urls.push(PartUrl { part_number, url });
+    }
+    Ok(part_urls)
+}
+
 #[derive(Serialize)]
 pub struct InitUploadResponse {
     upload_id: String,
@@ -34,7 +77,7 @@ pub struct InitUploadResponse {
     part_urls: Vec<PartUrl>,
 }
 
-/// Initializes a multipart upload to R2
+/// Initializes a multipart upload to storage
 pub async fn init_upload(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<Option<User>>,
@@ -43,93 +86,161 @@ pub async fn init_upload(
     // User required
     tracing::trace!("Checking for user authorization on upload");
     let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
-    // First, let R2 know we've completed the upload
-    tracing::trace!("Grabbing bucket");
-    let bucket = state
-        .config
-        .upload_bucket
-        .clone()
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    tracing::trace!("Bucket found {}", &bucket);
-    let video_id = Video::gen_id();
-    // Get the file extension from the original key
-    let extension = request.key.split('.').last().unwrap_or("");
-    let storage_root = get_storage_dir();
-    let storage_path = format!("{}/{}", storage_root, video_id);
-    let key = format!("{}/raw.{}", storage_path, extension);
-    tracing::debug!("Full parsed key: {key}");
-    // Start multipart upload on R2's side
-    let start_upload_output = state
-        .s3_client
-        .create_multipart_upload()
-        .bucket(&bucket)
-        .key(&key)
-        .content_type(&request.content_type)
-        .send()
-        .await
+    check_stream_owner(&state, &user, request.stream_id).await?;
+    let sha256 = match &request.sha256 {
+        Some(hash) => Some(parse_sha256(hash).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    // Only accept formats processing can handle, in sizes and part counts storage can take
+    let limits = &state.config.upload_limits;
+    let format = check_format(&request.key, &request.content_type)
+        .and_then(|format| {
+            limits.check_size(request.size)?;
+            limits.check_parts(request.size, request.parts)?;
+            Ok(format)
+        })
         .map_err(|e| {
-            tracing::error!("Could not start multipart upload {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            tracing::debug!("Rejecting upload from user {}: {}", user.id, e);
+            rejection_status(&e)
         })?;
+    // Make sure the upload fits in what's left of the uploaders quota
+    let declared_size = i64::try_from(request.size).map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let (quota, usage) = quota_and_usage(&state, &user).await?;
+    if !fits_quota(quota, usage.total_bytes(), declared_size) {
+        tracing::debug!(
+            "User {} would exceed their quota of {:?} bytes with {} more",
+            user.id,
+            quota,
+            declared_size
+        );
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
 
-    // Parse the upload ID out of the R2 output for downstream use while uploading
-    let upload_id = start_upload_output
-        .upload_id()
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?
-        .to_string();
-
-    // Generate presigned links for each part
-    let mut part_urls = Vec::new();
-    for part_number in 1..=request.parts {
-        // Construct Duration
-        let expires_in = std::time::Duration::from_secs(3600);
-        // Construct presigning config
-        let config =
-            aws_sdk_s3::presigning::PresigningConfig::expires_in(expires_in).map_err(|e| {
-                tracing::error!("Could not construct presigning config {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-        // Generate URL
-        let presigned_url = state
-            .s3_client
-            .upload_part()
-            .bucket(&bucket)
-            .key(&key)
-            .upload_id(&upload_id)
-            .part_number(part_number)
-            .presigned(config)
+    let (video, upload_id) = start_upload(
+        &state,
+        &user,
+        request.title,
+        request.stream_id,
+        format,
+        declared_size,
+    )
+    .await?;
+    // Remember the declared hash until processing computes the real one
+    if let Some(sha256) = &sha256 {
+        Video::set_content_hash(&state.db, &video.id, sha256)
             .await
             .map_err(|e| {
-                tracing::error!("Could not generate presigned url {}", e);
+                tracing::error!("Could not save declared hash {}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
             })?;
+    }
 
-        part_urls.push(PartUrl {
-            part_number,
-            url: presigned_url.uri().to_string(),
-        });
+    // Generate presigned links for each part
+    let part_urls =
+        presign_part_urls(&state, &video.raw_video_path, &upload_id, 1..=request.parts).await?;
+
+    Ok(Json(InitUploadResponse {
+        upload_id,
+        part_urls,
+        key: video.raw_video_path,
+        video_id: video.id,
+    }))
+}
+
+/// Makes sure a recording is only linked to one of the uploader's own streams
+pub(crate) async fn check_stream_owner(
+    state: &AppState,
+    user: &User,
+    stream_id: Option<Uuid>,
+) -> Result<(), StatusCode> {
+    let Some(stream_id) = stream_id else {
+        return Ok(());
+    };
+    let stream = Stream::find_by_id(stream_id, &state.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+    if stream.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to link upload to stream {} owned by {}",
+            user.id,
+            stream.id,
+            stream.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
     }
+    Ok(())
+}
+
+/// Creates a video for a new upload and starts the multipart upload of its raw video,
+/// returning the video along with the upload ID
+/// The declared size is held against the uploaders quota until the upload completes
+pub(crate) async fn start_upload(
+    state: &AppState,
+    user: &User,
+    title: Option<String>,
+    stream_id: Option<Uuid>,
+    format: UploadFormat,
+    declared_size: i64,
+) -> Result<(Video, String), StatusCode> {
+    let video_id = Video::gen_id();
+    // The raw video is stored with the extension of its format, never whatever the client sent
+    let storage_root = get_storage_dir();
+    let storage_path = format!("{}/{}", storage_root, video_id);
+    let key = format!("{}/raw.{}", storage_path, format.extension());
+    tracing::debug!("Full parsed key: {key}");
+    // Start multipart upload on the storage side
+    let upload_id = state
+        .storage
+        .create_multipart_upload(&key, Some(format.content_type()))
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not start multipart upload {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     // Initialize the video in the database
     let video = Video::create(
         &state.db,
         Some(video_id),
         user.id,
-        request.title.unwrap_or("Untitled".to_string()),
-        Some(key.clone()),
+        title.unwrap_or("Untitled".to_string()),
+        Some(key),
+        stream_id,
     )
     .await
     .map_err(|e| {
         tracing::error!("Could not initialize video in database {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    // Remember the upload so it can be aborted if the video is deleted before it completes,
+    // and hold the space for it until it does
+    let reserved = reserve_upload(&state.db, user.id, &video.id, &upload_id, declared_size)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not reserve space for upload {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    // Another upload took the space since it was checked
+    if !reserved {
+        tracing::debug!(
+            "User {} would exceed their quota with {} more bytes",
+            user.id,
+            declared_size
+        );
+        abandon_upload(state, user, &video, &upload_id).await?;
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
 
-    Ok(Json(InitUploadResponse {
-        upload_id,
-        part_urls,
-        key,
-        video_id: video.id,
-    }))
+    // Hand back the row as it is now, with the upload it's waiting on
+    let video = Video {
+        upload_id: Some(upload_id.clone()),
+        declared_size_bytes: Some(declared_size),
+        ..video
+    };
+    Ok((video, upload_id))
 }
 
 #[derive(Deserialize)]
@@ -139,7 +250,6 @@ pub struct Parts {
 }
 
 #[derive(Deserialize)]
-#[allow(dead_code)]
 pub struct CompleteUploadRequest {
     upload_id: String,
     video_id: String,
@@ -147,8 +257,7 @@ pub struct CompleteUploadRequest {
     completed_parts: Vec<Parts>,
 }
 
-// TODO: Write route for completing multipart upload
-/// Completes a multipart upload to R2
+/// Completes a multipart upload to storage and queues the video for processing
 pub async fn complete_upload(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<Option<User>>,
@@ -156,44 +265,176 @@ pub async fn complete_upload(
 ) -> Result<StatusCode, StatusCode> {
     // User required
     tracing::trace!("Checking for user authorization on upload");
-    let _user = user.ok_or(StatusCode::UNAUTHORIZED)?;
-    // First, let R2 know we've completed the upload
-    tracing::trace!("Grabbing bucket");
-    let bucket = state
-        .config
-        .upload_bucket
-        .clone()
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-    tracing::trace!("Bucket found {}", bucket);
-
-    let serialized_completed_parts = request
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    // Only the uploader can complete the upload
+    let video = Video::by_id(&state.db, &request.video_id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+    if video.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to complete upload for video {} owned by {}",
+            user.id,
+            video.id,
+            video.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    // The upload has to be the one started for this video
+    if video.upload_id.as_deref() != Some(request.upload_id.as_str())
+        || video.raw_video_path != request.key
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // First, let storage know we've completed the upload
+    let completed_parts: Vec<UploadedPart> = request
         .completed_parts
         .iter()
-        .map(|part| {
-            CompletedPart::builder()
-                .e_tag(part.etag.clone())
-                .part_number(part.number)
-                .build()
+        .map(|part| UploadedPart {
+            part_number: part.number,
+            etag: part.etag.clone(),
+            size: None,
         })
         .collect();
 
-    let completed_upload = CompletedMultipartUpload::builder()
-        .set_parts(Some(serialized_completed_parts))
-        .build();
-
-    let _completed_parts = state
-        .s3_client
-        .complete_multipart_upload()
-        .bucket(&bucket)
-        .key(&request.key)
-        .upload_id(&request.upload_id)
-        .multipart_upload(completed_upload)
-        .send()
+    state
+        .storage
+        .complete_multipart_upload(&request.key, &request.upload_id, &completed_parts)
         .await
         .map_err(|e| {
             tracing::error!("Could not complete multipart upload {}", e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
+    finalize_upload(&state, &user, video).await?;
     Ok(StatusCode::ACCEPTED)
 }
+
+/// Checks a raw video that has fully reached storage, records its size and queues it for processing
+/// Uploads that aren't what they claim to be, or don't fit in the quota, are deleted along with
+/// their video
+pub(crate) async fn finalize_upload(
+    state: &AppState,
+    user: &User,
+    video: Video,
+) -> Result<(), StatusCode> {
+    let key = video.raw_video_path.clone();
+    // Make sure the contents match the format the upload was accepted as
+    let format = UploadFormat::from_key(&key).ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+    let head = read_head(state.storage.as_ref(), &key).await.map_err(|e| {
+        tracing::error!("Could not read start of uploaded video {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if let Err(e) = check_contents(format, &head) {
+        tracing::warn!("Rejecting upload for video {}: {}", video.id, e);
+        discard_upload(state, user, &video).await?;
+        return Err(rejection_status(&e));
+    }
+    // Record what the upload actually takes up, it can be bigger than what was declared
+    let raw_size = state
+        .storage
+        .head(&key)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not get size of uploaded video {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .size as i64;
+    let recorded = record_raw_size(&state.db, user.id, &video.id, raw_size)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not save raw video size {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !recorded {
+        tracing::warn!(
+            "Upload for video {} is {} bytes, more than declared and over quota",
+            video.id,
+            raw_size
+        );
+        discard_upload(state, user, &video).await?;
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    // Queue the video up for processing
+    state
+        .job_queue
+        .enqueue(Job::from(VideoToStreamPayload { video_id: video.id }))
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not queue video for processing {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(())
+}
+
+/// Aborts an upload that never completed and deletes the video it was for
+pub(crate) async fn abandon_upload(
+    state: &AppState,
+    user: &User,
+    video: &Video,
+    upload_id: &str,
+) -> Result<(), StatusCode> {
+    match state
+        .storage
+        .abort_multipart_upload(&video.raw_video_path, upload_id)
+        .await
+    {
+        Ok(()) | Err(StorageError::NotFound(_)) => {}
+        Err(e) => {
+            tracing::error!("Could not abort upload {}: {}", upload_id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    Video::delete(&state.db, user.id, vec![video.id.clone()])
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not delete video of abandoned upload {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(())
+}
+
+/// Deletes a rejected upload and the video it was for
+pub(crate) async fn discard_upload(
+    state: &AppState,
+    user: &User,
+    video: &Video,
+) -> Result<(), StatusCode> {
+    state
+        .storage
+        .delete_prefix(&video.raw_video_path)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not delete rejected upload {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Video::delete(&state.db, user.id, vec![video.id.clone()])
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not delete rejected video {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(())
+}
+
+/// Gets the storage quota of a user along with what they're already using
+pub(crate) async fn quota_and_usage(
+    state: &AppState,
+    user: &User,
+) -> Result<(Option<i64>, StorageUsage), StatusCode> {
+    let quota = storage_quota(user.id, &state.db).await.map_err(|e| {
+        tracing::error!("Could not get storage quota {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let usage = StorageUsage::by_user_id(user.id, &state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not get storage usage {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok((quota, usage))
+}
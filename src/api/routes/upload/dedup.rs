@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Extension, Json};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        app_state::AppState,
+        routes::{upload::cloud::check_stream_owner, video::find_video},
+    },
+    db::{ProcessingStatus, User, Video},
+};
+
+/// Checks a hex encoded SHA-256, returning it lowercased so it compares the same either way
+pub fn parse_sha256(hash: &str) -> Option<String> {
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_lowercase())
+}
+
+#[derive(Deserialize)]
+pub struct CheckDuplicateRequest {
+    sha256: String,
+}
+
+#[derive(Serialize)]
+/// A video the uploader already has with the same source
+pub struct DuplicateVideo {
+    video_id: String,
+    title: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize)]
+pub struct CheckDuplicateResponse {
+    /// When set, the uploader can link to this video or reuse it instead of uploading again
+    duplicate: Option<DuplicateVideo>,
+}
+
+/// Checks whether the uploader already has a processed video with the same source
+pub async fn check_duplicate(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Json(request): Json<CheckDuplicateRequest>,
+) -> Result<Json<CheckDuplicateResponse>, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let sha256 = parse_sha256(&request.sha256).ok_or(StatusCode::BAD_REQUEST)?;
+    let duplicate = Video::find_duplicate(&state.db, user.id, &sha256)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not look up duplicate videos {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(CheckDuplicateResponse {
+        duplicate: duplicate.map(|video| DuplicateVideo {
+            video_id: video.id,
+            title: video.title,
+            created_at: video.created_at,
+        }),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ReuseVideoRequest {
+    /// The video whose source and renditions to reuse
+    video_id: String,
+    title: Option<String>,
+    /// The stream the new video is a recording of
+    stream_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct ReuseVideoResponse {
+    video_id: String,
+}
+
+/// Creates a new video from one the uploader already has, without storing or encoding it again
+pub async fn reuse_video(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Json(request): Json<ReuseVideoRequest>,
+) -> Result<Json<ReuseVideoResponse>, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let source = find_video(&state, &request.video_id).await?;
+    // Only the uploaders own videos can be reused, and only once they're processed
+    if source.user_id != user.id {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if source.processing_status != ProcessingStatus::Completed {
+        return Err(StatusCode::CONFLICT);
+    }
+    check_stream_owner(&state, &user, request.stream_id).await?;
+
+    let title = request.title.unwrap_or_else(|| source.title.clone());
+    let video = Video::create_copy(&state.db, &source, title, request.stream_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not create copy of video {}: {}", source.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tracing::debug!(
+        "Created video {} reusing storage of {}",
+        video.id,
+        video.storage_id()
+    );
+
+    Ok(Json(ReuseVideoResponse { video_id: video.id }))
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequest, Multipart, Query, Request, State},
+    http::{header, HeaderMap},
+    Extension, Json,
+};
+use bytes::Bytes;
+use futures::Stream;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        app_state::AppState,
+        routes::upload::{
+            cloud::{
+                abandon_upload, check_stream_owner, finalize_upload, quota_and_usage, start_upload,
+            },
+            validate::rejection_status,
+        },
+    },
+    db::{quotas::fits_quota, User},
+    error::UploadError,
+    vod::upload::{check_format, upload_stream},
+};
+
+#[derive(Deserialize)]
+pub struct DirectUploadQuery {
+    /// Name of the uploaded file, raw bodies don't carry one of their own
+    filename: Option<String>,
+    title: Option<String>,
+    /// The stream this video is a recording of, used to build chapters from stream events
+    stream_id: Option<Uuid>,
+    /// Size of the file in bytes, if the client knows it up front
+    size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct DirectUploadResponse {
+    video_id: String,
+}
+
+/// What's known about a direct upload before its contents arrive
+struct DirectUpload {
+    filename: String,
+    content_type: String,
+    title: Option<String>,
+    stream_id: Option<Uuid>,
+    size: Option<u64>,
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Uploads a video through the API instead of straight to storage, for deployments where
+/// clients can't reach storage themselves
+/// Takes either a multipart form, with any title, stream_id and size fields before the file
+/// field, or the raw file as the body with the rest in the query
+/// The body is streamed to storage part by part, never held in memory as a whole
+pub async fn direct_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Query(mut query): Query<DirectUploadQuery>,
+    request: Request,
+) -> Result<(StatusCode, Json<DirectUploadResponse>), StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let content_type = header_value(request.headers(), header::CONTENT_TYPE).unwrap_or_default();
+    let content_length = header_value(request.headers(), header::CONTENT_LENGTH)
+        .and_then(|length| length.parse().ok());
+
+    let video_id = if content_type.starts_with("multipart/form-data") {
+        let mut multipart = Multipart::from_request(request, &state)
+            .await
+            .map_err(|e| e.status())?;
+        loop {
+            // The form has to have a file
+            let field = multipart
+                .next_field()
+                .await
+                .map_err(|e| e.status())?
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            match field.name() {
+                Some("file") => {
+                    let upload = DirectUpload {
+                        filename: field
+                            .file_name()
+                            .map(str::to_string)
+                            .or(query.filename)
+                            .ok_or(StatusCode::BAD_REQUEST)?,
+                        content_type: field.content_type().unwrap_or_default().to_string(),
+                        title: query.title,
+                        stream_id: query.stream_id,
+                        size: query.size,
+                    };
+                    break receive_upload(&state, &user, upload, field).await?;
+                }
+                Some("title") => query.title = Some(field.text().await.map_err(|e| e.status())?),
+                Some("stream_id") => {
+                    let text = field.text().await.map_err(|e| e.status())?;
+                    query.stream_id = Some(text.parse().map_err(|_| StatusCode::BAD_REQUEST)?);
+                }
+                Some("size") => {
+                    let text = field.text().await.map_err(|e| e.status())?;
+                    query.size = Some(text.parse().map_err(|_| StatusCode::BAD_REQUEST)?);
+                }
+                _ => {}
+            }
+        }
+    } else {
+        let upload = DirectUpload {
+            filename: query.filename.ok_or(StatusCode::BAD_REQUEST)?,
+            content_type,
+            title: query.title,
+            stream_id: query.stream_id,
+            size: query.size.or(content_length),
+        };
+        let body = request.into_body().into_data_stream();
+        receive_upload(&state, &user, upload, body).await?
+    };
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(DirectUploadResponse { video_id }),
+    ))
+}
+
+/// Validates a direct upload, streams it to storage and queues it for processing,
+/// returning the ID of the video it created
+async fn receive_upload<E: std::fmt::Display>(
+    state: &AppState,
+    user: &User,
+    upload: DirectUpload,
+    body: impl Stream<Item = Result<Bytes, E>>,
+) -> Result<String, StatusCode> {
+    check_stream_owner(state, user, upload.stream_id).await?;
+    let limits = &state.config.upload_limits;
+    let format = check_format(&upload.filename, &upload.content_type)
+        .and_then(|format| {
+            if let Some(size) = upload.size {
+                limits.check_size(size)?;
+            }
+            Ok(format)
+        })
+        .map_err(|e| {
+            tracing::debug!("Rejecting direct upload from user {}: {}", user.id, e);
+            rejection_status(&e)
+        })?;
+    // Make sure the upload fits in what's left of the uploaders quota, when its size is known
+    let declared_size = upload
+        .size
+        .map(i64::try_from)
+        .transpose()
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?
+        .unwrap_or_default();
+    let (quota, usage) = quota_and_usage(state, user).await?;
+    if !fits_quota(quota, usage.total_bytes(), declared_size) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    // Bodies of unknown size are cut off once they're bigger than either limit allows
+    let remaining = quota.map(|quota| quota.saturating_sub(usage.total_bytes()).max(0) as u64);
+    let max_bytes = remaining.map_or(limits.max_size_bytes, |remaining| {
+        remaining.min(limits.max_size_bytes)
+    });
+
+    let (video, upload_id) = start_upload(
+        state,
+        user,
+        upload.title,
+        upload.stream_id,
+        format,
+        declared_size,
+    )
+    .await?;
+    if let Err(e) = upload_stream(
+        state.storage.as_ref(),
+        &video.raw_video_path,
+        &upload_id,
+        body,
+        max_bytes,
+    )
+    .await
+    {
+        match &e {
+            UploadError::Storage(_) => tracing::error!("Could not store direct upload: {}", e),
+            _ => tracing::debug!("Rejecting direct upload from user {}: {}", user.id, e),
+        }
+        abandon_upload(state, user, &video, &upload_id).await?;
+        return Err(rejection_status(&e));
+    }
+
+    let video_id = video.id.clone();
+    finalize_upload(state, user, video).await?;
+    Ok(video_id)
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Extension, Json};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        app_state::AppState,
+        routes::upload::cloud::{check_stream_owner, quota_and_usage},
+    },
+    db::{quotas::fits_quota, User, Video},
+    error::ImportError,
+    prelude::get_storage_dir,
+    queue::{
+        import::{check_url, ImportSettings, ImportVideoPayload},
+        Job,
+    },
+    vod::upload::UploadFormat,
+};
+
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    /// HTTP(S) URL of the file to import
+    url: String,
+    title: Option<String>,
+    /// The stream this video is a recording of, used to build chapters from stream events
+    stream_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    video_id: String,
+}
+
+/// Imports a video from another host, the file is fetched and processed in the background
+/// The video starts out pending, and is marked failed if the file can't be fetched
+pub async fn import_video(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Json(request): Json<ImportRequest>,
+) -> Result<(StatusCode, Json<ImportResponse>), StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    check_stream_owner(&state, &user, request.stream_id).await?;
+    // Hosts given by name are checked again once they're resolved by the import job
+    let url = check_url(
+        &request.url,
+        ImportSettings::from_env().allow_private_addresses,
+    )
+    .map_err(|e| {
+        tracing::debug!("Rejecting import from user {}: {}", user.id, e);
+        match e {
+            ImportError::BlockedAddress(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    })?;
+    // The size isn't known until the download starts, but there has to be some space left
+    let (quota, usage) = quota_and_usage(&state, &user).await?;
+    if !fits_quota(quota, usage.total_bytes(), 1) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    // Guess the format from the URL for now, the import job settles it once it sees the file
+    let video_id = Video::gen_id();
+    let format = UploadFormat::from_key(url.path()).unwrap_or(UploadFormat::Mp4);
+    let key = format!(
+        "{}/{}/raw.{}",
+        get_storage_dir(),
+        video_id,
+        format.extension()
+    );
+    let video = Video::create(
+        &state.db,
+        Some(video_id),
+        user.id,
+        request.title.unwrap_or("Untitled".to_string()),
+        Some(key),
+        request.stream_id,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Could not initialize imported video in database {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Video::set_import_url(&state.db, &video.id, url.as_str())
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not save import URL {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if let Err(e) = state
+        .job_queue
+        .enqueue(Job::from(ImportVideoPayload {
+            video_id: video.id.clone(),
+        }))
+        .await
+    {
+        tracing::error!("Could not queue video for import {}", e);
+        // Nothing will ever fetch the video, so don't leave it pending
+        if let Err(e) = Video::delete(&state.db, user.id, vec![video.id]).await {
+            tracing::error!("Could not delete unqueued import {}", e);
+        }
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ImportResponse { video_id: video.id }),
+    ))
+}
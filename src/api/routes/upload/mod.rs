@@ -1 +1,7 @@
 pub mod cloud;
+pub mod dedup;
+pub mod direct;
+pub mod import;
+pub mod resume;
+pub mod tus;
+pub mod validate;
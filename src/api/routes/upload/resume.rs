@@ -0,0 +1,174 @@
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{
+        app_state::AppState,
+        routes::upload::{
+            cloud::{abandon_upload, presign_part_urls, PartUrl},
+            validate::rejection_status,
+        },
+    },
+    db::{User, Video},
+    error::StorageError,
+    storage::UploadedPart,
+};
+
+/// Picks the part numbers up to the total that haven't been uploaded yet
+pub fn missing_parts(total: i32, uploaded: &[UploadedPart]) -> Vec<i32> {
+    let uploaded: HashSet<i32> = uploaded.iter().map(|part| part.part_number).collect();
+    (1..=total)
+        .filter(|part_number| !uploaded.contains(part_number))
+        .collect()
+}
+
+#[derive(Serialize)]
+/// A part storage already has, the ETag is what completing the upload needs
+pub struct UploadedPartResponse {
+    part_number: i32,
+    etag: String,
+    size: Option<u64>,
+}
+
+impl From<UploadedPart> for UploadedPartResponse {
+    fn from(part: UploadedPart) -> Self {
+        UploadedPartResponse {
+            part_number: part.part_number,
+            etag: part.etag,
+            size: part.size,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UploadPartsResponse {
+    upload_id: String,
+    video_id: String,
+    key: String,
+    uploaded_parts: Vec<UploadedPartResponse>,
+}
+
+#[derive(Deserialize)]
+pub struct ResignPartsRequest {
+    /// How many parts the whole upload has
+    parts: i32,
+}
+
+#[derive(Serialize)]
+pub struct ResignPartsResponse {
+    upload_id: String,
+    video_id: String,
+    key: String,
+    uploaded_parts: Vec<UploadedPartResponse>,
+    /// Fresh URLs for every part that still has to be uploaded
+    part_urls: Vec<PartUrl>,
+}
+
+/// Finds the video an upload is for, making sure it belongs to the user
+async fn find_upload(state: &AppState, user: &User, upload_id: &str) -> Result<Video, StatusCode> {
+    let video = Video::by_upload_id(&state.db, upload_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not look up upload {}: {}", upload_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if video.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to access upload for video {} owned by {}",
+            user.id,
+            video.id,
+            video.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(video)
+}
+
+/// Gets the parts storage already has for an upload
+async fn list_uploaded_parts(
+    state: &AppState,
+    video: &Video,
+    upload_id: &str,
+) -> Result<Vec<UploadedPart>, StatusCode> {
+    state
+        .storage
+        .list_parts(&video.raw_video_path, upload_id)
+        .await
+        .map_err(|e| match e {
+            // The upload is gone from storage, so it can't be resumed
+            StorageError::NotFound(_) => StatusCode::GONE,
+            e => {
+                tracing::error!("Could not list parts of upload {}: {}", upload_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })
+}
+
+/// Lists the parts already uploaded, so an interrupted upload can pick up where it left off
+pub async fn list_upload_parts(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(upload_id): Path<String>,
+) -> Result<Json<UploadPartsResponse>, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let video = find_upload(&state, &user, &upload_id).await?;
+    let uploaded_parts = list_uploaded_parts(&state, &video, &upload_id).await?;
+
+    Ok(Json(UploadPartsResponse {
+        upload_id,
+        video_id: video.id,
+        key: video.raw_video_path,
+        uploaded_parts: uploaded_parts.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Presigns fresh URLs for the parts of an upload that are still missing,
+/// for when the ones it started with have expired
+pub async fn resign_upload_parts(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(upload_id): Path<String>,
+    Json(request): Json<ResignPartsRequest>,
+) -> Result<Json<ResignPartsResponse>, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let video = find_upload(&state, &user, &upload_id).await?;
+    // The part count has to make sense for the size the upload was started with
+    let declared_size = video.declared_size_bytes.unwrap_or_default().max(0) as u64;
+    state
+        .config
+        .upload_limits
+        .check_parts(declared_size, request.parts)
+        .map_err(|e| rejection_status(&e))?;
+    let uploaded_parts = list_uploaded_parts(&state, &video, &upload_id).await?;
+    let missing = missing_parts(request.parts, &uploaded_parts);
+    let part_urls = presign_part_urls(&state, &video.raw_video_path, &upload_id, missing).await?;
+
+    Ok(Json(ResignPartsResponse {
+        upload_id,
+        video_id: video.id,
+        key: video.raw_video_path,
+        uploaded_parts: uploaded_parts.into_iter().map(Into::into).collect(),
+        part_urls,
+    }))
+}
+
+/// Abandons an upload, freeing its parts and the space it held against the uploaders quota
+/// The video it was for is deleted along with it, since it never got a source
+pub async fn abort_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(upload_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let video = find_upload(&state, &user, &upload_id).await?;
+    abandon_upload(&state, &user, &video, &upload_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,459 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use reqwest::StatusCode;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        app_state::AppState,
+        routes::upload::{
+            cloud::{
+                abandon_upload, check_stream_owner, finalize_upload, quota_and_usage, start_upload,
+            },
+            validate::rejection_status,
+        },
+    },
+    db::{quotas::fits_quota, User, Video},
+    error::StorageError,
+    storage::{PutOptions, UploadedPart},
+    vod::upload::{check_format, upload_part, UploadFormat, STREAM_PART_SIZE},
+};
+
+/// Version of the tus protocol spoken here, see https://tus.io/protocols/resumable-upload
+pub const TUS_VERSION: &str = "1.0.0";
+/// Optional parts of the protocol that are supported
+const TUS_EXTENSIONS: &str = "creation,termination";
+/// The only content type chunks can be sent as
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+
+/// Adds the protocol version to every tus response, as the protocol requires
+pub async fn tus_resumable(mut response: Response) -> Response {
+    let version_mismatch = response.status() == StatusCode::PRECONDITION_FAILED;
+    let headers = response.headers_mut();
+    headers.insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    // Clients speaking another version are told which ones they could use instead
+    if version_mismatch {
+        headers.insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+    }
+    response
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Makes sure the client speaks the same version of tus
+fn check_version(headers: &HeaderMap) -> Result<(), StatusCode> {
+    match header_str(headers, &TUS_RESUMABLE) {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(StatusCode::PRECONDITION_FAILED),
+    }
+}
+
+/// Parses Upload-Metadata, comma separated keys each followed by a base64 encoded value
+/// Returns None if any of the values isn't valid base64 or UTF-8
+pub fn parse_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = STANDARD.decode(value.trim()).ok()?;
+                (key, String::from_utf8(value).ok()?)
+            }
+            // Keys are allowed without a value
+            None => (pair, String::new()),
+        };
+        metadata.insert(key.to_string(), value);
+    }
+    Some(metadata)
+}
+
+/// Where the end of an upload that doesn't fill a whole part is kept between chunks,
+/// storage won't take parts smaller than that except for the last one
+fn tail_key(video: &Video) -> String {
+    format!("{}.tail", video.raw_video_path)
+}
+
+/// Metadata on the tail with how many parts were in storage when it was saved
+/// A tail that was already sent up in a later part, by a chunk that failed before saving the
+/// next one, is left out by comparing this to the parts there are now
+const TAIL_AFTER_PARTS: &str = "after-parts";
+
+/// How far along a tus upload is, the parts in storage plus whatever is waiting to fill the next
+struct TusProgress {
+    parts: Vec<UploadedPart>,
+    tail: Bytes,
+}
+
+impl TusProgress {
+    async fn load(state: &AppState, video: &Video, upload_id: &str) -> Result<Self, StatusCode> {
+        let mut parts = state
+            .storage
+            .list_parts(&video.raw_video_path, upload_id)
+            .await
+            .map_err(|e| match e {
+                // The upload is gone from storage, so it can't be resumed
+                StorageError::NotFound(_) => StatusCode::GONE,
+                e => {
+                    tracing::error!("Could not list parts of upload {}: {}", upload_id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            })?;
+        parts.sort_by_key(|part| part.part_number);
+        let tail_error = |e: StorageError| {
+            tracing::error!("Could not read tail of upload {}: {}", upload_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        let key = tail_key(video);
+        let current = match state.storage.head(&key).await {
+            Ok(meta) => meta.metadata.get(TAIL_AFTER_PARTS) == Some(&parts.len().to_string()),
+            Err(StorageError::NotFound(_)) => false,
+            Err(e) => return Err(tail_error(e)),
+        };
+        if !current {
+            return Ok(TusProgress {
+                parts,
+                tail: Bytes::new(),
+            });
+        }
+        let tail = match state.storage.get(&key).await {
+            Ok(mut reader) => {
+                let mut tail = Vec::new();
+                reader.read_to_end(&mut tail).await.map_err(|e| {
+                    tracing::error!("Could not read tail of upload {}: {}", upload_id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+                Bytes::from(tail)
+            }
+            Err(StorageError::NotFound(_)) => Bytes::new(),
+            Err(e) => return Err(tail_error(e)),
+        };
+        Ok(TusProgress { parts, tail })
+    }
+    /// How many bytes of the upload the server has
+    fn offset(&self) -> u64 {
+        let parts: u64 = self.parts.iter().filter_map(|part| part.size).sum();
+        parts + self.tail.len() as u64
+    }
+}
+
+/// Finds the video a tus upload is for, making sure it belongs to the user
+async fn find_video(state: &AppState, user: &User, video_id: &str) -> Result<Video, StatusCode> {
+    let video = Video::by_id(&state.db, video_id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!("Could not look up video {}: {}", video_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+    if video.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to access tus upload for video {} owned by {}",
+            user.id,
+            video.id,
+            video.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(video)
+}
+
+/// Tells clients which version, extensions and sizes are supported
+pub async fn tus_options(State(state): State<Arc<AppState>>) -> Response {
+    let max_size = state.config.upload_limits.max_size_bytes;
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_VERSION_HEADER, TUS_VERSION.to_string()),
+            (TUS_EXTENSION, TUS_EXTENSIONS.to_string()),
+            (TUS_MAX_SIZE, max_size.to_string()),
+        ],
+    )
+        .into_response()
+}
+
+/// Creates a tus upload, along with the video it's for
+/// The file name is required in the metadata, the title and stream_id it can also carry are
+/// used for the video
+pub async fn tus_create(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    check_version(&headers)?;
+    // Uploads of deferred length aren't supported, the size is needed for the quota
+    let length: u64 = header_str(&headers, &UPLOAD_LENGTH)
+        .and_then(|length| length.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let metadata = match header_str(&headers, &UPLOAD_METADATA) {
+        Some(header) => parse_metadata(header).ok_or(StatusCode::BAD_REQUEST)?,
+        None => HashMap::new(),
+    };
+    let filename = metadata.get("filename").ok_or(StatusCode::BAD_REQUEST)?;
+    let stream_id = match metadata.get("stream_id") {
+        Some(stream_id) => Some(
+            stream_id
+                .parse::<Uuid>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?,
+        ),
+        None => None,
+    };
+    check_stream_owner(&state, &user, stream_id).await?;
+
+    // Only accept formats processing can handle, clients don't always know the file type
+    let limits = &state.config.upload_limits;
+    let filetype = metadata
+        .get("filetype")
+        .filter(|filetype| !filetype.is_empty())
+        .map(String::as_str)
+        .or_else(|| UploadFormat::from_key(filename).map(|format| format.content_type()))
+        .unwrap_or_default();
+    let format = check_format(filename, filetype)
+        .and_then(|format| {
+            limits.check_size(length)?;
+            Ok(format)
+        })
+        .map_err(|e| {
+            tracing::debug!("Rejecting tus upload from user {}: {}", user.id, e);
+            rejection_status(&e)
+        })?;
+    // Make sure the upload fits in what's left of the uploaders quota
+    let declared_size = i64::try_from(length).map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    let (quota, usage) = quota_and_usage(&state, &user).await?;
+    if !fits_quota(quota, usage.total_bytes(), declared_size) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let (video, _) = start_upload(
+        &state,
+        &user,
+        metadata.get("title").cloned(),
+        stream_id,
+        format,
+        declared_size,
+    )
+    .await?;
+
+    let location = format!("{}/upload/tus/{}", state.config.api_url, video.id);
+    Ok((StatusCode::CREATED, [(header::LOCATION, location)]).into_response())
+}
+
+/// Gets the offset a tus upload should be resumed from
+pub async fn tus_head(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    check_version(&headers)?;
+    let video = find_video(&state, &user, &video_id).await?;
+    let offset = match &video.upload_id {
+        Some(upload_id) => TusProgress::load(&state, &video, upload_id).await?.offset(),
+        // Finished uploads have everything
+        None => video.raw_size_bytes.ok_or(StatusCode::NOT_FOUND)?.max(0) as u64,
+    };
+    let length = video.declared_size_bytes.unwrap_or_default().max(0) as u64;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, offset.to_string()),
+            (UPLOAD_LENGTH, length.max(offset).to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response())
+}
+
+/// Appends a chunk to a tus upload at the offset the client says it's at
+/// Whole parts go straight to storage, whatever is left over waits for the next chunk
+/// Once the last byte arrives the upload is completed and queued for processing
+pub async fn tus_patch(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    check_version(&headers)?;
+    if header_str(&headers, &header::CONTENT_TYPE) != Some(OFFSET_CONTENT_TYPE) {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let client_offset: u64 = header_str(&headers, &UPLOAD_OFFSET)
+        .and_then(|offset| offset.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    // Chunks sent at the same time would both be appended at the same offset
+    let mut lock = state.db.begin().await.map_err(|e| {
+        tracing::error!("Could not start transaction for tus upload {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let locked = Video::lock_upload(&mut lock, &video_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not lock tus upload for video {}: {}", video_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !locked {
+        return Err(StatusCode::CONFLICT);
+    }
+    let video = find_video(&state, &user, &video_id).await?;
+    let upload_id = video.upload_id.clone().ok_or(StatusCode::NOT_FOUND)?;
+    let progress = TusProgress::load(&state, &video, &upload_id).await?;
+    if client_offset != progress.offset() {
+        return Err(StatusCode::CONFLICT);
+    }
+    let length = video.declared_size_bytes.unwrap_or_default().max(0) as u64;
+
+    let key = video.raw_video_path.clone();
+    let mut offset = progress.offset();
+    let mut parts = progress.parts;
+    let mut buffer = BytesMut::from(progress.tail.as_ref());
+    let mut body = body.into_data_stream();
+    // Whatever arrived before something goes wrong is kept, so the client can resume after it
+    let mut rejection = None;
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::debug!("tus upload {} was interrupted: {}", upload_id, e);
+                rejection = Some(StatusCode::BAD_REQUEST);
+                break;
+            }
+        };
+        if offset + chunk.len() as u64 > length {
+            rejection = Some(StatusCode::PAYLOAD_TOO_LARGE);
+            break;
+        }
+        offset += chunk.len() as u64;
+        buffer.extend_from_slice(&chunk);
+        while buffer.len() >= STREAM_PART_SIZE {
+            let part = buffer.split_to(STREAM_PART_SIZE).freeze();
+            let part_number = parts.len() as i32 + 1;
+            let part = upload_part(state.storage.as_ref(), &key, &upload_id, part_number, part)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Could not upload part of tus upload {}: {}", upload_id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            parts.push(part);
+        }
+    }
+
+    if offset < length || rejection.is_some() {
+        save_tail(&state, &video, parts.len(), buffer.freeze()).await?;
+        if let Some(status) = rejection {
+            return Err(status);
+        }
+        return Ok((
+            StatusCode::NO_CONTENT,
+            [(UPLOAD_OFFSET, offset.to_string())],
+        )
+            .into_response());
+    }
+
+    // Everything is here, so the rest goes up as the last part
+    if !buffer.is_empty() {
+        let part_number = parts.len() as i32 + 1;
+        let part = upload_part(
+            state.storage.as_ref(),
+            &key,
+            &upload_id,
+            part_number,
+            buffer.freeze(),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not upload part of tus upload {}: {}", upload_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        parts.push(part);
+    }
+    state
+        .storage
+        .complete_multipart_upload(&key, &upload_id, &parts)
+        .await
+        .map_err(|e| {
+            tracing::error!("Could not complete tus upload {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    save_tail(&state, &video, parts.len(), Bytes::new()).await?;
+    // Finalizing writes to the video, which would wait on the lock forever
+    lock.rollback().await.map_err(|e| {
+        tracing::error!("Could not unlock tus upload {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    finalize_upload(&state, &user, video).await?;
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(UPLOAD_OFFSET, offset.to_string())],
+    )
+        .into_response())
+}
+
+/// Keeps the end of an upload that doesn't fill a part yet, removing it once there's none
+async fn save_tail(
+    state: &AppState,
+    video: &Video,
+    after_parts: usize,
+    tail: Bytes,
+) -> Result<(), StatusCode> {
+    let key = tail_key(video);
+    let options = PutOptions {
+        metadata: HashMap::from([(TAIL_AFTER_PARTS.to_string(), after_parts.to_string())]),
+        ..Default::default()
+    };
+    let saved = match tail.is_empty() {
+        true => state.storage.delete_prefix(&key).await.map(|_| ()),
+        false => state.storage.put(&key, tail, &options).await,
+    };
+    saved.map_err(|e| {
+        tracing::error!("Could not save tail of tus upload for {}: {}", video.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Terminates a tus upload, freeing what it has uploaded and deleting the video it was for
+pub async fn tus_delete(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    check_version(&headers)?;
+    let video = find_video(&state, &user, &video_id).await?;
+    let upload_id = video.upload_id.clone().ok_or(StatusCode::NOT_FOUND)?;
+    save_tail(&state, &video, 0, Bytes::new()).await?;
+    abandon_upload(&state, &user, &video, &upload_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
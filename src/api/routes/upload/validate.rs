@@ -0,0 +1,17 @@
+use reqwest::StatusCode;
+
+use crate::error::UploadError;
+
+/// Gets the status an upload is rejected with
+pub fn rejection_status(error: &UploadError) -> StatusCode {
+    match error {
+        UploadError::UnsupportedExtension(_)
+        | UploadError::UnsupportedContentType(_)
+        | UploadError::ContentMismatch(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        UploadError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        UploadError::InvalidSize(_)
+        | UploadError::InvalidParts(_)
+        | UploadError::Interrupted(_) => StatusCode::BAD_REQUEST,
+        UploadError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -13,6 +13,7 @@ use sqlx::{Pool, Postgres};
 use crate::{
     api::{app_state::AppState, twitch::eventsub::subscribers::subscribe_to_events},
     db::{
+        quotas::{storage_quota, StorageUsage, VideoStorageUsage},
         users::{UserRole, UserSettings},
         User,
     },
@@ -40,6 +41,43 @@ pub async fn get_self(Extension(user): Extension<Option<User>>) -> impl IntoResp
     }
 }
 
+#[derive(Serialize)]
+pub struct UsageResponse {
+    /// None when the user has no quota
+    quota_bytes: Option<i64>,
+    used_bytes: i64,
+    raw_bytes: i64,
+    processed_bytes: i64,
+    reserved_bytes: i64,
+    videos: Vec<VideoStorageUsage>,
+}
+
+/// Gets how much storage the owner of the token uses, broken down per video
+pub async fn get_usage(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+) -> Result<Json<UsageResponse>, StatusCode> {
+    let user = user.ok_or(StatusCode::UNAUTHORIZED)?;
+    let quota_bytes = storage_quota(user.id, &state.db).await.map_err(|e| {
+        tracing::error!("Error getting storage quota for {}: {}", user.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let usage = StorageUsage::by_user_id(user.id, &state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!("Error getting storage usage for {}: {}", user.id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(UsageResponse {
+        quota_bytes,
+        used_bytes: usage.total_bytes(),
+        raw_bytes: usage.raw_bytes(),
+        processed_bytes: usage.processed_bytes(),
+        reserved_bytes: usage.reserved_bytes(),
+        videos: usage.videos,
+    }))
+}
+
 #[derive(Deserialize, Debug)]
 pub struct UserByID {
     id: String,
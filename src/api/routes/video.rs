@@ -1,15 +1,23 @@
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Extension, Json,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use sqlx::PgPool;
+use std::{collections::HashSet, sync::Arc};
 
 use crate::{
     api::app_state::AppState,
-    db::{ProcessingStatus, User, Video},
+    db::{
+        PrivacyStatus, ProcessingError, ProcessingStatus, User, Video, VideoCursor, VideoFilter,
+        VideoSort,
+    },
+    queue::{
+        cleanup::{cleanup_storage, PendingUpload, StorageCleanupPayload},
+        Job,
+    },
 };
 
 #[derive(Deserialize, Debug)]
@@ -17,117 +25,270 @@ pub struct VideoByID {
     id: String,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct VideoByUserName {
-    name: String,
+/// Most videos returned in a single page, and how many are returned unless fewer are asked for
+const MAX_PAGE_SIZE: i64 = 100;
+const DEFAULT_PAGE_SIZE: i64 = 24;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ListVideosQuery {
+    /// The next_cursor of the previous page
+    cursor: Option<String>,
+    limit: Option<i64>,
+    #[serde(default)]
+    sort: VideoSort,
+    /// User name of the owner
+    #[serde(alias = "name")]
+    owner: Option<String>,
+    processing_status: Option<ProcessingStatus>,
+    privacy_status: Option<PrivacyStatus>,
+    created_after: Option<chrono::DateTime<chrono::Utc>>,
+    created_before: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Serialize)]
 pub struct SanitizedVideoData {
     id: String,
     title: String,
+    description: Option<String>,
+    tags: Vec<String>,
+    duration_seconds: Option<f64>,
     processing_status: ProcessingStatus,
-    video_path: Option<String>,
+    processing_error: Option<ProcessingError>,
+    processing_error_message: Option<String>,
+    privacy_status: PrivacyStatus,
+    chapters_path: Option<String>,
+    preview_webp_path: Option<String>,
+    preview_mp4_path: Option<String>,
+    waveform_path: Option<String>,
     created_at: chrono::DateTime<chrono::Utc>,
     updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl From<Video> for SanitizedVideoData {
+    fn from(video: Video) -> Self {
+        SanitizedVideoData {
+            id: video.id,
+            title: video.title,
+            description: video.description,
+            tags: video.tags,
+            duration_seconds: video.duration_seconds,
+            processing_status: video.processing_status,
+            processing_error: video.processing_error,
+            processing_error_message: video.processing_error_message,
+            privacy_status: video.privacy_status,
+            chapters_path: video.chapters_path,
+            preview_webp_path: video.preview_webp_path,
+            preview_mp4_path: video.preview_mp4_path,
+            waveform_path: video.waveform_path,
+            created_at: video.created_at,
+            updated_at: video.updated_at,
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct VideoResponse {
     videos: Vec<SanitizedVideoData>,
+    /// Fetches the page after this one, missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
 }
 
-/// A function for getting videos based on video id, user id, username, or combinations thereof
+/// A function for getting a video by ID, or a page of videos sorted and filtered by the query
+/// Listings only include public videos, plus the callers own private and unlisted ones, and
+/// videos fetched by ID that the caller isn't allowed to watch are not found
 pub async fn get_videos(
     State(state): State<Arc<AppState>>,
-    Extension(_user): Extension<Option<User>>,
+    Extension(user): Extension<Option<User>>,
     video_query: Option<Query<VideoByID>>,
-    username_query: Option<Query<VideoByUserName>>,
+    Query(list_query): Query<ListVideosQuery>,
 ) -> impl IntoResponse {
     tracing::trace!(
-        "Got video get query params:\n\tvideo_query: {:?}\n\tusername_query: {:?}",
+        "Got video get query params:\n\tvideo_query: {:?}\n\tlist_query: {:?}",
         video_query,
-        username_query
+        list_query
     );
-    match (video_query, username_query) {
-        // Video by ID
-        (Some(video_query), None) => {
-            let video = Video::by_id(&state.db, &video_query.id)
-                .await
-                .map_err(|_e| StatusCode::BAD_REQUEST)?;
-            let res_video = SanitizedVideoData {
-                id: video.id,
-                processing_status: video.processing_status,
-                title: video.title,
-                video_path: video.processed_video_path,
-                created_at: video.created_at,
-                updated_at: video.updated_at,
-            };
-            Ok(Json(VideoResponse {
-                videos: vec![res_video],
-            }))
-        }
-        // Videos by user name
-        (None, Some(username_query)) => {
-            let videos = Video::by_username(&state.db, &username_query.name)
-                .await
-                .map_err(|e| {
-                    tracing::error!("Error getting videos by username {e}");
-                    StatusCode::BAD_REQUEST
-                })?;
+    videos_for(
+        &state.db,
+        user.as_ref(),
+        video_query.map(|Query(video_query)| video_query),
+        list_query,
+    )
+    .await
+    .map(Json)
+}
 
-            if !videos.is_empty() {
-                let videos = videos
-                    .into_iter()
-                    .map(|video| SanitizedVideoData {
-                        id: video.id,
-                        title: video.title,
-                        processing_status: video.processing_status,
-                        video_path: video.processed_video_path,
-                        created_at: video.created_at,
-                        updated_at: video.updated_at,
-                    })
-                    .collect();
-                Ok(Json(VideoResponse { videos }))
-            } else {
-                Err(StatusCode::NOT_FOUND)
+/// Gets the videos a query asks for, as the user sees them
+pub async fn videos_for(
+    pool: &PgPool,
+    user: Option<&User>,
+    video_query: Option<VideoByID>,
+    list_query: ListVideosQuery,
+) -> Result<VideoResponse, StatusCode> {
+    match video_query {
+        // Video by ID, which can't be combined with listing by owner
+        Some(_) if list_query.owner.is_some() => Err(StatusCode::NOT_IMPLEMENTED),
+        Some(video_query) => {
+            let video = fetch_video(pool, &video_query.id).await?;
+            // Hide that the video exists from anyone who can't watch it
+            if !video.is_viewable_by(user) {
+                return Err(StatusCode::NOT_FOUND);
             }
+            Ok(VideoResponse {
+                videos: vec![video.into()],
+                next_cursor: None,
+            })
         }
-        // Videos by video id and user name
-        (Some(_video_query), Some(_username_query)) => Err(StatusCode::NOT_IMPLEMENTED),
-        // No query params, get all videos
-        (None, None) => {
-            tracing::debug!("No queries provided, getting all videos");
-            let videos = match Video::all(&state.db).await {
-                Ok(videos) => videos,
-                Err(e) => {
-                    tracing::error!("Error listing videos: {e}");
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
+        // A page of the videos the caller can see
+        None => {
+            let cursor = match &list_query.cursor {
+                Some(cursor) => Some(VideoCursor::decode(cursor).ok_or(StatusCode::BAD_REQUEST)?),
+                None => None,
+            };
+            let limit = list_query
+                .limit
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .clamp(1, MAX_PAGE_SIZE);
+            let filter = VideoFilter {
+                viewer_id: user.map(|user| user.id),
+                owner: list_query.owner,
+                processing_status: list_query.processing_status,
+                privacy_status: list_query.privacy_status,
+                created_after: list_query.created_after,
+                created_before: list_query.created_before,
+            };
+            // Fetch one extra to know whether there's another page
+            let mut videos =
+                Video::page(pool, &filter, list_query.sort, cursor.as_ref(), limit + 1)
+                    .await
+                    .map_err(|e| {
+                        tracing::error!("Error listing videos: {e}");
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    })?;
+            let next_cursor = if videos.len() as i64 > limit {
+                videos.truncate(limit as usize);
+                videos.last().map(|video| VideoCursor::from(video).encode())
+            } else {
+                None
             };
 
-            let videos = videos
-                .into_iter()
-                .map(|video| SanitizedVideoData {
-                    id: video.id,
-                    title: video.title,
-                    processing_status: video.processing_status,
-                    video_path: video.processed_video_path,
-                    created_at: video.created_at,
-                    updated_at: video.updated_at,
-                })
-                .collect();
-            Ok(Json(VideoResponse { videos }))
+            let videos = videos.into_iter().map(SanitizedVideoData::from).collect();
+            Ok(VideoResponse {
+                videos,
+                next_cursor,
+            })
         }
     }
 }
 
+/// Finds a video, mapping a missing one to a 404
+pub(crate) async fn find_video(state: &AppState, video_id: &str) -> Result<Video, StatusCode> {
+    fetch_video(&state.db, video_id).await
+}
+
+async fn fetch_video(pool: &PgPool, video_id: &str) -> Result<Video, StatusCode> {
+    Video::by_id(pool, video_id).await.map_err(|e| {
+        if let sqlx::Error::RowNotFound = e {
+            StatusCode::NOT_FOUND
+        } else {
+            tracing::error!("Error getting video {}: {}", video_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })
+}
+
+/// Longest title a video can have, matching the column
+pub(crate) const MAX_TITLE_LENGTH: usize = 100;
+/// Longest description a video can have
+pub(crate) const MAX_DESCRIPTION_LENGTH: usize = 5000;
+/// Most tags a video can have, and the longest each of them can be
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 50;
+
+#[derive(Deserialize)]
+pub struct UpdateVideoRequest {
+    title: Option<String>,
+    /// An empty description clears it
+    description: Option<String>,
+    /// Replaces all of the videos tags
+    tags: Option<Vec<String>>,
+    privacy_status: Option<PrivacyStatus>,
+}
+
+/// Cleans up tags so the same tag is only stored once, however it was typed
+fn normalize_tags(tags: &[String]) -> Option<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+            return None;
+        }
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    (normalized.len() <= MAX_TAGS).then_some(normalized)
+}
+
+/// Updates the title, description, tags and privacy of a video, leaving out what isn't in the request
+pub async fn update_video(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<Option<User>>,
+    Path(video_id): Path<String>,
+    Json(request): Json<UpdateVideoRequest>,
+) -> Result<Json<SanitizedVideoData>, StatusCode> {
+    let Some(user) = user else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let video = find_video(&state, &video_id).await?;
+    // Only allow editing if the user owns the video
+    if video.user_id != user.id {
+        tracing::warn!(
+            "User {} attempted to edit video {} owned by {}",
+            user.id,
+            video.id,
+            video.user_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let title = request.title.as_deref().map(str::trim);
+    if title.is_some_and(|title| title.is_empty() || title.chars().count() > MAX_TITLE_LENGTH) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let description = request.description.as_deref().map(str::trim);
+    if description.is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LENGTH) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let tags = match &request.tags {
+        Some(tags) => Some(normalize_tags(tags).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+
+    let video = Video::update_metadata(
+        &state.db,
+        &video.id,
+        title,
+        description,
+        tags.as_deref(),
+        request.privacy_status,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Error updating video {}: {}", video.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(video.into()))
+}
+
 #[derive(Serialize)]
 pub struct DeleteVideoResponse {
     deleted_videos: Vec<String>,
 }
 
-// TODO: Refactor to delete from R2 instead
+/// Deletes videos along with everything they have in storage
+/// Storage that can't be cleaned up right away is handed to a cleanup job, and videos are only
+/// removed from the database once their storage is gone or the job is queued
 pub async fn delete_videos(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<Option<User>>,
@@ -156,104 +317,80 @@ pub async fn delete_videos(
         Err(_e) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
     };
 
-    let mut successfully_deleted_ids = Vec::new();
-
-    // Delete the physical video files
-    for video in &videos {
-        // Only allow deletion if the user owns the video
-        if video.user_id != user.id {
-            tracing::warn!(
-                "User {} attempted to delete video {} owned by {}",
-                user.id,
-                video.id,
-                video.user_id
-            );
-            continue; // Skip this video and continue with others
-        }
-
-        let mut deletion_successful = true;
-
-        // Delete raw video file
-        tracing::debug!(
-            "Attempting to delete raw video file {}",
-            video.raw_video_path
-        );
-        if let Err(e) = tokio::fs::remove_file(&video.raw_video_path).await {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                tracing::debug!("Raw video file {} already deleted", video.raw_video_path);
-            } else {
-                tracing::error!(
-                    "Failed to delete raw video file {}: {}",
-                    video.raw_video_path,
-                    e
+    // Only allow deletion if the user owns the video
+    let owned_videos: Vec<&Video> = videos
+        .iter()
+        .filter(|video| {
+            if video.user_id != user.id {
+                tracing::warn!(
+                    "User {} attempted to delete video {} owned by {}",
+                    user.id,
+                    video.id,
+                    video.user_id
                 );
-                deletion_successful = false;
             }
-        } else {
-            tracing::debug!(
-                "Successfully deleted raw video file {}",
-                video.raw_video_path
-            );
-        }
+            video.user_id == user.id
+        })
+        .collect();
+    let owned_ids: Vec<String> = owned_videos.iter().map(|video| video.id.clone()).collect();
 
-        // For the processed video folder:
-        if let Some(processed_path) = &video.processed_video_path {
-            tracing::debug!("Attempting to delete processed video folder");
-            let path = std::path::PathBuf::from(processed_path);
+    // Storage reused by videos that aren't being deleted has to stay
+    let storage_ids: Vec<String> = owned_videos
+        .iter()
+        .flat_map(|video| [video.id.clone(), video.storage_id().to_string()])
+        .collect();
+    let still_referenced: HashSet<String> =
+        match Video::referenced_storage_ids(&state.db, &storage_ids, &owned_ids).await {
+            Ok(ids) => ids.into_iter().collect(),
+            Err(e) => {
+                tracing::error!("Error checking which storage is still in use: {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
 
-            if let Some(parent_dir) = path.parent() {
-                if let Some(folder_name) = parent_dir.file_name() {
-                    if let Some(folder_str) = folder_name.to_str() {
-                        if folder_str == video.id {
-                            if let Err(e) = tokio::fs::remove_dir_all(parent_dir).await {
-                                if e.kind() == std::io::ErrorKind::NotFound {
-                                    tracing::debug!(
-                                        "Processed video folder {} already deleted",
-                                        parent_dir.display()
-                                    );
-                                } else {
-                                    tracing::error!(
-                                        "Failed to delete processed video folder {}: {}",
-                                        parent_dir.display(),
-                                        e
-                                    );
-                                    deletion_successful = false;
-                                }
-                            } else {
-                                tracing::debug!(
-                                    "Successfully deleted processed video folder {}",
-                                    parent_dir.display()
-                                );
-                            }
-                        } else {
-                            tracing::error!(
-                                "Video folder name '{}' doesn't match video ID '{}'. Skipping deletion.",
-                                folder_str,
-                                video.id
-                            );
-                            deletion_successful = false;
-                        }
-                    } else {
-                        tracing::error!("Invalid folder name encoding");
-                        deletion_successful = false;
-                    }
-                } else {
-                    tracing::error!("Couldn't get folder name from path");
-                    deletion_successful = false;
-                }
-            } else {
-                tracing::error!("Couldn't get parent directory from path");
-                deletion_successful = false;
+    let mut successfully_deleted_ids = Vec::new();
+    let mut released = HashSet::new();
+
+    for video in owned_videos {
+        // A video has its own files, and shares the raw source and renditions of its source video
+        let mut cleanups = Vec::new();
+        let pending_upload = video.upload_id.as_ref().map(|upload_id| PendingUpload {
+            key: video.raw_video_path.clone(),
+            upload_id: upload_id.clone(),
+        });
+        for (storage_id, prefix) in [
+            (video.id.as_str(), video.own_storage_prefix()),
+            (video.storage_id(), video.storage_prefix()),
+        ] {
+            if still_referenced.contains(storage_id) || !released.insert(storage_id.to_string()) {
+                continue;
             }
+            cleanups.push(StorageCleanupPayload {
+                // The trailing slash keeps other videos sharing the start of the ID safe
+                prefix: format!("{}/", prefix),
+                pending_upload: None,
+            });
+        }
+        match cleanups.first_mut() {
+            Some(cleanup) => cleanup.pending_upload = pending_upload,
+            None if pending_upload.is_some() => cleanups.push(StorageCleanupPayload {
+                // Nothing else of the video can go, but its unfinished upload still has to
+                prefix: video.raw_video_path.clone(),
+                pending_upload,
+            }),
+            None => {}
         }
 
-        // Only add to successfully deleted list if all files were deleted
-        if deletion_successful {
+        let mut released_all = true;
+        for cleanup in cleanups {
+            released_all &= release_storage(&state, &video.id, cleanup).await;
+        }
+        if released_all {
             successfully_deleted_ids.push(video.id.clone());
         }
     }
 
-    // Only delete videos from database if we successfully deleted their files
+    // Only delete videos from database if their storage is gone or queued for cleanup
     if !successfully_deleted_ids.is_empty() {
         match Video::delete(&state.db, user.id, successfully_deleted_ids.clone()).await {
             Ok(_) => Ok(Json(DeleteVideoResponse {
@@ -266,3 +403,37 @@ pub async fn delete_videos(
         Err(StatusCode::INTERNAL_SERVER_ERROR)
     }
 }
+
+/// Cleans up storage a deleted video no longer needs, handing it to a cleanup job if that fails
+/// Returns whether the storage is gone or queued to be
+async fn release_storage(state: &AppState, video_id: &str, cleanup: StorageCleanupPayload) -> bool {
+    match cleanup_storage(state.storage.as_ref(), &state.cold_storage, &cleanup).await {
+        Ok(deleted) => {
+            tracing::debug!(
+                "Deleted {} objects under {} for video {}",
+                deleted,
+                cleanup.prefix,
+                video_id
+            );
+            true
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Failed to clean up storage for video {}, queueing a cleanup job: {}",
+                video_id,
+                e
+            );
+            match state.job_queue.enqueue(Job::from(cleanup)).await {
+                Ok(_) => true,
+                Err(e) => {
+                    tracing::error!(
+                        "Could not queue storage cleanup for video {}: {}",
+                        video_id,
+                        e
+                    );
+                    false
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long playback URLs stay valid when PLAYBACK_URL_TTL isn't set
+pub const DEFAULT_PLAYBACK_URL_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    Expired,
+    Invalid,
+}
+
+/// The query parameters that grant access to a videos playback files until they expire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackToken {
+    /// Unix timestamp the token stops working at
+    pub expires: i64,
+    pub signature: String,
+}
+
+impl PlaybackToken {
+    /// Gets when the token stops working
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.expires, 0).unwrap_or_default()
+    }
+    /// Gets how long until the token stops working
+    pub fn remaining(&self) -> Duration {
+        let seconds = self.expires - Utc::now().timestamp();
+        Duration::from_secs(seconds.max(1) as u64)
+    }
+    /// Formats the token as a query string to append to playback URLs
+    pub fn to_query(&self) -> String {
+        format!("expires={}&signature={}", self.expires, self.signature)
+    }
+}
+
+/// Signs and verifies playback tokens, scoped to a single video
+pub struct PlaybackSigner {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl PlaybackSigner {
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        PlaybackSigner {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+    /// Creates a signer from PLAYBACK_SIGNING_SECRET, falling back to JWT_SECRET
+    /// PLAYBACK_URL_TTL sets how many seconds signed URLs are valid for
+    pub fn from_env() -> Self {
+        let secret = std::env::var("PLAYBACK_SIGNING_SECRET")
+            .or_else(|_| std::env::var("JWT_SECRET"))
+            .expect("PLAYBACK_SIGNING_SECRET or JWT_SECRET must be set");
+        let ttl = std::env::var("PLAYBACK_URL_TTL")
+            .ok()
+            .map(|ttl| ttl.parse().expect("PLAYBACK_URL_TTL must be a number"))
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_PLAYBACK_URL_TTL);
+        PlaybackSigner::new(secret, ttl)
+    }
+    fn mac(&self, video_id: &str, expires: i64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC can take key of any size");
+        mac.update(format!("{}:{}", video_id, expires).as_bytes());
+        mac
+    }
+    /// Issues a token for the given video that expires after the configured TTL
+    pub fn issue(&self, video_id: &str) -> PlaybackToken {
+        let expires = Utc::now().timestamp() + self.ttl.as_secs() as i64;
+        let signature = hex::encode(self.mac(video_id, expires).finalize().into_bytes());
+        PlaybackToken { expires, signature }
+    }
+    /// Checks that a token was issued for the given video and hasn't expired yet
+    pub fn verify(&self, video_id: &str, token: &PlaybackToken) -> Result<(), SignatureError> {
+        let signature = hex::decode(&token.signature).map_err(|_| SignatureError::Invalid)?;
+        // Constant-time comparison
+        self.mac(video_id, token.expires)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+        if token.expires <= Utc::now().timestamp() {
+            return Err(SignatureError::Expired);
+        }
+        Ok(())
+    }
+}
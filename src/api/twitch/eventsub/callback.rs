@@ -4,16 +4,19 @@ use axum::{
     response::IntoResponse,
 };
 use bytes::Bytes;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use std::sync::Arc;
 
 use crate::{
     api::{app_state::AppState, routes::auth::oauth::twitch::TwitchCredentials},
-    db::streams::Stream,
+    db::{stream_events::StreamEvent, streams::Stream, DBPool},
     event::Event,
-    twitch::{subscription::Notification, ChatMessagePayload, StreamStatusPayload},
+    twitch::{
+        subscription::Notification, ChannelUpdatePayload, ChatMessagePayload, RaidPayload,
+        StreamStatusPayload,
+    },
 };
 
 type HmacSha256 = Hmac<Sha256>;
@@ -199,6 +202,94 @@ pub async fn handle_webhook(
                         })
                         .expect("Failed to publish stream status event");
                 }
+                "channel.update" => {
+                    let Some(raw_payload) = notification.event else {
+                        tracing::error!("Received channel.update notification without event");
+                        return (StatusCode::BAD_REQUEST, "Missing event data").into_response();
+                    };
+                    let Ok(update_payload) =
+                        serde_json::from_value::<ChannelUpdatePayload>(raw_payload.clone())
+                    else {
+                        tracing::error!("Failed to parse channel.update notification");
+                        return (StatusCode::BAD_REQUEST, "Invalid event data").into_response();
+                    };
+                    let Ok(user_account) = update_payload.find_broadcaster_account(&state.db).await
+                    else {
+                        tracing::error!("Failed to find broadcaster account");
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            "Failed to find broadcaster account",
+                        )
+                            .into_response();
+                    };
+                    // Save the event against the live stream so it can become a chapter
+                    if let Err(e) = record_stream_event(
+                        &state.db,
+                        user_account.user_id,
+                        &notification_type,
+                        raw_payload.clone(),
+                        parse_message_timestamp(timestamp),
+                    )
+                    .await
+                    {
+                        tracing::error!("Failed to record channel.update event: {}", e);
+                        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to record event")
+                            .into_response();
+                    }
+
+                    let subject = Event::from(update_payload).get_subject();
+                    if let Err(e) = state
+                        .event_stream
+                        .publish(subject, raw_payload.to_string()) // Pass the original payload so we can skip serialization
+                        .await
+                    {
+                        tracing::error!("Failed to publish channel update event: {}", e);
+                    }
+                }
+                "channel.raid" => {
+                    let Some(raw_payload) = notification.event else {
+                        tracing::error!("Received channel.raid notification without event");
+                        return (StatusCode::BAD_REQUEST, "Missing event data").into_response();
+                    };
+                    let Ok(raid_payload) =
+                        serde_json::from_value::<RaidPayload>(raw_payload.clone())
+                    else {
+                        tracing::error!("Failed to parse channel.raid notification");
+                        return (StatusCode::BAD_REQUEST, "Invalid event data").into_response();
+                    };
+                    let Ok(user_account) = raid_payload.find_broadcaster_account(&state.db).await
+                    else {
+                        tracing::error!("Failed to find broadcaster account");
+                        return (
+                            StatusCode::BAD_REQUEST,
+                            "Failed to find broadcaster account",
+                        )
+                            .into_response();
+                    };
+                    // Save the event against the live stream so it can become a chapter
+                    if let Err(e) = record_stream_event(
+                        &state.db,
+                        user_account.user_id,
+                        &notification_type,
+                        raw_payload.clone(),
+                        parse_message_timestamp(timestamp),
+                    )
+                    .await
+                    {
+                        tracing::error!("Failed to record channel.raid event: {}", e);
+                        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to record event")
+                            .into_response();
+                    }
+
+                    let subject = Event::from(raid_payload).get_subject();
+                    if let Err(e) = state
+                        .event_stream
+                        .publish(subject, raw_payload.to_string()) // Pass the original payload so we can skip serialization
+                        .await
+                    {
+                        tracing::error!("Failed to publish raid event: {}", e);
+                    }
+                }
                 "channel.follow" => {
                     return (
                         StatusCode::NOT_IMPLEMENTED,
@@ -279,6 +370,34 @@ pub async fn handle_webhook(
     }
 }
 
+/// Records an event against the user's live stream, events outside of a stream are skipped
+async fn record_stream_event(
+    db: &DBPool,
+    user_id: uuid::Uuid,
+    event_type: &str,
+    payload: serde_json::Value,
+    occurred_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    match Stream::find_most_recent_active_by_user_id(user_id, db).await? {
+        Some(stream) => {
+            StreamEvent::create(stream.id, event_type, payload, occurred_at, db).await?;
+        }
+        None => tracing::debug!(
+            "No active stream for user {}, skipping {} event",
+            user_id,
+            event_type
+        ),
+    }
+    Ok(())
+}
+
+/// Parses the EventSub message timestamp, falling back to now if it's malformed
+fn parse_message_timestamp(timestamp: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
 fn verify_signature(secret: &str, message: &str, signature: &str) -> bool {
     let mut mac =
         HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
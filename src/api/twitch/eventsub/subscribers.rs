@@ -30,6 +30,9 @@ enum EventSubCondition {
     ChannelPoints {
         broadcaster_user_id: String,
     },
+    Raid {
+        to_broadcaster_user_id: String,
+    },
 }
 
 #[derive(Debug, Serialize)]
@@ -173,6 +176,31 @@ pub async fn subscribe_to_events(
                 &app_access_token,
             ));
         }
+        // Title, category and raid events become chapters in the stream's VOD
+        subscription_tasks.push(subscribe_to_event(
+            &client,
+            "channel.update",
+            "2",
+            EventSubCondition::Basic {
+                broadcaster_user_id: twitch_user_id.clone(),
+            },
+            webhook_url,
+            &secret,
+            &credentials.id,
+            &app_access_token,
+        ));
+        subscription_tasks.push(subscribe_to_event(
+            &client,
+            "channel.raid",
+            "1",
+            EventSubCondition::Raid {
+                to_broadcaster_user_id: twitch_user_id.clone(),
+            },
+            webhook_url,
+            &secret,
+            &credentials.id,
+            &app_access_token,
+        ));
     }
 
     if settings.chat_messages_enabled.is_some() {
@@ -1,12 +1,13 @@
 use axum::{
+    extract::DefaultBodyLimit,
     middleware as axum_mw,
     response::IntoResponse,
-    routing::{delete, get, post, put},
+    routing::{delete, get, head, options, patch, post, put},
     Router,
 };
 use farmhand::api::{app_state::AppState, config::Config, middleware, routes, twitch};
 
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 use tower_http::{
     cors::CorsLayer,
     trace::{DefaultOnResponse, TraceLayer},
@@ -70,6 +71,7 @@ async fn main() {
             Router::new()
                 .route("/me", get(routes::user::get_self))
                 .route("/me", put(routes::user::save_user))
+                .route("/me/usage", get(routes::user::get_usage))
                 .layer(axum_mw::from_fn_with_state(
                     state.clone(),
                     middleware::auth::auth_middleware,
@@ -80,6 +82,35 @@ async fn main() {
             Router::new()
                 .route("/start", post(routes::upload::cloud::init_upload))
                 .route("/finish", post(routes::upload::cloud::complete_upload))
+                .route(
+                    "/direct",
+                    // Direct uploads enforce their own size limits while streaming
+                    post(routes::upload::direct::direct_upload).layer(DefaultBodyLimit::disable()),
+                )
+                .route("/import", post(routes::upload::import::import_video))
+                .route(
+                    "/tus",
+                    options(routes::upload::tus::tus_options)
+                        .post(routes::upload::tus::tus_create)
+                        .layer(axum_mw::map_response(routes::upload::tus::tus_resumable)),
+                )
+                .route(
+                    "/tus/:video_id",
+                    head(routes::upload::tus::tus_head)
+                        // Chunks are limited by what's left of the upload instead
+                        .patch(routes::upload::tus::tus_patch)
+                        .delete(routes::upload::tus::tus_delete)
+                        .layer(DefaultBodyLimit::disable())
+                        .layer(axum_mw::map_response(routes::upload::tus::tus_resumable)),
+                )
+                .route("/check", post(routes::upload::dedup::check_duplicate))
+                .route("/reuse", post(routes::upload::dedup::reuse_video))
+                .route("/:upload_id", delete(routes::upload::resume::abort_upload))
+                .route(
+                    "/:upload_id/parts",
+                    get(routes::upload::resume::list_upload_parts)
+                        .post(routes::upload::resume::resign_upload_parts),
+                )
                 .layer(axum_mw::from_fn_with_state(
                     state.clone(),
                     middleware::auth::auth_middleware,
@@ -90,12 +121,68 @@ async fn main() {
             Router::new()
                 .route("/", get(routes::video::get_videos))
                 .route("/", delete(routes::video::delete_videos))
+                .route("/:id", patch(routes::video::update_video))
+                .route("/:id/playback", get(routes::playback::get_playback))
+                .route("/:id/heartbeat", post(routes::analytics::record_heartbeat))
+                .route("/:id/stats", get(routes::analytics::get_video_stats))
+                .route(
+                    "/:id/chapters",
+                    get(routes::chapters::get_chapters).put(routes::chapters::update_chapters),
+                )
                 .layer(axum_mw::from_fn_with_state(
                     state.clone(),
                     middleware::auth::auth_middleware,
                 )),
         )
-        .nest_service("/videos", tower_http::services::ServeDir::new("videos"))
+        .nest(
+            "/playlist",
+            Router::new()
+                .route(
+                    "/",
+                    get(routes::playlist::get_playlists).post(routes::playlist::create_playlist),
+                )
+                .route(
+                    "/:id",
+                    get(routes::playlist::get_playlist)
+                        .patch(routes::playlist::update_playlist)
+                        .delete(routes::playlist::delete_playlist),
+                )
+                .route(
+                    "/:id/items",
+                    post(routes::playlist::add_playlist_item)
+                        .put(routes::playlist::reorder_playlist_items),
+                )
+                .route(
+                    "/:id/items/:video_id",
+                    delete(routes::playlist::remove_playlist_item),
+                )
+                .route(
+                    "/:id/cover",
+                    get(routes::playlist::get_cover)
+                        .put(routes::playlist::upload_cover)
+                        .delete(routes::playlist::delete_cover)
+                        .layer(DefaultBodyLimit::max(
+                            routes::playlist::MAX_COVER_IMAGE_BYTES,
+                        )),
+                )
+                .layer(axum_mw::from_fn_with_state(
+                    state.clone(),
+                    middleware::auth::auth_middleware,
+                )),
+        )
+        .nest(
+            "/search",
+            Router::new().route("/", get(routes::search::search)).layer(
+                axum_mw::from_fn_with_state(state.clone(), middleware::auth::auth_middleware),
+            ),
+        )
+        .route(
+            "/playback/:id/*path",
+            get(routes::playback::get_playback_file).layer(axum_mw::from_fn_with_state(
+                state.clone(),
+                middleware::auth::auth_middleware,
+            )),
+        )
         .route("/health", get(routes::health::health_check))
         .with_state(state)
         .layer(CorsLayer::permissive())
@@ -128,104 +215,16 @@ async fn main() {
         "API Server started on http://{}",
         listener.local_addr().unwrap()
     );
-    axum::serve(listener, app).await.unwrap();
+    // Heartbeats tell signed out viewers apart by their address
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 /// Render the root index page
 async fn index() -> impl IntoResponse {
     "Welcome to the farmhand api"
 }
-
-// Deletes all files from cloudflare
-// TODO: Move to a script and make environment specific
-// pub async fn delete_all_files(
-//     State(state): State<Arc<AppState>>,
-// ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-//     let client = &state.s3_client;
-//     let bucket = std::env::var("UPLOAD_BUCKET").expect("UPLOAD_BUCKET required");
-
-//     // First, abort all multipart uploads
-//     let multipart_uploads = client
-//         .list_multipart_uploads()
-//         .bucket(&bucket)
-//         .send()
-//         .await
-//         .map_err(|e| {
-//             (
-//                 StatusCode::INTERNAL_SERVER_ERROR,
-//                 Json(json!({ "error": format!("Failed to list multipart uploads: {}", e) })),
-//             )
-//         })?;
-
-//     let uploads = multipart_uploads.uploads();
-//     for upload in uploads {
-//         if let (Some(key), Some(upload_id)) = (upload.key(), upload.upload_id()) {
-//             client
-//                 .abort_multipart_upload()
-//                 .bucket(&bucket)
-//                 .key(key)
-//                 .upload_id(upload_id)
-//                 .send()
-//                 .await
-//                 .map_err(|e| {
-//                     (
-//                         StatusCode::INTERNAL_SERVER_ERROR,
-//                         Json(
-//                             json!({ "error": format!("Failed to abort multipart upload: {}", e) }),
-//                         ),
-//                     )
-//                 })?;
-//         }
-//     }
-
-//     // Then delete all complete objects
-//     let objects = client
-//         .list_objects_v2()
-//         .bucket(&bucket)
-//         .send()
-//         .await
-//         .map_err(|e| {
-//             (
-//                 StatusCode::INTERNAL_SERVER_ERROR,
-//                 Json(json!({ "error": format!("Failed to list objects: {}", e) })),
-//             )
-//         })?;
-
-//     // If there are objects to delete
-//     if !objects.contents().is_empty() {
-//         // Prepare objects for deletion
-//         let objects_to_delete: Vec<_> = objects
-//             .contents()
-//             .iter()
-//             .filter_map(|obj| {
-//                 obj.key().map(|k| {
-//                     aws_sdk_s3::types::ObjectIdentifier::builder()
-//                         .key(k)
-//                         .build()
-//                         .expect("Could not build object identifier")
-//                 })
-//             })
-//             .collect();
-
-//         // Delete the objects
-//         client
-//             .delete_objects()
-//             .bucket(&bucket)
-//             .delete(
-//                 aws_sdk_s3::types::Delete::builder()
-//                     .set_objects(Some(objects_to_delete))
-//                     .build()
-//                     .expect("Could not build deleter"),
-//             )
-//             .send()
-//             .await
-//             .map_err(|e| {
-//                 (
-//                     StatusCode::INTERNAL_SERVER_ERROR,
-//                     Json(json!({ "error": format!("Failed to delete objects: {}", e) })),
-//                 )
-//             })?;
-//     }
-
-//     Ok(Json(json!({ "message": "All files deleted successfully" })))
-// }
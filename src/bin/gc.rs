@@ -0,0 +1,54 @@
+use anyhow::Result;
+use farmhand::queue::{
+    gc::{collect_garbage, GarbageCollectPayload},
+    RunnerContext,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Runs storage garbage collection once and prints the report
+/// Usage: gc [--dry-run] [--stale-upload-hours N] [--orphan-grace-hours N]
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "gc=info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let mut payload = GarbageCollectPayload::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => payload.dry_run = true,
+            "--stale-upload-hours" => {
+                payload.stale_upload_hours = args
+                    .next()
+                    .and_then(|hours| hours.parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("--stale-upload-hours needs a number"))?;
+            }
+            "--orphan-grace-hours" => {
+                payload.orphan_grace_hours = args
+                    .next()
+                    .and_then(|hours| hours.parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("--orphan-grace-hours needs a number"))?;
+            }
+            other => return Err(anyhow::anyhow!("Unknown argument {}", other)),
+        }
+    }
+
+    if payload.dry_run {
+        tracing::info!("Dry run, nothing will be deleted");
+    }
+    let context = RunnerContext::from_env().await?;
+    let report = collect_garbage(
+        &context.db,
+        context.storage.as_ref(),
+        &context.cold_storage,
+        &payload,
+    )
+    .await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
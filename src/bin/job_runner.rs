@@ -1,13 +1,49 @@
+use std::{sync::Arc, time::Duration};
+
 use anyhow::Result;
 use async_nats::jetstream::AckKind;
 use farmhand::{
     event::MESSAGE_PREFIX,
     nats::create_nats_client,
-    queue::{process_message, Queue},
+    queue::{
+        analytics::AggregateViewStatsPayload, gc::GarbageCollectPayload, process_message,
+        schedule_job, tiering::TierRawSourcesPayload, Job, Queue, RunnerContext,
+    },
 };
 use futures::StreamExt;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+/// Queues a job every interval read from the given env var, counted in units, 0 turns it off
+async fn spawn_scheduled(
+    nats_client: &async_nats::Client,
+    interval_var: &str,
+    default_interval: u64,
+    unit: Duration,
+    make_job: impl Fn() -> Job + Send + 'static,
+) {
+    let interval = std::env::var(interval_var)
+        .ok()
+        .map(|interval| {
+            interval
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number", interval_var))
+        })
+        .unwrap_or(default_interval);
+    if interval == 0 {
+        return;
+    }
+    let queue = Queue::connect(nats_client.clone())
+        .await
+        .expect("Failed to create scheduling queue");
+    let every = Duration::from_secs(unit.as_secs() * interval);
+    tracing::info!(
+        "Scheduling {} every {} seconds",
+        make_job().get_subject(),
+        every.as_secs()
+    );
+    tokio::spawn(schedule_job(queue, every, make_job));
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::registry()
@@ -20,9 +56,54 @@ async fn main() -> Result<()> {
     tracing::debug!("Connecting to NATS server");
     let nats_client = create_nats_client().await?;
     tracing::debug!("Connecting to queue");
-    let queue = Queue::connect(nats_client)
+    let queue = Queue::connect(nats_client.clone())
         .await
         .expect("Failed to create worker queue");
+    // Periodically queue storage garbage collection, GC_INTERVAL_HOURS=0 turns it off
+    let gc_dry_run = std::env::var("GC_DRY_RUN")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    spawn_scheduled(
+        &nats_client,
+        "GC_INTERVAL_HOURS",
+        24,
+        Duration::from_secs(60 * 60),
+        move || {
+            Job::from(GarbageCollectPayload {
+                dry_run: gc_dry_run,
+                ..Default::default()
+            })
+        },
+    )
+    .await;
+    // Periodically move unused raw sources to cold storage, TIERING_INTERVAL_HOURS=0 turns it off
+    spawn_scheduled(
+        &nats_client,
+        "TIERING_INTERVAL_HOURS",
+        24,
+        Duration::from_secs(60 * 60),
+        || Job::from(TierRawSourcesPayload::default()),
+    )
+    .await;
+    // Periodically roll up view sessions into daily stats, ANALYTICS_INTERVAL_MINUTES=0 turns it off
+    spawn_scheduled(
+        &nats_client,
+        "ANALYTICS_INTERVAL_MINUTES",
+        60,
+        Duration::from_secs(60),
+        || Job::from(AggregateViewStatsPayload::default()),
+    )
+    .await;
+    // Create the dependencies shared between runners
+    tracing::debug!("Creating runner context");
+    let runner_queue = Queue::connect(nats_client.clone())
+        .await
+        .expect("Failed to create runner queue");
+    let context = Arc::new(
+        RunnerContext::from_env()
+            .await?
+            .with_job_queue(runner_queue),
+    );
 
     // Get all jobs from the stream
     let subject = format!("{}.jobs.>", MESSAGE_PREFIX); // All farmhand jobs
@@ -35,6 +116,12 @@ async fn main() -> Result<()> {
     let consumer = queue.create_consumer(Some(runner_name), subject).await?;
     // Start consuming jobs
     loop {
+        // Leave jobs for other runners while this one is short on disk space
+        if let Err(e) = context.work_dir.ensure_free_space(0) {
+            tracing::warn!("Not accepting jobs: {}", e);
+            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+            continue;
+        }
         // TODO: Make this max_messages dynamic
         let mut jobs = consumer.fetch().max_messages(3).messages().await?;
         // Start processing jobs
@@ -46,8 +133,9 @@ async fn main() -> Result<()> {
                 continue;
             };
             // Process the message itself, ack on success, nack on failure
+            let context = context.clone();
             let handle = tokio::spawn(async move {
-                match process_message(&job.message).await {
+                match process_message(&job.message, context).await {
                     Ok(_) => job.ack().await.expect("Failed to ack job"),
                     Err(err) => {
                         tracing::error!("Failed to process job: {}", err);
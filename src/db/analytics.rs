@@ -0,0 +1,284 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{types::Uuid, PgPool};
+
+/// How many equal slices of a video retention is tracked in
+pub const RETENTION_BUCKETS: usize = 20;
+
+/// A viewer watching a video, kept up to date by heartbeats from the player
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ViewSession {
+    pub id: Uuid,
+    pub video_id: String,
+    pub viewer_id: Option<Uuid>,
+    /// The user when signed in, the players session otherwise
+    pub viewer_key: String,
+    pub started_at: DateTime<Utc>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    pub watched_seconds: f64,
+    pub last_position_seconds: f64,
+    /// Which slices of the video were watched
+    pub retention_buckets: Vec<i16>,
+    /// Hash of the address a signed out viewer watched from
+    pub client_key: Option<String>,
+}
+
+/// Views and watch time of a video on a single day
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct VideoDailyStats {
+    pub video_id: String,
+    pub day: NaiveDate,
+    pub views: i64,
+    /// Viewers are counted once per day, so these don't add up across days
+    pub unique_viewers: i64,
+    pub watched_seconds: f64,
+    /// How many views reached each slice of the video
+    pub retention_counts: Vec<i64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The first heartbeat of a viewer, starting a session
+pub struct NewViewSession<'a> {
+    pub id: Uuid,
+    pub video_id: &'a str,
+    pub viewer_id: Option<Uuid>,
+    pub viewer_key: &'a str,
+    pub client_key: Option<&'a str>,
+    pub at: DateTime<Utc>,
+    pub position_seconds: f64,
+    pub buckets: Vec<i16>,
+}
+
+/// Gets the slices of a video between two positions, or none if its length isn't known yet
+pub fn retention_buckets(from: f64, to: f64, duration: Option<f64>) -> Vec<i16> {
+    let Some(duration) = duration.filter(|duration| *duration > 0.0) else {
+        return Vec::new();
+    };
+    let bucket = |position: f64| {
+        ((position / duration * RETENTION_BUCKETS as f64) as i64)
+            .clamp(0, RETENTION_BUCKETS as i64 - 1) as i16
+    };
+    (bucket(from.min(to))..=bucket(from.max(to))).collect()
+}
+
+impl ViewSession {
+    pub async fn by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ViewSession>("SELECT * FROM view_sessions WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+    /// Finds the latest session of a viewer on a video, if they've sent a heartbeat since `since`
+    pub async fn latest_since(
+        pool: &PgPool,
+        video_id: &str,
+        viewer_key: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ViewSession>(
+            r#"
+            SELECT * FROM view_sessions
+            WHERE video_id = $1 AND viewer_key = $2 AND last_heartbeat_at >= $3
+            ORDER BY last_heartbeat_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(video_id)
+        .bind(viewer_key)
+        .bind(since)
+        .fetch_optional(pool)
+        .await
+    }
+    /// Counts the sessions a viewer has started on any video since `since`
+    pub async fn count_started_since(
+        pool: &PgPool,
+        viewer_key: &str,
+        since: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM view_sessions WHERE viewer_key = $1 AND started_at >= $2",
+        )
+        .bind(viewer_key)
+        .bind(since)
+        .fetch_one(pool)
+        .await
+    }
+    /// Counts the sessions started from an address on any video since `since`
+    pub async fn count_started_by_client_since(
+        pool: &PgPool,
+        client_key: &str,
+        since: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM view_sessions WHERE client_key = $1 AND started_at >= $2",
+        )
+        .bind(client_key)
+        .bind(since)
+        .fetch_one(pool)
+        .await
+    }
+    /// Starts a session from the first heartbeat of a viewer
+    pub async fn create(pool: &PgPool, session: NewViewSession<'_>) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, ViewSession>(
+            r#"
+            INSERT INTO view_sessions (
+                id, video_id, viewer_id, viewer_key, client_key, started_at, last_heartbeat_at,
+                last_position_seconds, retention_buckets
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $6, $7, $8)
+            RETURNING *
+            "#,
+        )
+        .bind(session.id)
+        .bind(session.video_id)
+        .bind(session.viewer_id)
+        .bind(session.viewer_key)
+        .bind(session.client_key)
+        .bind(session.at)
+        .bind(session.position_seconds)
+        .bind(session.buckets)
+        .fetch_one(pool)
+        .await
+    }
+    /// Adds a heartbeat to the session, crediting it with watch time and the slices it reached
+    /// Returns None if another heartbeat was recorded since `previous_heartbeat_at` was read
+    pub async fn record_heartbeat(
+        pool: &PgPool,
+        id: Uuid,
+        previous_heartbeat_at: DateTime<Utc>,
+        at: DateTime<Utc>,
+        watched_seconds: f64,
+        position_seconds: f64,
+        buckets: &[i16],
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ViewSession>(
+            r#"
+            UPDATE view_sessions
+            SET last_heartbeat_at = $3,
+                watched_seconds = watched_seconds + $4,
+                last_position_seconds = $5,
+                retention_buckets = ARRAY(
+                    SELECT DISTINCT bucket
+                    FROM unnest(retention_buckets || $6::SMALLINT[]) AS bucket
+                    ORDER BY bucket
+                )
+            WHERE id = $1 AND last_heartbeat_at = $2
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(previous_heartbeat_at)
+        .bind(at)
+        .bind(watched_seconds)
+        .bind(position_seconds)
+        .bind(buckets)
+        .fetch_optional(pool)
+        .await
+    }
+    /// Deletes sessions started before `before`, returning how many were deleted
+    /// Their days should already be rolled up, since they can't be counted again afterwards
+    pub async fn delete_started_before(
+        pool: &PgPool,
+        before: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM view_sessions WHERE started_at < $1")
+            .bind(before)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+impl VideoDailyStats {
+    /// Rolls up the sessions started since `since` into daily stats, replacing what those days had
+    /// Sessions only count as views once they've watched `view_seconds`, or half of shorter videos
+    /// Returns how many video days were updated
+    pub async fn aggregate_since(
+        pool: &PgPool,
+        since: NaiveDate,
+        view_seconds: f64,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            WITH counted AS (
+                SELECT
+                    s.video_id,
+                    (s.started_at AT TIME ZONE 'UTC')::DATE AS day,
+                    s.viewer_key,
+                    s.watched_seconds,
+                    s.retention_buckets
+                FROM view_sessions s
+                JOIN videos v ON v.id = s.video_id
+                WHERE s.started_at >= ($1::DATE)::TIMESTAMP AT TIME ZONE 'UTC'
+                AND s.watched_seconds >= LEAST($2, COALESCE(v.duration_seconds / 2, $2))
+            ),
+            totals AS (
+                SELECT
+                    video_id,
+                    day,
+                    COUNT(*) AS views,
+                    COUNT(DISTINCT viewer_key) AS unique_viewers,
+                    SUM(watched_seconds) AS watched_seconds
+                FROM counted
+                GROUP BY video_id, day
+            ),
+            reached AS (
+                SELECT video_id, day, bucket, COUNT(*) AS views
+                FROM counted, unnest(retention_buckets) AS bucket
+                GROUP BY video_id, day, bucket
+            ),
+            retention AS (
+                SELECT
+                    t.video_id,
+                    t.day,
+                    array_agg(COALESCE(r.views, 0) ORDER BY b.bucket) AS retention_counts
+                FROM totals t
+                CROSS JOIN generate_series(0, $3 - 1) AS b(bucket)
+                LEFT JOIN reached r
+                    ON r.video_id = t.video_id AND r.day = t.day AND r.bucket = b.bucket
+                GROUP BY t.video_id, t.day
+            )
+            INSERT INTO video_daily_stats (
+                video_id, day, views, unique_viewers, watched_seconds, retention_counts, updated_at
+            )
+            SELECT
+                t.video_id, t.day, t.views, t.unique_viewers, t.watched_seconds,
+                r.retention_counts, NOW()
+            FROM totals t
+            JOIN retention r ON r.video_id = t.video_id AND r.day = t.day
+            ON CONFLICT (video_id, day) DO UPDATE SET
+                views = EXCLUDED.views,
+                unique_viewers = EXCLUDED.unique_viewers,
+                watched_seconds = EXCLUDED.watched_seconds,
+                retention_counts = EXCLUDED.retention_counts,
+                updated_at = EXCLUDED.updated_at
+            "#,
+        )
+        .bind(since)
+        .bind(view_seconds)
+        .bind(RETENTION_BUCKETS as i32)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+    /// Finds the daily stats of a video between two days, both included, oldest first
+    pub async fn by_video_id(
+        pool: &PgPool,
+        video_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, VideoDailyStats>(
+            r#"
+            SELECT * FROM video_daily_stats
+            WHERE video_id = $1 AND day BETWEEN $2 AND $3
+            ORDER BY day ASC
+            "#,
+        )
+        .bind(video_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await
+    }
+}
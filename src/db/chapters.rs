@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, PgPool};
+
+use crate::vod::chapters::Chapter;
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[sqlx(type_name = "chapter_source", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ChapterSource {
+    Stream,
+    Scene,
+    Manual,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct VideoChapter {
+    pub id: Uuid,
+    pub video_id: String,
+    pub position: i32,
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+    pub title: String,
+    pub source: ChapterSource,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A chapter to be saved for a video
+pub struct NewChapter {
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+    pub title: String,
+    pub source: ChapterSource,
+    pub accepted: bool,
+}
+
+impl From<&VideoChapter> for Chapter {
+    fn from(chapter: &VideoChapter) -> Self {
+        Chapter {
+            start: chapter.start_seconds,
+            end: chapter.end_seconds,
+            title: chapter.title.clone(),
+        }
+    }
+}
+
+impl VideoChapter {
+    /// Finds all chapters for a video, including unaccepted proposals
+    pub async fn by_video_id(video_id: &str, pool: &PgPool) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, VideoChapter>(
+            "SELECT * FROM video_chapters WHERE video_id = $1 ORDER BY position ASC",
+        )
+        .bind(video_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Finds the accepted chapters for a video
+    pub async fn accepted_by_video_id(
+        video_id: &str,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, VideoChapter>(
+            "SELECT * FROM video_chapters
+            WHERE video_id = $1 AND accepted_at IS NOT NULL
+            ORDER BY position ASC",
+        )
+        .bind(video_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Replaces every chapter of a video with the given chapters, in order
+    pub async fn replace_all(
+        video_id: &str,
+        chapters: &[NewChapter],
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM video_chapters WHERE video_id = $1")
+            .bind(video_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let now = Utc::now();
+        let mut saved = Vec::with_capacity(chapters.len());
+        for (position, chapter) in chapters.iter().enumerate() {
+            let row = sqlx::query_as::<_, VideoChapter>(
+                "INSERT INTO video_chapters (
+                    video_id, position, start_seconds, end_seconds, title, source, accepted_at
+                ) VALUES ($1, $2, $3, $4, $5, $6, CASE WHEN $7 THEN $8 ELSE NULL END)
+                RETURNING *",
+            )
+            .bind(video_id)
+            .bind(position as i32)
+            .bind(chapter.start_seconds)
+            .bind(chapter.end_seconds)
+            .bind(&chapter.title)
+            .bind(chapter.source)
+            .bind(chapter.accepted)
+            .bind(now)
+            .fetch_one(&mut *tx)
+            .await?;
+            saved.push(row);
+        }
+
+        tx.commit().await?;
+        Ok(saved)
+    }
+}
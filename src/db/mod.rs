@@ -1,10 +1,19 @@
 pub mod accounts;
+pub mod analytics;
+pub mod chapters;
+pub mod playlists;
+pub mod quotas;
+pub mod search;
+pub mod stream_events;
 pub mod streams;
 pub mod users;
 pub mod videos;
 
 pub use users::User;
-pub use videos::{ProcessingStatus, Video};
+pub use videos::{
+    CompressionStatus, PrivacyStatus, ProcessingError, ProcessingStatus, StorageTier, Video,
+    VideoCursor, VideoFilter, VideoSort,
+};
 
 use sqlx::{postgres::PgPool, Pool, Postgres};
 
@@ -0,0 +1,312 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, PgPool, Postgres, Transaction};
+
+use super::{PrivacyStatus, User, Video};
+use crate::prelude::get_storage_dir;
+
+/// Directory under the storage root that playlist files are kept in, apart from the videos
+pub const PLAYLIST_STORAGE_DIR: &str = "playlists";
+
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct Playlist {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub title: String,
+    pub description: Option<String>,
+    pub privacy_status: PrivacyStatus,
+    /// Storage key of the uploaded cover image
+    pub cover_image_path: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A playlist in a listing, with how many of its videos the viewer can see
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct PlaylistSummary {
+    #[sqlx(flatten)]
+    pub playlist: Playlist,
+    pub item_count: i64,
+}
+
+impl Playlist {
+    /// Gets the prefix the playlists files, like its cover image, are stored under
+    pub fn storage_prefix(&self) -> String {
+        format!("{}/{}/{}", get_storage_dir(), PLAYLIST_STORAGE_DIR, self.id)
+    }
+    /// Whether a user, or a signed out visitor, is allowed to see the playlist
+    pub fn is_viewable_by(&self, user: Option<&User>) -> bool {
+        match self.privacy_status {
+            PrivacyStatus::Public | PrivacyStatus::Unlisted => true,
+            PrivacyStatus::Private => user.is_some_and(|user| user.id == self.user_id),
+        }
+    }
+    pub async fn by_id(pool: &PgPool, id: Uuid) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Playlist>("SELECT * FROM playlists WHERE id = $1")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+    }
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        title: &str,
+        description: Option<&str>,
+        privacy_status: PrivacyStatus,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Playlist>(
+            r#"
+            INSERT INTO playlists (user_id, title, description, privacy_status)
+            VALUES ($1, $2, NULLIF($3, ''), $4)
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(title)
+        .bind(description)
+        .bind(privacy_status)
+        .fetch_one(pool)
+        .await
+    }
+    /// Updates the title, description and privacy of a playlist, keeping whatever is None
+    /// An empty description clears it
+    pub async fn update_metadata(
+        pool: &PgPool,
+        id: Uuid,
+        title: Option<&str>,
+        description: Option<&str>,
+        privacy_status: Option<PrivacyStatus>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Playlist>(
+            r#"
+            UPDATE playlists
+            SET title = COALESCE($1, title),
+                description = CASE WHEN $2::TEXT IS NULL THEN description ELSE NULLIF($2, '') END,
+                privacy_status = COALESCE($3, privacy_status)
+            WHERE id = $4
+            RETURNING *
+            "#,
+        )
+        .bind(title)
+        .bind(description)
+        .bind(privacy_status)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
+    pub async fn set_cover_image_path(
+        pool: &PgPool,
+        id: Uuid,
+        path: Option<&str>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Playlist>(
+            "UPDATE playlists SET cover_image_path = $1 WHERE id = $2 RETURNING *",
+        )
+        .bind(path)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
+    pub async fn delete(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM playlists WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+    /// Lists the playlists a viewer can see listed, most recently changed first
+    /// Unlisted and private playlists are only listed for their owner
+    pub async fn listed(
+        pool: &PgPool,
+        owner: Option<&str>,
+        viewer_id: Option<Uuid>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<PlaylistSummary>, sqlx::Error> {
+        sqlx::query_as::<_, PlaylistSummary>(
+            r#"
+            SELECT
+                p.*,
+                (
+                    SELECT COUNT(*)
+                    FROM playlist_items i
+                    JOIN videos v ON v.id = i.video_id
+                    WHERE i.playlist_id = p.id
+                    AND (v.privacy_status != 'private' OR v.user_id = $2)
+                ) AS item_count
+            FROM playlists p
+            JOIN users u ON u.id = p.user_id
+            WHERE ($1::TEXT IS NULL OR u.username = $1)
+            AND (p.privacy_status = 'public' OR p.user_id = $2)
+            ORDER BY p.updated_at DESC, p.id
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(owner)
+        .bind(viewer_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+    }
+    /// Gets every video in the playlist in order, whoever can see them
+    pub async fn videos(pool: &PgPool, id: Uuid) -> Result<Vec<Video>, sqlx::Error> {
+        sqlx::query_as::<_, Video>(
+            r#"
+            SELECT v.*
+            FROM playlist_items i
+            JOIN videos v ON v.id = i.video_id
+            WHERE i.playlist_id = $1
+            ORDER BY i.position ASC
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await
+    }
+    pub async fn item_count(pool: &PgPool, id: Uuid) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM playlist_items WHERE playlist_id = $1")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+    }
+    /// Locks the playlist so its items can be moved around, marking it as changed
+    /// Gaps left behind by deleted videos are closed, so positions always count up from 0
+    async fn lock(tx: &mut Transaction<'_, Postgres>, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE playlists SET updated_at = NOW() WHERE id = $1")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE playlist_items i
+            SET position = o.position
+            FROM (
+                SELECT video_id, ROW_NUMBER() OVER (ORDER BY position, added_at) - 1 AS position
+                FROM playlist_items
+                WHERE playlist_id = $1
+            ) o
+            WHERE i.playlist_id = $1 AND i.video_id = o.video_id AND i.position != o.position
+            "#,
+        )
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+        Ok(())
+    }
+    /// Adds a video at a position, moving everything from there on back, or at the end without one
+    /// Returns false if the video is already in the playlist
+    pub async fn add_video(
+        pool: &PgPool,
+        id: Uuid,
+        video_id: &str,
+        position: Option<i32>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        Self::lock(&mut tx, id).await?;
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM playlist_items WHERE playlist_id = $1")
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await?;
+        let position = position.map_or(count, |position| (position.max(0) as i64).min(count));
+        sqlx::query(
+            r#"
+            UPDATE playlist_items SET position = position + 1
+            WHERE playlist_id = $1 AND position >= $2
+            "#,
+        )
+        .bind(id)
+        .bind(position as i32)
+        .execute(&mut *tx)
+        .await?;
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO playlist_items (playlist_id, video_id, position)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (playlist_id, video_id) DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(video_id)
+        .bind(position as i32)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        // Leave the positions alone if the video was already there
+        match inserted {
+            true => tx.commit().await?,
+            false => tx.rollback().await?,
+        }
+        Ok(inserted)
+    }
+    /// Removes a video, moving everything after it forward
+    /// Returns false if the video wasn't in the playlist
+    pub async fn remove_video(
+        pool: &PgPool,
+        id: Uuid,
+        video_id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        Self::lock(&mut tx, id).await?;
+        let removed: Option<i32> = sqlx::query_scalar(
+            "DELETE FROM playlist_items WHERE playlist_id = $1 AND video_id = $2 RETURNING position",
+        )
+        .bind(id)
+        .bind(video_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(position) = removed else {
+            return Ok(false);
+        };
+        sqlx::query(
+            r#"
+            UPDATE playlist_items SET position = position - 1
+            WHERE playlist_id = $1 AND position > $2
+            "#,
+        )
+        .bind(id)
+        .bind(position)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+    /// Puts the videos of a playlist in the given order
+    /// Returns false, changing nothing, unless every video in the playlist is given exactly once
+    pub async fn reorder(
+        pool: &PgPool,
+        id: Uuid,
+        video_ids: &[String],
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        Self::lock(&mut tx, id).await?;
+        let mut current: Vec<String> =
+            sqlx::query_scalar("SELECT video_id FROM playlist_items WHERE playlist_id = $1")
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await?;
+        let mut requested = video_ids.to_vec();
+        current.sort();
+        requested.sort();
+        if current != requested {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+        sqlx::query(
+            r#"
+            UPDATE playlist_items i
+            SET position = o.ordinality - 1
+            FROM unnest($2::TEXT[]) WITH ORDINALITY AS o(video_id, ordinality)
+            WHERE i.playlist_id = $1 AND i.video_id = o.video_id
+            "#,
+        )
+        .bind(id)
+        .bind(video_ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
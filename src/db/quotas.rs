@@ -0,0 +1,189 @@
+use serde::Serialize;
+use sqlx::{types::Uuid, PgExecutor, PgPool, Postgres, Transaction};
+
+/// What a single video takes up in storage
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct VideoStorageUsage {
+    pub video_id: String,
+    pub title: String,
+    pub raw_bytes: i64,
+    pub processed_bytes: i64,
+    /// Space held for a raw upload that hasn't completed yet
+    pub reserved_bytes: i64,
+}
+
+impl VideoStorageUsage {
+    pub fn total_bytes(&self) -> i64 {
+        self.raw_bytes + self.processed_bytes + self.reserved_bytes
+    }
+}
+
+/// Everything a user has in storage, video by video
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct StorageUsage {
+    pub videos: Vec<VideoStorageUsage>,
+}
+
+impl StorageUsage {
+    /// Gets the storage usage of every video a user owns
+    /// Videos sharing their storage with others are only charged for it once, on the original
+    /// while it's still around and on the oldest copy after that
+    pub async fn by_user_id(user_id: Uuid, pool: impl PgExecutor<'_>) -> Result<Self, sqlx::Error> {
+        let videos = sqlx::query_as::<_, VideoStorageUsage>(
+            r#"
+            SELECT id AS video_id,
+                title,
+                CASE WHEN charged THEN COALESCE(raw_size_bytes, 0) ELSE 0 END AS raw_bytes,
+                CASE WHEN charged THEN COALESCE(processed_size_bytes, 0) ELSE 0 END
+                    AS processed_bytes,
+                CASE
+                    WHEN raw_size_bytes IS NULL AND upload_id IS NOT NULL
+                    THEN COALESCE(declared_size_bytes, 0)
+                    ELSE 0
+                END AS reserved_bytes
+            FROM (
+                SELECT *,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY COALESCE(source_video_id, id)
+                        ORDER BY source_video_id IS NULL DESC, created_at, id
+                    ) = 1 AS charged
+                FROM videos
+                WHERE user_id = $1
+            ) v
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(StorageUsage { videos })
+    }
+    pub fn raw_bytes(&self) -> i64 {
+        self.videos.iter().map(|video| video.raw_bytes).sum()
+    }
+    pub fn processed_bytes(&self) -> i64 {
+        self.videos.iter().map(|video| video.processed_bytes).sum()
+    }
+    pub fn reserved_bytes(&self) -> i64 {
+        self.videos.iter().map(|video| video.reserved_bytes).sum()
+    }
+    pub fn total_bytes(&self) -> i64 {
+        self.videos.iter().map(VideoStorageUsage::total_bytes).sum()
+    }
+}
+
+/// Gets the storage quota of a user in bytes, their own quota wins over the one of their role
+/// None means the user can store as much as they like
+pub async fn storage_quota(
+    user_id: Uuid,
+    pool: impl PgExecutor<'_>,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<i64>>(
+        r#"
+        SELECT COALESCE(u.storage_quota_bytes, q.quota_bytes)
+        FROM users u
+        LEFT JOIN role_storage_quotas q ON q.role = u.role
+        WHERE u.id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Checks whether storing more bytes keeps a user within their quota
+pub fn fits_quota(quota: Option<i64>, used: i64, additional: i64) -> bool {
+    quota.is_none_or(|quota| used.saturating_add(additional) <= quota)
+}
+
+/// Locks a user until the transaction ends, then gets their quota along with what everything
+/// but one of their videos uses
+/// Holding the lock keeps uploads of the same user from being fit into the same space at once
+async fn lock_usage(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    video_id: &str,
+) -> Result<(Option<i64>, i64), sqlx::Error> {
+    sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await?;
+    let quota = storage_quota(user_id, &mut **tx).await?;
+    let used = StorageUsage::by_user_id(user_id, &mut **tx)
+        .await?
+        .videos
+        .iter()
+        .filter(|usage| usage.video_id != video_id)
+        .map(VideoStorageUsage::total_bytes)
+        .sum();
+    Ok((quota, used))
+}
+
+/// Starts holding space for the upload of a video against its owners quota
+/// Returns false, holding nothing, if the declared size doesn't fit in what's left
+pub async fn reserve_upload(
+    pool: &PgPool,
+    user_id: Uuid,
+    video_id: &str,
+    upload_id: &str,
+    declared_size_bytes: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let (quota, used) = lock_usage(&mut tx, user_id, video_id).await?;
+    if !fits_quota(quota, used, declared_size_bytes) {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    sqlx::query(
+        r#"
+            UPDATE videos
+            SET upload_id = $1,
+                declared_size_bytes = $2,
+                updated_at = NOW()
+            WHERE id = $3
+        "#,
+    )
+    .bind(upload_id)
+    .bind(declared_size_bytes)
+    .bind(video_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Replaces the space held for a finished upload with the size it actually came out as
+/// Returns false, changing nothing, if it's bigger than declared and doesn't fit in what's left
+pub async fn record_raw_size(
+    pool: &PgPool,
+    user_id: Uuid,
+    video_id: &str,
+    raw_size_bytes: i64,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let (quota, used) = lock_usage(&mut tx, user_id, video_id).await?;
+    let declared: Option<i64> =
+        sqlx::query_scalar("SELECT declared_size_bytes FROM videos WHERE id = $1")
+            .bind(video_id)
+            .fetch_one(&mut *tx)
+            .await?;
+    if raw_size_bytes > declared.unwrap_or_default() && !fits_quota(quota, used, raw_size_bytes) {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    sqlx::query(
+        r#"
+            UPDATE videos
+            SET raw_size_bytes = $1,
+                upload_id = NULL,
+                updated_at = NOW()
+            WHERE id = $2
+        "#,
+    )
+    .bind(raw_size_bytes)
+    .bind(video_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
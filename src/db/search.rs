@@ -0,0 +1,141 @@
+use serde::Serialize;
+use sqlx::{types::Uuid, PgPool};
+
+use super::PrivacyStatus;
+
+/// Marks the start and end of matches in highlights, replaced once the text has been escaped
+pub const HIGHLIGHT_START: char = '\u{2}';
+pub const HIGHLIGHT_STOP: char = '\u{3}';
+
+/// A video matching a search, with the parts that matched highlighted
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct VideoSearchHit {
+    pub id: String,
+    pub title: String,
+    /// User name of the owner
+    pub username: String,
+    pub privacy_status: PrivacyStatus,
+    pub duration_seconds: Option<f64>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub title_highlight: String,
+    /// The parts of the tags and description that matched, if the video has either
+    pub snippet: Option<String>,
+    pub rank: f32,
+}
+
+/// A channel matching a search
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ChannelSearchHit {
+    pub username: String,
+    pub username_highlight: String,
+    /// How many public videos the channel has
+    pub video_count: i64,
+    pub rank: f32,
+}
+
+/// Options passed to ts_headline, with the markers that are turned into highlights
+fn headline_options(fragments: bool) -> String {
+    let markers = format!("StartSel={}, StopSel={}", HIGHLIGHT_START, HIGHLIGHT_STOP);
+    match fragments {
+        true => format!("{}, MaxFragments=2, MaxWords=20, MinWords=5", markers),
+        false => format!("{}, HighlightAll=true", markers),
+    }
+}
+
+/// Searches the titles, tags and descriptions of the videos a viewer can see listed, best match first
+/// The query is in the same syntax web search engines use, like `"exact phrase" -excluded`
+pub async fn search_videos(
+    pool: &PgPool,
+    query: &str,
+    viewer_id: Option<Uuid>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<VideoSearchHit>, sqlx::Error> {
+    sqlx::query_as::<_, VideoSearchHit>(
+        r#"
+        WITH q AS (SELECT websearch_to_tsquery('english', $1) AS query)
+        SELECT
+            v.id,
+            v.title,
+            u.username,
+            v.privacy_status,
+            v.duration_seconds,
+            v.created_at,
+            ts_headline('english', v.title, q.query, $3) AS title_highlight,
+            CASE
+                WHEN v.description IS NULL AND cardinality(v.tags) = 0 THEN NULL
+                ELSE ts_headline(
+                    'english',
+                    concat_ws(' ', array_to_string(v.tags, ' '), v.description),
+                    q.query,
+                    $4
+                )
+            END AS snippet,
+            ts_rank(v.search_vector, q.query) AS rank
+        FROM videos v
+        JOIN users u ON u.id = v.user_id
+        CROSS JOIN q
+        WHERE v.search_vector @@ q.query
+        AND (v.privacy_status = 'public' OR v.user_id = $2)
+        ORDER BY rank DESC, v.created_at DESC, v.id
+        LIMIT $5 OFFSET $6
+        "#,
+    )
+    .bind(query)
+    .bind(viewer_id)
+    .bind(headline_options(false))
+    .bind(headline_options(true))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+}
+
+/// Turns a search into a query matching the start of each word, since user names are typed
+/// partially far more often than in full
+/// Returns None if the search has no words in it
+pub fn channel_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("{}:*", word.to_lowercase()))
+        .collect();
+    (!words.is_empty()).then(|| words.join(" & "))
+}
+
+/// Searches channels by user name, best match first
+pub async fn search_channels(
+    pool: &PgPool,
+    query: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<ChannelSearchHit>, sqlx::Error> {
+    let Some(query) = channel_query(query) else {
+        return Ok(Vec::new());
+    };
+    sqlx::query_as::<_, ChannelSearchHit>(
+        r#"
+        WITH q AS (SELECT to_tsquery('simple', $1) AS query)
+        SELECT
+            u.username,
+            ts_headline('simple', u.username, q.query, $2) AS username_highlight,
+            (
+                SELECT COUNT(*)
+                FROM videos v
+                WHERE v.user_id = u.id AND v.privacy_status = 'public'
+            ) AS video_count,
+            ts_rank(to_tsvector('simple', u.username), q.query) AS rank
+        FROM users u
+        CROSS JOIN q
+        WHERE to_tsvector('simple', u.username) @@ q.query
+        ORDER BY rank DESC, u.username
+        LIMIT $3 OFFSET $4
+        "#,
+    )
+    .bind(query)
+    .bind(headline_options(false))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+}
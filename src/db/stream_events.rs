@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, PgPool};
+
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
+pub struct StreamEvent {
+    pub id: Uuid,
+    pub stream_id: Uuid,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub occurred_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl StreamEvent {
+    /// Records an event that happened during a stream
+    pub async fn create(
+        stream_id: Uuid,
+        event_type: &str,
+        payload: serde_json::Value,
+        occurred_at: DateTime<Utc>,
+        pool: &PgPool,
+    ) -> Result<StreamEvent, sqlx::Error> {
+        sqlx::query_as::<_, StreamEvent>(
+            "INSERT INTO stream_events (
+                id, stream_id, event_type, payload, occurred_at
+            ) VALUES ($1, $2, $3, $4, $5)
+            RETURNING *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(stream_id)
+        .bind(event_type)
+        .bind(payload)
+        .bind(occurred_at)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Finds all events for a stream in the order they happened
+    pub async fn find_by_stream_id(
+        stream_id: Uuid,
+        pool: &PgPool,
+    ) -> Result<Vec<StreamEvent>, sqlx::Error> {
+        sqlx::query_as::<_, StreamEvent>(
+            "SELECT * FROM stream_events WHERE stream_id = $1 ORDER BY occurred_at ASC",
+        )
+        .bind(stream_id)
+        .fetch_all(pool)
+        .await
+    }
+}
@@ -1,21 +1,55 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, PgPool};
+use sqlx::{prelude::FromRow, PgPool, Postgres, QueryBuilder, Transaction};
 use uuid::Uuid;
 
+use crate::{db::User, prelude::get_storage_dir};
+
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct Video {
     pub id: String,
     pub user_id: Uuid,
     pub title: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
     pub raw_video_path: String,
     pub processed_video_path: Option<String>,
+    pub privacy_status: PrivacyStatus,
     pub processing_status: ProcessingStatus,
+    pub compression_status: CompressionStatus,
+    pub processing_error: Option<ProcessingError>,
+    pub processing_error_message: Option<String>,
+    pub stream_id: Option<Uuid>,
+    pub chapters_path: Option<String>,
+    pub preview_webp_path: Option<String>,
+    pub preview_mp4_path: Option<String>,
+    pub waveform_path: Option<String>,
+    /// The multipart upload of the raw video, until it completes
+    pub upload_id: Option<String>,
+    /// The size the uploader said the raw video would be, reserved against their quota
+    pub declared_size_bytes: Option<i64>,
+    pub raw_size_bytes: Option<i64>,
+    pub processed_size_bytes: Option<i64>,
+    /// Length of the video, known once it's processed
+    pub duration_seconds: Option<f64>,
+    /// SHA-256 of the raw source, hex encoded
+    pub content_sha256: Option<String>,
+    /// The video whose raw source and renditions this one reuses instead of storing its own
+    pub source_video_id: Option<String>,
+    /// Whether the raw source is in hot or cold storage
+    pub raw_storage_tier: StorageTier,
+    /// Where the raw source is kept in cold storage, only set while it's there
+    pub raw_cold_path: Option<String>,
+    /// When a job last needed the raw source, old sources are moved to cold storage
+    pub raw_accessed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The URL the raw source is imported from, for videos fetched from another host
+    pub import_url: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone)]
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, PartialEq)]
 #[sqlx(type_name = "processing_status", rename_all = "lowercase")]
 pub enum ProcessingStatus {
     Pending,
@@ -24,11 +58,138 @@ pub enum ProcessingStatus {
     Failed,
 }
 
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, PartialEq)]
+#[sqlx(type_name = "compression_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+/// Progress of the legacy compression job
+pub enum CompressionStatus {
+    Pending,
+    Compressing,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq)]
+#[sqlx(type_name = "privacy_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+/// Who is allowed to watch a video
+pub enum PrivacyStatus {
+    Private,
+    Public,
+    /// Anyone with the link can watch, but it isn't listed
+    Unlisted,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq)]
+#[sqlx(type_name = "storage_tier", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+/// Where a raw source is kept, cold storage is cheaper but has to be restored before use
+pub enum StorageTier {
+    Hot,
+    Cold,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq)]
+#[sqlx(type_name = "processing_error", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+/// Classifies why processing a video failed, so creators know whether to re-upload
+pub enum ProcessingError {
+    UnsupportedFormat,
+    UnreadableSource,
+    NoVideoStream,
+    InvalidDimensions,
+    InvalidDuration,
+    CorruptSource,
+    EncodingFailed,
+    StorageFailed,
+    ImportFailed,
+    Internal,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// Orders video listings can be sorted in
+pub enum VideoSort {
+    #[default]
+    Newest,
+    Oldest,
+    /// Alphabetical
+    Title,
+    /// Longest first
+    Duration,
+}
+
+/// Narrows down which videos are listed, the viewer decides which private videos can be seen
+#[derive(Debug, Clone, Default)]
+pub struct VideoFilter {
+    pub viewer_id: Option<Uuid>,
+    /// User name of the owner
+    pub owner: Option<String>,
+    pub processing_status: Option<ProcessingStatus>,
+    pub privacy_status: Option<PrivacyStatus>,
+    pub created_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_before: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Where a page of videos picks up, the sort keys of the last video on the previous page
+/// It carries every key so it keeps working whichever order the pages are in
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VideoCursor {
+    pub id: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub title: String,
+    pub duration_seconds: Option<f64>,
+}
+
+impl VideoCursor {
+    /// Encodes the cursor into an opaque string that's safe to put in a URL
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+    /// Decodes a cursor from its string, None if it isn't one
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+impl From<&Video> for VideoCursor {
+    fn from(video: &Video) -> Self {
+        VideoCursor {
+            id: video.id.clone(),
+            created_at: video.created_at,
+            title: video.title.clone(),
+            duration_seconds: video.duration_seconds,
+        }
+    }
+}
+
 impl Video {
     /// A function for generating a video id
     pub fn gen_id() -> String {
         nanoid!(10)
     }
+    /// Gets the ID of the video that owns the raw source and renditions this one plays
+    pub fn storage_id(&self) -> &str {
+        self.source_video_id.as_deref().unwrap_or(&self.id)
+    }
+    /// Gets the prefix the videos raw source and renditions are stored under in remote storage
+    /// Videos reusing another videos renditions share its prefix
+    pub fn storage_prefix(&self) -> String {
+        format!("{}/{}", get_storage_dir(), self.storage_id())
+    }
+    /// Gets the prefix of files that only ever belong to this video, like its chapters
+    /// This is the same as the storage prefix unless the video reuses another ones renditions
+    pub fn own_storage_prefix(&self) -> String {
+        format!("{}/{}", get_storage_dir(), self.id)
+    }
+    /// Checks whether the given user, if any, is allowed to watch the video
+    pub fn is_viewable_by(&self, user: Option<&User>) -> bool {
+        match self.privacy_status {
+            PrivacyStatus::Public | PrivacyStatus::Unlisted => true,
+            PrivacyStatus::Private => user.is_some_and(|user| user.id == self.user_id),
+        }
+    }
     /// A function for creating new video data in the db
     pub async fn create(
         pool: &PgPool,
@@ -36,20 +197,21 @@ impl Video {
         user_id: Uuid,
         title: String,
         raw_video_path: Option<String>,
+        stream_id: Option<Uuid>,
     ) -> Result<Self, sqlx::Error> {
         let video_id = video_id.unwrap_or(Self::gen_id());
         sqlx::query_as::<_, Video>(
             r#"
-            INSERT INTO videos (id, user_id, title, raw_video_path, processing_status)
-            VALUES ($1, $2, $3, $4, 'pending')
-            RETURNING id, user_id, title, raw_video_path, processed_video_path,
-                      processing_status, created_at, updated_at
+            INSERT INTO videos (id, user_id, title, raw_video_path, stream_id, processing_status)
+            VALUES ($1, $2, $3, $4, $5, 'pending')
+            RETURNING *
             "#,
         )
         .bind(video_id)
         .bind(user_id)
         .bind(title)
         .bind(raw_video_path)
+        .bind(stream_id)
         .fetch_one(pool)
         .await
     }
@@ -57,8 +219,7 @@ impl Video {
     pub async fn by_ids(pool: &PgPool, video_ids: &Vec<String>) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, Video>(
             r#"
-            SELECT id, user_id, title, raw_video_path, processed_video_path,
-                   processing_status, created_at, updated_at
+            SELECT *
             FROM videos
             WHERE id = ANY($1)
             "#,
@@ -67,64 +228,223 @@ impl Video {
         .fetch_all(pool)
         .await
     }
-    /// A function for fetching a single video from the db by video ID
-    pub async fn by_id(pool: &PgPool, video_id: &str) -> Result<Self, sqlx::Error> {
-        sqlx::query_as::<_, Video>(
+    /// A function for finding which of the given video IDs still exist in the db,
+    /// or still have their storage reused by another video
+    pub async fn existing_ids(
+        pool: &PgPool,
+        video_ids: &[String],
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, String>(
             r#"
-            SELECT id, user_id, title, raw_video_path, processed_video_path,
-                   processing_status, created_at, updated_at
+            SELECT id
             FROM videos
-            WHERE id = $1
+            WHERE id = ANY($1)
+            UNION
+            SELECT source_video_id
+            FROM videos
+            WHERE source_video_id = ANY($1)
             "#,
         )
-        .bind(video_id)
-        .fetch_one(pool)
+        .bind(video_ids)
+        .fetch_all(pool)
         .await
     }
-    /// A function for getting all user owned videos by user ID
-    pub async fn by_userid(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+    /// A function for finding which of the given storage IDs are still used by videos,
+    /// leaving out the videos that are about to be deleted
+    pub async fn referenced_storage_ids(
+        pool: &PgPool,
+        storage_ids: &[String],
+        excluding_video_ids: &[String],
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT DISTINCT COALESCE(source_video_id, id)
+            FROM videos
+            WHERE COALESCE(source_video_id, id) = ANY($1)
+            AND NOT (id = ANY($2))
+            "#,
+        )
+        .bind(storage_ids)
+        .bind(excluding_video_ids)
+        .fetch_all(pool)
+        .await
+    }
+    /// A function for finding the oldest processed video a user has with the same raw source
+    pub async fn find_duplicate(
+        pool: &PgPool,
+        user_id: Uuid,
+        content_sha256: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, Video>(
             r#"
-            SELECT id, user_id, title, raw_video_path, processed_video_path,
-                   processing_status, created_at, updated_at
+            SELECT *
             FROM videos
             WHERE user_id = $1
-            ORDER BY created_at DESC
+            AND content_sha256 = $2
+            AND processing_status = 'completed'
+            ORDER BY created_at ASC
+            LIMIT 1
             "#,
         )
         .bind(user_id)
-        .fetch_all(pool)
+        .bind(content_sha256)
+        .fetch_optional(pool)
         .await
     }
-    /// A function for getting all user owned videos by user name
-    pub async fn by_username(pool: &PgPool, username: &str) -> Result<Vec<Self>, sqlx::Error> {
+    /// A function for creating a video that reuses the raw source and renditions of another
+    pub async fn create_copy(
+        pool: &PgPool,
+        source: &Video,
+        title: String,
+        stream_id: Option<Uuid>,
+    ) -> Result<Self, sqlx::Error> {
         sqlx::query_as::<_, Video>(
             r#"
-            SELECT v.id, v.user_id, v.title, v.raw_video_path, v.processed_video_path,
-                   v.processing_status, v.created_at, v.updated_at
-            FROM videos v
-            JOIN users u ON u.id = v.user_id
-            WHERE u.name = $1
-            ORDER BY v.created_at DESC
+            INSERT INTO videos (
+                id, user_id, title, raw_video_path, processed_video_path, processing_status,
+                stream_id, preview_webp_path, preview_mp4_path, waveform_path,
+                content_sha256, source_video_id, raw_size_bytes, processed_size_bytes
+            )
+            VALUES ($1, $2, $3, $4, $5, 'completed', $6, $7, $8, $9, $10, $11, $12, $13)
+            RETURNING *
             "#,
         )
-        .bind(username)
-        .fetch_all(pool)
+        .bind(Self::gen_id())
+        .bind(source.user_id)
+        .bind(title)
+        .bind(&source.raw_video_path)
+        .bind(&source.processed_video_path)
+        .bind(stream_id)
+        .bind(&source.preview_webp_path)
+        .bind(&source.preview_mp4_path)
+        .bind(&source.waveform_path)
+        .bind(&source.content_sha256)
+        .bind(source.storage_id())
+        // Sizes go along, so the storage is still charged for once the original is deleted
+        .bind(source.raw_size_bytes)
+        .bind(source.processed_size_bytes)
+        .fetch_one(pool)
         .await
     }
-    /// A function for getting all videos
-    pub async fn all(pool: &PgPool) -> Result<Vec<Self>, sqlx::Error> {
+    /// Locks a video until the transaction ends, so only one request works on its upload at a time
+    /// Returns false straight away if another request already holds it
+    pub async fn lock_upload(
+        tx: &mut Transaction<'_, Postgres>,
+        id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let locked = sqlx::query("SELECT id FROM videos WHERE id = $1 FOR UPDATE NOWAIT")
+            .bind(id)
+            .execute(&mut **tx)
+            .await;
+        match locked {
+            Ok(_) => Ok(true),
+            // lock_not_available
+            Err(e)
+                if e.as_database_error()
+                    .and_then(|e| e.code())
+                    .is_some_and(|code| code == "55P03") =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+    /// A function for fetching a single video from the db by video ID
+    pub async fn by_id(pool: &PgPool, video_id: &str) -> Result<Self, sqlx::Error> {
         sqlx::query_as::<_, Video>(
             r#"
-                SELECT id, user_id, title, raw_video_path, processed_video_path,
-                       processing_status, created_at, updated_at
-                FROM videos
-                ORDER BY created_at DESC
-                "#,
+            SELECT *
+            FROM videos
+            WHERE id = $1
+            "#,
         )
+        .bind(video_id)
+        .fetch_one(pool)
+        .await
+    }
+    /// A function for finding the video a multipart upload is for
+    pub async fn by_upload_id(pool: &PgPool, upload_id: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Video>(
+            r#"
+            SELECT *
+            FROM videos
+            WHERE upload_id = $1
+            "#,
+        )
+        .bind(upload_id)
+        .fetch_optional(pool)
+        .await
+    }
+    /// A function for getting all user owned videos by user ID
+    pub async fn by_userid(pool: &PgPool, user_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Video>(
+            r#"
+            SELECT *
+            FROM videos
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
         .fetch_all(pool)
         .await
     }
+    /// A function for getting a page of the videos a viewer can see listed, which are public
+    /// videos along with the viewers own private and unlisted ones
+    /// Pages pick up after the cursor, which is made from the last video of the previous page
+    pub async fn page(
+        pool: &PgPool,
+        filter: &VideoFilter,
+        sort: VideoSort,
+        cursor: Option<&VideoCursor>,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "SELECT v.* FROM videos v JOIN users u ON u.id = v.user_id WHERE (v.privacy_status = 'public' OR v.user_id = ",
+        );
+        query.push_bind(filter.viewer_id).push(")");
+        if let Some(owner) = &filter.owner {
+            query.push(" AND u.username = ").push_bind(owner.clone());
+        }
+        if let Some(status) = &filter.processing_status {
+            query
+                .push(" AND v.processing_status = ")
+                .push_bind(status.clone());
+        }
+        if let Some(privacy) = filter.privacy_status {
+            query.push(" AND v.privacy_status = ").push_bind(privacy);
+        }
+        if let Some(after) = filter.created_after {
+            query.push(" AND v.created_at >= ").push_bind(after);
+        }
+        if let Some(before) = filter.created_before {
+            query.push(" AND v.created_at < ").push_bind(before);
+        }
+        // Rows are compared on the sort key, with the ID breaking ties
+        let (key, direction) = match sort {
+            VideoSort::Newest => ("v.created_at", "DESC"),
+            VideoSort::Oldest => ("v.created_at", "ASC"),
+            VideoSort::Title => ("v.title", "ASC"),
+            // Videos that aren't processed yet sort as if they were empty
+            VideoSort::Duration => ("COALESCE(v.duration_seconds, 0)", "DESC"),
+        };
+        if let Some(cursor) = cursor {
+            let comparison = if direction == "DESC" { "<" } else { ">" };
+            query.push(format!(" AND ({}, v.id) {} (", key, comparison));
+            match sort {
+                VideoSort::Newest | VideoSort::Oldest => query.push_bind(cursor.created_at),
+                VideoSort::Title => query.push_bind(cursor.title.clone()),
+                VideoSort::Duration => query.push_bind(cursor.duration_seconds.unwrap_or(0.0)),
+            };
+            query.push(", ").push_bind(cursor.id.clone()).push(")");
+        }
+        query.push(format!(
+            " ORDER BY {} {}, v.id {} LIMIT ",
+            key, direction, direction
+        ));
+        query.push_bind(limit);
+        query.build_query_as::<Video>().fetch_all(pool).await
+    }
     /// A function for deleting videos by ID
     /// NOTE: Only a video owner can delete their video
     pub async fn delete(
@@ -145,7 +465,38 @@ impl Video {
         .await?;
         Ok(())
     }
+    /// A function for updating the details a creator can edit, leaving out any that are None
+    /// An empty description clears it
+    pub async fn update_metadata(
+        pool: &PgPool,
+        id: &str,
+        title: Option<&str>,
+        description: Option<&str>,
+        tags: Option<&[String]>,
+        privacy_status: Option<PrivacyStatus>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Video>(
+            r#"
+                UPDATE videos
+                SET title = COALESCE($1, title),
+                    description = CASE WHEN $2::TEXT IS NULL THEN description ELSE NULLIF($2, '') END,
+                    tags = COALESCE($3, tags),
+                    privacy_status = COALESCE($4, privacy_status),
+                    updated_at = NOW()
+                WHERE id = $5
+                RETURNING *
+            "#,
+        )
+        .bind(title)
+        .bind(description)
+        .bind(tags)
+        .bind(privacy_status)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
     /// A function for updating a videos processing status
+    /// NOTE: This clears any previously recorded processing error
     pub async fn update_status(
         pool: &PgPool,
         id: String,
@@ -154,7 +505,10 @@ impl Video {
         sqlx::query(
             r#"
                 UPDATE videos
-                SET processing_status = $1
+                SET processing_status = $1,
+                    processing_error = NULL,
+                    processing_error_message = NULL,
+                    updated_at = NOW()
                 WHERE id = $2
             "#,
         )
@@ -164,4 +518,304 @@ impl Video {
         .await?;
         Ok(())
     }
+    /// A function for marking a video as successfully processed
+    pub async fn mark_completed(
+        pool: &PgPool,
+        id: &str,
+        processed_video_path: &str,
+        processed_size_bytes: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET processing_status = 'completed',
+                    processed_video_path = $1,
+                    processed_size_bytes = $2,
+                    processing_error = NULL,
+                    processing_error_message = NULL,
+                    updated_at = NOW()
+                WHERE id = $3
+            "#,
+        )
+        .bind(processed_video_path)
+        .bind(processed_size_bytes)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for marking a video as failed, along with the reason it failed
+    pub async fn mark_failed(
+        pool: &PgPool,
+        id: &str,
+        error: ProcessingError,
+        message: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET processing_status = 'failed',
+                    processing_error = $1,
+                    processing_error_message = $2,
+                    updated_at = NOW()
+                WHERE id = $3
+            "#,
+        )
+        .bind(error)
+        .bind(message)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for recording how long a video is
+    pub async fn set_duration(
+        pool: &PgPool,
+        id: &str,
+        duration_seconds: f64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET duration_seconds = $1,
+                    updated_at = NOW()
+                WHERE id = $2
+            "#,
+        )
+        .bind(duration_seconds)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for setting the path of a videos chapters track
+    pub async fn set_chapters_path(
+        pool: &PgPool,
+        id: &str,
+        chapters_path: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET chapters_path = $1,
+                    updated_at = NOW()
+                WHERE id = $2
+            "#,
+        )
+        .bind(chapters_path)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for setting the paths of a videos hover previews and waveform
+    pub async fn set_previews(
+        pool: &PgPool,
+        id: &str,
+        preview_webp_path: Option<&str>,
+        preview_mp4_path: Option<&str>,
+        waveform_path: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET preview_webp_path = $1,
+                    preview_mp4_path = $2,
+                    waveform_path = $3,
+                    updated_at = NOW()
+                WHERE id = $4
+            "#,
+        )
+        .bind(preview_webp_path)
+        .bind(preview_mp4_path)
+        .bind(waveform_path)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for recording the SHA-256 of a videos raw source
+    pub async fn set_content_hash(
+        pool: &PgPool,
+        id: &str,
+        content_sha256: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET content_sha256 = $1,
+                    updated_at = NOW()
+                WHERE id = $2
+            "#,
+        )
+        .bind(content_sha256)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for forgetting a multipart upload that was aborted, whichever video it was for
+    pub async fn clear_upload_id(pool: &PgPool, upload_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET upload_id = NULL,
+                    updated_at = NOW()
+                WHERE upload_id = $1
+            "#,
+        )
+        .bind(upload_id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for recording where an imported video is fetched from
+    pub async fn set_import_url(
+        pool: &PgPool,
+        id: &str,
+        import_url: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET import_url = $1,
+                    updated_at = NOW()
+                WHERE id = $2
+            "#,
+        )
+        .bind(import_url)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for moving where the raw video of a video is stored, before it's uploaded
+    pub async fn set_raw_video_path(
+        pool: &PgPool,
+        id: &str,
+        raw_video_path: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET raw_video_path = $1,
+                    updated_at = NOW()
+                WHERE id = $2
+            "#,
+        )
+        .bind(raw_video_path)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for tracking the multipart upload of a video, None once it completes
+    pub async fn set_upload_id(
+        pool: &PgPool,
+        id: &str,
+        upload_id: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET upload_id = $1,
+                    updated_at = NOW()
+                WHERE id = $2
+            "#,
+        )
+        .bind(upload_id)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for recording that a job needed the raw source of a video
+    pub async fn touch_raw_source(pool: &PgPool, id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET raw_accessed_at = NOW()
+                WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// A function for finding videos whose raw source hasn't been needed for longer than
+    /// the lifecycle of their uploaders role allows, least recently used first
+    /// Only videos that own their storage are returned, copies follow the video they reuse
+    pub async fn due_for_cold_storage(pool: &PgPool, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Video>(
+            r#"
+            SELECT v.*
+            FROM videos v
+            JOIN users u ON u.id = v.user_id
+            JOIN role_storage_lifecycles l ON l.role = u.role
+            WHERE v.source_video_id IS NULL
+            AND v.raw_storage_tier = 'hot'
+            AND v.upload_id IS NULL
+            AND v.processing_status IN ('completed', 'failed')
+            -- Imports that failed never got a raw source to move
+            AND (v.import_url IS NULL OR v.raw_size_bytes IS NOT NULL)
+            AND l.raw_cold_after_days IS NOT NULL
+            AND COALESCE(v.raw_accessed_at, v.created_at)
+                < NOW() - make_interval(days => l.raw_cold_after_days)
+            ORDER BY COALESCE(v.raw_accessed_at, v.created_at) ASC
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+    /// A function for recording that a raw source was moved to cold storage, along with
+    /// the videos reusing it
+    /// Returns false without changing anything if the source is being processed, or isn't hot
+    pub async fn set_raw_cold(
+        pool: &PgPool,
+        storage_id: &str,
+        raw_cold_path: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+                UPDATE videos
+                SET raw_storage_tier = 'cold',
+                    raw_cold_path = $1,
+                    updated_at = NOW()
+                WHERE (id = $2 OR source_video_id = $2)
+                AND EXISTS (
+                    SELECT 1
+                    FROM videos
+                    WHERE id = $2
+                    AND raw_storage_tier = 'hot'
+                    AND processing_status <> 'processing'
+                )
+            "#,
+        )
+        .bind(raw_cold_path)
+        .bind(storage_id)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+    /// A function for recording that a raw source is back in hot storage, along with
+    /// the videos reusing it
+    pub async fn set_raw_hot(pool: &PgPool, storage_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+                UPDATE videos
+                SET raw_storage_tier = 'hot',
+                    raw_cold_path = NULL,
+                    raw_accessed_at = NOW(),
+                    updated_at = NOW()
+                WHERE id = $1 OR source_video_id = $1
+            "#,
+        )
+        .bind(storage_id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,70 @@
+use thiserror::Error;
+
+use super::UploadError;
+use crate::db::ProcessingError;
+
+/// Classified failures that can happen while importing a video from another host
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("Blocked Address: {0}")]
+    BlockedAddress(String),
+    #[error("Request Failed: {0}")]
+    Request(String),
+    #[error("Unexpected Status: {0}")]
+    Status(u16),
+    #[error("Over Quota: {0} bytes left")]
+    OverQuota(u64),
+    #[error("Upload Failed: {0}")]
+    Upload(#[from] UploadError),
+}
+
+impl ImportError {
+    /// Gets the error code stored alongside the video
+    pub fn code(&self) -> ProcessingError {
+        match self {
+            ImportError::Upload(
+                UploadError::UnsupportedExtension(_)
+                | UploadError::UnsupportedContentType(_)
+                | UploadError::ContentMismatch(_),
+            ) => ProcessingError::UnsupportedFormat,
+            ImportError::Upload(UploadError::Storage(_)) => ProcessingError::StorageFailed,
+            _ => ProcessingError::ImportFailed,
+        }
+    }
+    /// Whether retrying the job could succeed, the remote host may only be having trouble for now
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ImportError::Request(_) => true,
+            ImportError::Status(status) => *status == 429 || *status >= 500,
+            ImportError::Upload(UploadError::Interrupted(_) | UploadError::Storage(_)) => true,
+            _ => false,
+        }
+    }
+    /// Gets an explanation of the failure that is safe to show to the creator
+    pub fn user_message(&self) -> String {
+        match self {
+            ImportError::InvalidUrl(_) => "The import URL is not valid. Please check the link and try again.".to_string(),
+            ImportError::BlockedAddress(_) => "The import URL points to an address that videos can't be imported from. Please use a publicly reachable link.".to_string(),
+            ImportError::Request(_) => "The host of the import URL could not be reached. The import will be retried.".to_string(),
+            ImportError::Status(status) => format!(
+                "The host of the import URL responded with status {}. Please check that the link is publicly downloadable.",
+                status
+            ),
+            ImportError::OverQuota(_) => "The video is bigger than the storage you have left. Please free up space and import it again.".to_string(),
+            ImportError::Upload(UploadError::TooLarge { max, .. }) => format!(
+                "The video is bigger than the {} bytes that can be imported.",
+                max
+            ),
+            ImportError::Upload(
+                UploadError::UnsupportedExtension(_)
+                | UploadError::UnsupportedContentType(_)
+                | UploadError::ContentMismatch(_),
+            ) => "The import URL does not point to a supported video. Please link to an MP4 or MOV file.".to_string(),
+            ImportError::Upload(UploadError::Interrupted(_)) => "The download was interrupted. The import will be retried.".to_string(),
+            ImportError::Upload(UploadError::Storage(_)) => "The video could not be transferred to storage. The import will be retried.".to_string(),
+            ImportError::Upload(_) => "The import URL did not return a usable video. Please check the link and try again.".to_string(),
+        }
+    }
+}
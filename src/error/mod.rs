@@ -1,3 +1,13 @@
+pub mod import;
 pub mod queue;
+pub mod storage;
+pub mod upload;
+pub mod vod;
+pub mod workdir;
 
+pub use import::ImportError;
 pub use queue::{QueueError, StreamError};
+pub use storage::StorageError;
+pub use upload::UploadError;
+pub use vod::VodError;
+pub use workdir::WorkDirError;
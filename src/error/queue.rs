@@ -4,6 +4,8 @@ use thiserror::Error;
 pub enum QueueError {
     #[error("Invalid Connection: {0}")]
     InvalidConnection(String),
+    #[error("Invalid Payload: {0}")]
+    InvalidPayload(String),
 }
 
 #[derive(Error, Debug)]
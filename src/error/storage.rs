@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+/// Failures from a storage backend
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Not Found: {0}")]
+    NotFound(String),
+    #[error("Invalid Key: {0}")]
+    InvalidKey(String),
+    #[error("Unsupported Operation: {0}")]
+    Unsupported(&'static str),
+    #[error("IO Error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Backend Error: {0}")]
+    Backend(String),
+}
@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+use super::StorageError;
+
+/// Reasons an upload is rejected before or after it reaches storage
+#[derive(Error, Debug)]
+pub enum UploadError {
+    #[error("Unsupported Extension: {0}")]
+    UnsupportedExtension(String),
+    #[error("Unsupported Content Type: {0}")]
+    UnsupportedContentType(String),
+    #[error("Invalid Size: {0}")]
+    InvalidSize(String),
+    #[error("Too Large: {size} bytes, at most {max} allowed")]
+    TooLarge { size: u64, max: u64 },
+    #[error("Invalid Parts: {0}")]
+    InvalidParts(String),
+    #[error("Content Mismatch: {0}")]
+    ContentMismatch(String),
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
+    #[error("Storage Error: {0}")]
+    Storage(#[from] StorageError),
+}
@@ -0,0 +1,66 @@
+use thiserror::Error;
+
+use crate::db::ProcessingError;
+
+/// Classified failures that can happen while validating or transcoding a VOD
+#[derive(Error, Debug)]
+pub enum VodError {
+    #[error("Unsupported Format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Unreadable Source: {0}")]
+    UnreadableSource(String),
+    #[error("No Video Stream")]
+    NoVideoStream,
+    #[error("Invalid Dimensions: {0}x{1}")]
+    InvalidDimensions(u32, u32),
+    #[error("Invalid Duration: {0}")]
+    InvalidDuration(String),
+    #[error("Corrupt Source: {0}")]
+    CorruptSource(String),
+    #[error("Encoding Failed: {0}")]
+    EncodingFailed(String),
+    #[error("Storage Failed: {0}")]
+    StorageFailed(String),
+}
+
+impl VodError {
+    /// Gets the error code stored alongside the video
+    pub fn code(&self) -> ProcessingError {
+        match self {
+            VodError::UnsupportedFormat(_) => ProcessingError::UnsupportedFormat,
+            VodError::UnreadableSource(_) => ProcessingError::UnreadableSource,
+            VodError::NoVideoStream => ProcessingError::NoVideoStream,
+            VodError::InvalidDimensions(..) => ProcessingError::InvalidDimensions,
+            VodError::InvalidDuration(_) => ProcessingError::InvalidDuration,
+            VodError::CorruptSource(_) => ProcessingError::CorruptSource,
+            VodError::EncodingFailed(_) => ProcessingError::EncodingFailed,
+            VodError::StorageFailed(_) => ProcessingError::StorageFailed,
+        }
+    }
+    /// Whether retrying the job could succeed, problems with the source itself never will
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, VodError::StorageFailed(_))
+    }
+    /// Gets an explanation of the failure that is safe to show to the creator
+    pub fn user_message(&self) -> String {
+        match self {
+            VodError::UnsupportedFormat(format) => format!(
+                "The {} format is not supported. Please re-upload the video as an MP4 or MOV file.",
+                format
+            ),
+            VodError::UnreadableSource(_) => "This file could not be read as a video. Please check that it plays on your device and re-upload it.".to_string(),
+            VodError::NoVideoStream => "This file does not contain a video track. Please re-upload a file that includes video.".to_string(),
+            VodError::InvalidDimensions(width, height) => format!(
+                "The video resolution {}x{} is not supported. Videos must be at most 7680x4320. Please re-upload at a supported resolution.",
+                width, height
+            ),
+            VodError::InvalidDuration(reason) => format!(
+                "The video length is not supported ({}). Please re-upload a different recording.",
+                reason
+            ),
+            VodError::CorruptSource(_) => "The video appears to be corrupt or incomplete, which usually means the recording or upload was interrupted. Please re-upload the original file.".to_string(),
+            VodError::EncodingFailed(_) => "The video could not be converted for streaming. Re-uploading the same file is unlikely to help, the error has been logged for review.".to_string(),
+            VodError::StorageFailed(_) => "The video files could not be transferred to storage. There is no need to re-upload, processing will be retried.".to_string(),
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use thiserror::Error;
+
+/// Failures managing the local directory runners work in
+#[derive(Error, Debug)]
+pub enum WorkDirError {
+    #[error("Insufficient Space: {needed} bytes needed, {available} available")]
+    InsufficientSpace { needed: u64, available: u64 },
+    #[error("IO Error: {0}")]
+    Io(#[from] std::io::Error),
+}
@@ -1,7 +1,7 @@
 pub mod stream;
 pub use stream::Stream;
 
-use crate::twitch::{ChatMessagePayload, StreamStatusPayload};
+use crate::twitch::{ChannelUpdatePayload, ChatMessagePayload, RaidPayload, StreamStatusPayload};
 pub use stream::EVENT_STREAM;
 
 pub const MESSAGE_PREFIX: &str = "farmhand";
@@ -14,6 +14,8 @@ pub const JOB_STREAM: &str = "FARMHAND_JOBS";
 pub enum Event {
     ChatMessage(ChatMessagePayload),
     StreamStatus(StreamStatusPayload),
+    ChannelUpdate(ChannelUpdatePayload),
+    Raid(RaidPayload),
 }
 
 impl Event {
@@ -36,6 +38,16 @@ impl Event {
                     MESSAGE_PREFIX, EVENT_PREFIX, payload.broadcaster_user_name, status
                 )
             }
+            // farmhand.events.twitch.{broadcaster_name}.channel_update
+            Event::ChannelUpdate(payload) => format!(
+                "{}.{}.twitch.events.{}.channel_update",
+                MESSAGE_PREFIX, EVENT_PREFIX, payload.broadcaster_user_name
+            ),
+            // farmhand.events.twitch.{broadcaster_name}.raid
+            Event::Raid(payload) => format!(
+                "{}.{}.twitch.events.{}.raid",
+                MESSAGE_PREFIX, EVENT_PREFIX, payload.to_broadcaster_user_name
+            ),
         };
         // Make sure the subject is lowercase
         raw_subject.to_lowercase()
@@ -53,3 +65,15 @@ impl From<StreamStatusPayload> for Event {
         Event::StreamStatus(payload)
     }
 }
+
+impl From<ChannelUpdatePayload> for Event {
+    fn from(payload: ChannelUpdatePayload) -> Self {
+        Event::ChannelUpdate(payload)
+    }
+}
+
+impl From<RaidPayload> for Event {
+    fn from(payload: RaidPayload) -> Self {
+        Event::Raid(payload)
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{Runner, RunnerContext};
+use crate::db::{
+    analytics::{VideoDailyStats, ViewSession},
+    DBPool,
+};
+
+/// Watch time a session needs before it counts as a view, or half of shorter videos
+pub const VIEW_THRESHOLD_SECONDS: f64 = 10.0;
+/// Days rolled up in each run, including today, so sessions running past midnight are caught up
+pub const DEFAULT_AGGREGATE_DAYS: i64 = 2;
+/// Days sessions are kept after they start, only their daily stats are kept after that
+pub const DEFAULT_SESSION_RETENTION_DAYS: i64 = 90;
+
+fn default_days() -> i64 {
+    DEFAULT_AGGREGATE_DAYS
+}
+
+fn default_session_retention_days() -> i64 {
+    DEFAULT_SESSION_RETENTION_DAYS
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AggregateViewStatsPayload {
+    /// How many days back to roll up again, including today
+    #[serde(default = "default_days")]
+    pub days: i64,
+    /// Sessions started longer ago than this are deleted
+    #[serde(default = "default_session_retention_days")]
+    pub session_retention_days: i64,
+}
+
+impl Default for AggregateViewStatsPayload {
+    fn default() -> Self {
+        AggregateViewStatsPayload {
+            days: DEFAULT_AGGREGATE_DAYS,
+            session_retention_days: DEFAULT_SESSION_RETENTION_DAYS,
+        }
+    }
+}
+
+/// What an aggregation run updated
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct AggregationReport {
+    /// Days of single videos whose stats were rolled up again
+    pub video_days: u64,
+    pub deleted_sessions: u64,
+}
+
+/// Rolls up recent view sessions into daily stats, then deletes the sessions too old to keep
+pub async fn aggregate_view_stats(
+    db: &DBPool,
+    payload: &AggregateViewStatsPayload,
+) -> Result<AggregationReport> {
+    let today = chrono::Utc::now().date_naive();
+    let since = today - chrono::Days::new(payload.days.max(1) as u64 - 1);
+    let video_days = VideoDailyStats::aggregate_since(db, since, VIEW_THRESHOLD_SECONDS).await?;
+    // Never delete sessions whose days are still being rolled up
+    let retention_days = payload.session_retention_days.max(payload.days);
+    let deleted_sessions = ViewSession::delete_started_before(
+        db,
+        chrono::Utc::now() - chrono::Duration::days(retention_days),
+    )
+    .await?;
+    Ok(AggregationReport {
+        video_days,
+        deleted_sessions,
+    })
+}
+
+/// Turns view sessions into the daily stats creators see
+pub struct AggregateViewStatsRunner {
+    context: Arc<RunnerContext>,
+}
+
+impl AggregateViewStatsRunner {
+    pub fn new(context: Arc<RunnerContext>) -> Self {
+        AggregateViewStatsRunner { context }
+    }
+}
+
+impl Runner for AggregateViewStatsRunner {
+    type Payload = AggregateViewStatsPayload;
+
+    async fn process_job(&self, payload: Self::Payload) -> Result<()> {
+        tracing::debug!(
+            "Processing job with runner AggregateViewStatsRunner, days: {days}",
+            days = payload.days,
+        );
+        let report = aggregate_view_stats(&self.context.db, &payload).await?;
+        tracing::info!("View stats report: {}", serde_json::to_string(&report)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{Runner, RunnerContext};
+use crate::{
+    error::StorageError,
+    storage::{tier::ColdStorage, Storage},
+};
+
+/// A multipart upload that was never completed
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PendingUpload {
+    pub key: String,
+    pub upload_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StorageCleanupPayload {
+    /// Every object under this prefix is deleted
+    pub prefix: String,
+    pub pending_upload: Option<PendingUpload>,
+}
+
+/// Removes everything a deleted video left in storage
+pub struct StorageCleanupRunner {
+    context: Arc<RunnerContext>,
+}
+
+impl StorageCleanupRunner {
+    pub fn new(context: Arc<RunnerContext>) -> Self {
+        StorageCleanupRunner { context }
+    }
+}
+
+/// Aborts any unfinished upload, then deletes every object under the prefix,
+/// both in hot storage and in cold storage where raw sources may have been moved
+/// Safe to run more than once, things that are already gone are skipped
+pub async fn cleanup_storage(
+    storage: &dyn Storage,
+    cold_storage: &ColdStorage,
+    payload: &StorageCleanupPayload,
+) -> Result<u64, StorageError> {
+    if let Some(upload) = &payload.pending_upload {
+        match storage
+            .abort_multipart_upload(&upload.key, &upload.upload_id)
+            .await
+        {
+            Ok(()) | Err(StorageError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let deleted = storage.delete_prefix(&payload.prefix).await?;
+    let cold_deleted = cold_storage
+        .storage
+        .delete_prefix(&cold_storage.key_for(&payload.prefix))
+        .await?;
+    Ok(deleted + cold_deleted)
+}
+
+impl Runner for StorageCleanupRunner {
+    type Payload = StorageCleanupPayload;
+
+    async fn process_job(&self, payload: Self::Payload) -> Result<()> {
+        tracing::debug!(
+            "Processing job with runner StorageCleanupRunner for prefix {prefix}",
+            prefix = payload.prefix,
+        );
+        let deleted = cleanup_storage(
+            self.context.storage.as_ref(),
+            &self.context.cold_storage,
+            &payload,
+        )
+        .await?;
+        tracing::info!("Deleted {} objects under {}", deleted, payload.prefix);
+        Ok(())
+    }
+}
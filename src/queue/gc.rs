@@ -0,0 +1,267 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{Runner, RunnerContext};
+use crate::{
+    db::{playlists::PLAYLIST_STORAGE_DIR, DBPool, StorageTier, Video},
+    prelude::get_storage_dir,
+    storage::{tier::ColdStorage, MultipartUpload, ObjectMeta, Storage},
+};
+
+/// How old an unfinished multipart upload has to be before it's aborted
+pub const DEFAULT_STALE_UPLOAD_HOURS: u64 = 24;
+/// How long objects without a video have to sit untouched before they're deleted
+pub const DEFAULT_ORPHAN_GRACE_HOURS: u64 = 72;
+
+fn default_stale_upload_hours() -> u64 {
+    DEFAULT_STALE_UPLOAD_HOURS
+}
+
+fn default_orphan_grace_hours() -> u64 {
+    DEFAULT_ORPHAN_GRACE_HOURS
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GarbageCollectPayload {
+    /// Only report what would be cleaned up, without touching storage
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default = "default_stale_upload_hours")]
+    pub stale_upload_hours: u64,
+    #[serde(default = "default_orphan_grace_hours")]
+    pub orphan_grace_hours: u64,
+}
+
+impl Default for GarbageCollectPayload {
+    fn default() -> Self {
+        GarbageCollectPayload {
+            dry_run: false,
+            stale_upload_hours: DEFAULT_STALE_UPLOAD_HOURS,
+            orphan_grace_hours: DEFAULT_ORPHAN_GRACE_HOURS,
+        }
+    }
+}
+
+/// Objects stored under a single video prefix
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PrefixUsage {
+    pub prefix: String,
+    pub video_id: String,
+    /// Whether the prefix is in hot or cold storage
+    pub tier: StorageTier,
+    pub objects: u64,
+    pub size: u64,
+    /// When the most recently changed object under the prefix was written
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// An unfinished multipart upload that was, or would be, aborted
+#[derive(Serialize, Debug, Clone)]
+pub struct StaleUpload {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: Option<DateTime<Utc>>,
+}
+
+/// Everything a garbage collection run found, and what it did about it
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct GarbageCollectReport {
+    pub dry_run: bool,
+    pub stale_uploads: Vec<StaleUpload>,
+    pub orphaned_prefixes: Vec<PrefixUsage>,
+    /// Orphaned prefixes that are still inside the grace period
+    pub pending_prefixes: Vec<PrefixUsage>,
+    pub aborted_uploads: u64,
+    pub deleted_objects: u64,
+    pub freed_bytes: u64,
+    pub errors: Vec<String>,
+}
+
+/// Picks out the uploads started before the cutoff
+/// Uploads that don't say when they started are left alone, since their age can't be known
+pub fn stale_uploads(uploads: Vec<MultipartUpload>, cutoff: DateTime<Utc>) -> Vec<StaleUpload> {
+    uploads
+        .into_iter()
+        .filter(|upload| upload.initiated.is_some_and(|initiated| initiated < cutoff))
+        .map(|upload| StaleUpload {
+            key: upload.key,
+            upload_id: upload.upload_id,
+            initiated: upload.initiated,
+        })
+        .collect()
+}
+
+/// Groups objects under the storage root by the video ID that makes up the first path segment
+/// Playlist files are left out, they aren't owned by a video
+pub fn group_by_video(objects: &[ObjectMeta], root: &str, tier: StorageTier) -> Vec<PrefixUsage> {
+    let root = format!("{}/", root.trim_end_matches('/'));
+    let mut groups: BTreeMap<&str, PrefixUsage> = BTreeMap::new();
+    for object in objects {
+        let Some((video_id, _)) = object
+            .key
+            .strip_prefix(&root)
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            continue;
+        };
+        // Playlist files sit next to the videos, but are cleaned up with their playlists
+        if video_id.is_empty() || video_id == PLAYLIST_STORAGE_DIR {
+            continue;
+        }
+        let usage = groups.entry(video_id).or_insert_with(|| PrefixUsage {
+            prefix: format!("{}{}/", root, video_id),
+            video_id: video_id.to_string(),
+            tier,
+            objects: 0,
+            size: 0,
+            last_modified: None,
+        });
+        usage.objects += 1;
+        usage.size += object.size;
+        usage.last_modified = usage.last_modified.max(object.last_modified);
+    }
+    groups.into_values().collect()
+}
+
+/// Splits prefixes without a video into ones past the grace period and ones still inside it
+/// Prefixes with no modification times are treated as past it, nothing will ever make them newer
+pub fn orphaned_prefixes(
+    prefixes: Vec<PrefixUsage>,
+    existing_video_ids: &HashSet<String>,
+    cutoff: DateTime<Utc>,
+) -> (Vec<PrefixUsage>, Vec<PrefixUsage>) {
+    prefixes
+        .into_iter()
+        .filter(|usage| !existing_video_ids.contains(&usage.video_id))
+        .partition(|usage| usage.last_modified.is_none_or(|modified| modified < cutoff))
+}
+
+/// Aborts stale multipart uploads and deletes objects no video owns anymore,
+/// in hot storage as well as cold storage
+/// In a dry run everything is found and reported, but nothing is changed
+pub async fn collect_garbage(
+    db: &DBPool,
+    storage: &dyn Storage,
+    cold_storage: &ColdStorage,
+    payload: &GarbageCollectPayload,
+) -> Result<GarbageCollectReport> {
+    let now = Utc::now();
+    let root = get_storage_dir();
+    let mut report = GarbageCollectReport {
+        dry_run: payload.dry_run,
+        ..Default::default()
+    };
+
+    let upload_cutoff = now - chrono::Duration::hours(payload.stale_upload_hours as i64);
+    let uploads = storage
+        .list_multipart_uploads(&format!("{}/", root))
+        .await?;
+    report.stale_uploads = stale_uploads(uploads, upload_cutoff);
+
+    let objects = storage.list(&format!("{}/", root)).await?;
+    let mut prefixes = group_by_video(&objects, &root, StorageTier::Hot);
+    let cold_root = cold_storage.key_for(&root);
+    let cold_objects = cold_storage
+        .storage
+        .list(&format!("{}/", cold_root))
+        .await?;
+    prefixes.extend(group_by_video(&cold_objects, &cold_root, StorageTier::Cold));
+    let video_ids = prefixes
+        .iter()
+        .map(|usage| usage.video_id.clone())
+        .collect::<Vec<_>>();
+    let existing_video_ids = Video::existing_ids(db, &video_ids)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let orphan_cutoff = now - chrono::Duration::hours(payload.orphan_grace_hours as i64);
+    (report.orphaned_prefixes, report.pending_prefixes) =
+        orphaned_prefixes(prefixes, &existing_video_ids, orphan_cutoff);
+
+    if payload.dry_run {
+        return Ok(report);
+    }
+
+    // Keep going when one thing fails, the rest will still be cleaned up and the next run retries
+    for upload in &report.stale_uploads {
+        match storage
+            .abort_multipart_upload(&upload.key, &upload.upload_id)
+            .await
+        {
+            Ok(()) => {
+                report.aborted_uploads += 1;
+                // Stop the upload holding space against its uploaders quota
+                if let Err(e) = Video::clear_upload_id(db, &upload.upload_id).await {
+                    report.errors.push(format!(
+                        "Failed to forget upload {}: {}",
+                        upload.upload_id, e
+                    ));
+                }
+            }
+            Err(e) => report.errors.push(format!(
+                "Failed to abort upload {}: {}",
+                upload.upload_id, e
+            )),
+        }
+    }
+    for usage in &report.orphaned_prefixes {
+        let storage = match usage.tier {
+            StorageTier::Hot => storage,
+            StorageTier::Cold => cold_storage.storage.as_ref(),
+        };
+        match storage.delete_prefix(&usage.prefix).await {
+            Ok(deleted) => {
+                report.deleted_objects += deleted;
+                report.freed_bytes += usage.size;
+            }
+            Err(e) => report
+                .errors
+                .push(format!("Failed to delete {}: {}", usage.prefix, e)),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Cleans up storage that was abandoned by unfinished uploads and deleted videos
+pub struct GarbageCollectRunner {
+    context: Arc<RunnerContext>,
+}
+
+impl GarbageCollectRunner {
+    pub fn new(context: Arc<RunnerContext>) -> Self {
+        GarbageCollectRunner { context }
+    }
+}
+
+impl Runner for GarbageCollectRunner {
+    type Payload = GarbageCollectPayload;
+
+    async fn process_job(&self, payload: Self::Payload) -> Result<()> {
+        tracing::debug!(
+            "Processing job with runner GarbageCollectRunner, dry run: {dry_run}",
+            dry_run = payload.dry_run,
+        );
+        let report = collect_garbage(
+            &self.context.db,
+            self.context.storage.as_ref(),
+            &self.context.cold_storage,
+            &payload,
+        )
+        .await?;
+        tracing::info!(
+            "Garbage collection report: {}",
+            serde_json::to_string(&report)?
+        );
+        for error in &report.errors {
+            tracing::warn!("{}", error);
+        }
+        Ok(())
+    }
+}
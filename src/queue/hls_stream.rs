@@ -1,14 +1,315 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
 use anyhow::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use super::{Runner, RunnerContext};
+use crate::{
+    db::{
+        chapters::{ChapterSource, NewChapter, VideoChapter},
+        stream_events::StreamEvent,
+        streams::Stream,
+        ProcessingError, ProcessingStatus, Video,
+    },
+    error::VodError,
+    storage::sync::{sha256_file, sync_directory, SyncOptions},
+    vod::{
+        analysis::AnalysisSettings,
+        chapters::{chapters_from_stream_events, Chapter, CHAPTERS_FILE},
+        preview::{PreviewFormat, PreviewSettings, WAVEFORM_FILE},
+        probe::ProbeInfo,
+        stream::{HLSConverter, Quality, SourceProfile},
+        DownloadSettings, Vod,
+    },
+};
 
-use super::Runner;
+/// Message shown to creators when processing fails for a reason we could not classify
+const INTERNAL_ERROR_MESSAGE: &str =
+    "Something went wrong on our end while processing this video. There is no need to re-upload.";
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct VideoToStreamPayload {
     pub video_id: String,
 }
 
-pub struct HlsStreamRunner;
+pub struct HlsStreamRunner {
+    context: Arc<RunnerContext>,
+}
+
+/// Remote paths of everything produced while transforming a video
+struct TransformOutput {
+    master_playlist_path: String,
+    chapters_path: Option<String>,
+    /// Bytes the processed output takes up in storage
+    processed_size: u64,
+    previews: PreviewPaths,
+    duration: f64,
+}
+
+/// Remote paths of the hover previews and waveform, each is skipped if it could not be generated
+#[derive(Default)]
+struct PreviewPaths {
+    webp: Option<String>,
+    mp4: Option<String>,
+    waveform: Option<String>,
+}
+
+impl HlsStreamRunner {
+    pub fn new(context: Arc<RunnerContext>) -> Self {
+        HlsStreamRunner { context }
+    }
+
+    /// Downloads, validates and converts the raw video, then uploads the results
+    async fn transform(&self, video_id: &str) -> Result<TransformOutput> {
+        let work_dir = &self.context.work_dir;
+        let scratch_dir = work_dir.scratch_dir(video_id)?;
+        let output_dir = scratch_dir.path().to_path_buf();
+        let vod = Vod::by_id(&self.context.db, video_id.to_string(), output_dir.clone()).await?;
+
+        // Leave room for the raw video, and about as much again for what's made from it
+        let raw_size = vod.video.raw_size_bytes.unwrap_or_default().max(0) as u64;
+        work_dir.ensure_free_space(raw_size.saturating_mul(2))?;
+
+        // Get the raw video, from the cache if it's already been downloaded
+        // Sources that were moved to cold storage are restored on the way
+        let download_settings = DownloadSettings {
+            db: &self.context.db,
+            storage: self.context.storage.as_ref(),
+            cold_storage: &self.context.cold_storage,
+        };
+        let raw_source = work_dir
+            .raw_source(&vod, download_settings)
+            .await
+            .map_err(|e| VodError::StorageFailed(e.to_string()))?;
+        let video_path = raw_source.path().to_path_buf();
+        // Keep the source from being moved to cold storage while it's still in use
+        if let Err(e) = Video::touch_raw_source(&self.context.db, video_id).await {
+            tracing::warn!("Could not record use of raw video {}: {}", video_id, e);
+        }
+        self.record_content_hash(&vod.video, &video_path).await;
+
+        // Validate and convert the video, ffmpeg blocks so keep it off the async runtime
+        let converter = vod.converter.clone();
+        let source_path = video_path.clone();
+        let info = tokio::task::spawn_blocking(move || -> Result<ProbeInfo> {
+            let info = converter.validate_source(&source_path)?;
+
+            let profile = SourceProfile::from_probe(&info);
+
+            // Define quality levels
+            let mut qualities = Vec::new();
+            if profile.supports_high_frame_rate() {
+                qualities.push(Quality::new(1920, 1080, "7500k", "1080p60").with_fps(60));
+                qualities.push(Quality::new(1280, 720, "4200k", "720p60").with_fps(60));
+            }
+            qualities.extend([
+                Quality::new(1920, 1080, "5000k", "1080p"),
+                Quality::new(1280, 720, "2800k", "720p"),
+                Quality::new(854, 480, "1400k", "480p"),
+            ]);
+            converter.convert_to_hls(&source_path, qualities, &profile)?;
+            Ok(info)
+        })
+        .await??;
+        let duration = info.duration_secs().unwrap_or_default();
+
+        let remote_prefix = vod.get_remote_storage_prefix();
+        let chapters_path = match self
+            .prepare_chapters(&vod, video_path.clone(), duration)
+            .await
+        {
+            Ok(true) => Some(format!("{}/{}", remote_prefix, CHAPTERS_FILE)),
+            Ok(false) => None,
+            Err(e) => {
+                // Chapters are a nice to have, so don't fail processing over them
+                tracing::warn!("Could not create chapters for video {}: {}", video_id, e);
+                None
+            }
+        };
+
+        let converter = vod.converter.clone();
+        let has_audio = info.audio_stream().is_some();
+        let preview_prefix = remote_prefix.clone();
+        let previews = tokio::task::spawn_blocking(move || {
+            generate_previews(
+                &converter,
+                &video_path,
+                duration,
+                has_audio,
+                &preview_prefix,
+            )
+        })
+        .await?;
+
+        // Upload the stream files, the raw video already lives in storage
+        let manifest = sync_directory(
+            self.context.storage.as_ref(),
+            &output_dir,
+            &remote_prefix,
+            &SyncOptions::default(),
+        )
+        .await
+        .map_err(|e| VodError::StorageFailed(e.to_string()))?;
+        tracing::info!(
+            "Synced {} files ({} bytes) for video {}, {} needed uploading",
+            manifest.objects.len(),
+            manifest.total_size(),
+            video_id,
+            manifest.uploaded_count()
+        );
+
+        // Everything is in storage now, so the local output isn't needed anymore
+        if let Err(e) = scratch_dir.remove() {
+            tracing::warn!("Could not remove scratch directory {:?}: {}", output_dir, e);
+        }
+
+        Ok(TransformOutput {
+            master_playlist_path: format!("{}/master.m3u8", remote_prefix),
+            chapters_path,
+            processed_size: manifest.total_size(),
+            previews,
+            duration,
+        })
+    }
+
+    /// Hashes the raw source so re-uploads of it can be spotted, replacing whatever the uploader
+    /// declared since that can't be trusted
+    async fn record_content_hash(&self, video: &Video, video_path: &Path) {
+        let content_sha256 = match sha256_file(video_path).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                // Deduplication is a nice to have, so don't fail processing over it
+                tracing::warn!("Could not hash raw source of video {}: {}", video.id, e);
+                return;
+            }
+        };
+        if let Some(declared) = &video.content_sha256 {
+            if *declared != content_sha256 {
+                tracing::warn!(
+                    "Declared hash {} of video {} doesn't match its raw source {}",
+                    declared,
+                    video.id,
+                    content_sha256
+                );
+            }
+        }
+        if let Err(e) = Video::set_content_hash(&self.context.db, &video.id, &content_sha256).await
+        {
+            tracing::warn!("Could not save hash of video {}: {}", video.id, e);
+        }
+    }
+
+    /// Works out the chapters of a video, embedding the accepted ones into the output
+    /// Chapters the creator already has are kept, otherwise recordings of a stream get chapters
+    /// from the stream's events and uploads get proposals from analyzing the video itself
+    /// Returns whether a chapters track was written
+    async fn prepare_chapters(
+        &self,
+        vod: &Vod,
+        video_path: PathBuf,
+        duration: f64,
+    ) -> Result<bool> {
+        let db = &self.context.db;
+        let existing = VideoChapter::by_video_id(&vod.video.id, db).await?;
+        let accepted: Vec<Chapter> = if !existing.is_empty() {
+            existing
+                .iter()
+                .filter(|chapter| chapter.accepted_at.is_some())
+                .map(Chapter::from)
+                .collect()
+        } else if let Some(stream_id) = vod.video.stream_id {
+            let stream = Stream::find_by_id(stream_id, db).await?;
+            let events = StreamEvent::find_by_stream_id(stream_id, db).await?;
+            let chapters = chapters_from_stream_events(stream.start_time, &events, duration);
+            VideoChapter::replace_all(
+                &vod.video.id,
+                &to_new_chapters(&chapters, ChapterSource::Stream, true),
+                db,
+            )
+            .await?;
+            chapters
+        } else {
+            // Proposals wait for the creator to accept them before being published
+            let converter = vod.converter.clone();
+            let chapters = tokio::task::spawn_blocking(move || {
+                converter.analyze_chapters(&video_path, duration, &AnalysisSettings::from_env())
+            })
+            .await??;
+            tracing::debug!(
+                "Proposing {} chapters for video {}",
+                chapters.len(),
+                vod.video.id
+            );
+            VideoChapter::replace_all(
+                &vod.video.id,
+                &to_new_chapters(&chapters, ChapterSource::Scene, false),
+                db,
+            )
+            .await?;
+            Vec::new()
+        };
+
+        if accepted.is_empty() {
+            return Ok(false);
+        }
+        // Without a stream to anchor to, the date ranges are relative to when the video was created
+        let program_start = match vod.video.stream_id {
+            Some(stream_id) => Stream::find_by_id(stream_id, db).await?.start_time,
+            None => vod.video.created_at,
+        };
+        vod.converter.embed_chapters(&accepted, program_start)?;
+        Ok(true)
+    }
+}
+
+/// Generates the hover previews and waveform, returning the remote paths of the ones that worked
+/// These are nice to haves, so failures are only logged
+fn generate_previews(
+    converter: &HLSConverter,
+    video_path: &Path,
+    duration: f64,
+    has_audio: bool,
+    remote_prefix: &str,
+) -> PreviewPaths {
+    let settings = PreviewSettings::default();
+    let mut previews = PreviewPaths::default();
+    for format in [PreviewFormat::WebP, PreviewFormat::MP4] {
+        match converter.generate_preview(video_path, duration, format, &settings) {
+            Ok(_) => {
+                let file = Some(format!("{}/{}", remote_prefix, format.file_name()));
+                match format {
+                    PreviewFormat::WebP => previews.webp = file,
+                    PreviewFormat::MP4 => previews.mp4 = file,
+                }
+            }
+            Err(e) => tracing::warn!("Could not generate {:?} preview: {}", format, e),
+        }
+    }
+    if has_audio {
+        match converter.generate_waveform(video_path, duration) {
+            Ok(_) => previews.waveform = Some(format!("{}/{}", remote_prefix, WAVEFORM_FILE)),
+            Err(e) => tracing::warn!("Could not generate waveform: {}", e),
+        }
+    }
+    previews
+}
+
+/// Converts chapters into rows to be saved
+fn to_new_chapters(chapters: &[Chapter], source: ChapterSource, accepted: bool) -> Vec<NewChapter> {
+    chapters
+        .iter()
+        .map(|chapter| NewChapter {
+            start_seconds: chapter.start,
+            end_seconds: chapter.end,
+            title: chapter.title.clone(),
+            source,
+            accepted,
+        })
+        .collect()
+}
 
 impl Runner for HlsStreamRunner {
     type Payload = VideoToStreamPayload;
@@ -19,9 +320,55 @@ impl Runner for HlsStreamRunner {
             "Processing job with runner HlsStreamRunner for video ID {video_id}",
             video_id = payload.video_id,
         );
+        let db = &self.context.db;
+        Video::update_status(db, payload.video_id.clone(), ProcessingStatus::Processing).await?;
 
-        // TODO: Implement video to HLS stream conversion
-        tracing::warn!("HLS stream conversion not implemented");
-        Ok(())
+        match self.transform(&payload.video_id).await {
+            Ok(output) => {
+                Video::set_chapters_path(db, &payload.video_id, output.chapters_path.as_deref())
+                    .await?;
+                Video::set_previews(
+                    db,
+                    &payload.video_id,
+                    output.previews.webp.as_deref(),
+                    output.previews.mp4.as_deref(),
+                    output.previews.waveform.as_deref(),
+                )
+                .await?;
+                Video::set_duration(db, &payload.video_id, output.duration).await?;
+                Video::mark_completed(
+                    db,
+                    &payload.video_id,
+                    &output.master_playlist_path,
+                    output.processed_size as i64,
+                )
+                .await?;
+                tracing::info!("Successfully processed video {}", payload.video_id);
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!("Failed to process video {}: {:?}", payload.video_id, err);
+                // Classify the failure so the creator knows whether to re-upload
+                let (code, message, retryable) = match err.downcast_ref::<VodError>() {
+                    Some(vod_err) => (
+                        vod_err.code(),
+                        vod_err.user_message(),
+                        vod_err.is_retryable(),
+                    ),
+                    None => (
+                        ProcessingError::Internal,
+                        INTERNAL_ERROR_MESSAGE.to_string(),
+                        true,
+                    ),
+                };
+                Video::mark_failed(db, &payload.video_id, code, &message).await?;
+                // Problems with the source will never succeed, so only hand back retryable errors
+                if retryable {
+                    Err(err)
+                } else {
+                    Ok(())
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,421 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::CONTENT_TYPE,
+    redirect::Policy,
+    Client, Url,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{hls_stream::VideoToStreamPayload, Job, Runner, RunnerContext};
+use crate::{
+    db::{
+        quotas::{record_raw_size, reserve_upload, storage_quota, StorageUsage},
+        DBPool, ProcessingError, ProcessingStatus, Video,
+    },
+    error::{ImportError, StorageError, UploadError},
+    storage::Storage,
+    vod::upload::{
+        check_contents, content_type_essence, read_head, upload_stream, UploadFormat, UploadLimits,
+    },
+};
+
+/// Most redirects followed before giving up on an import
+pub const DEFAULT_IMPORT_MAX_REDIRECTS: usize = 5;
+/// Content types hosts send when they don't say what a file is, the URL has to tell instead
+const GENERIC_CONTENT_TYPES: [&str; 3] = ["", "application/octet-stream", "binary/octet-stream"];
+
+#[derive(Deserialize, Serialize)]
+pub struct ImportVideoPayload {
+    pub video_id: String,
+}
+
+/// Limits on what imports can fetch, and from where
+pub struct ImportSettings {
+    pub limits: UploadLimits,
+    pub max_redirects: usize,
+    pub connect_timeout: Duration,
+    /// How long the remote host can go without sending anything
+    pub read_timeout: Duration,
+    /// Lets imports reach loopback and private addresses, only ever for a local server in development
+    pub allow_private_addresses: bool,
+}
+
+impl ImportSettings {
+    /// Gets the import settings from environment configuration, falling back to defaults
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(Duration::from_secs(default))
+        };
+        ImportSettings {
+            limits: UploadLimits::from_env(),
+            max_redirects: std::env::var("IMPORT_MAX_REDIRECTS")
+                .ok()
+                .and_then(|redirects| redirects.parse().ok())
+                .unwrap_or(DEFAULT_IMPORT_MAX_REDIRECTS),
+            connect_timeout: secs("IMPORT_CONNECT_TIMEOUT_SECS", 10),
+            read_timeout: secs("IMPORT_READ_TIMEOUT_SECS", 60),
+            allow_private_addresses: std::env::var("IMPORT_ALLOW_PRIVATE_ADDRESSES")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Checks whether an address is reachable on the public internet, imports can't reach anything else
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network"
+        || a == 0
+        // Carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // Reserved
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Documentation
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        // IPv4 translation, which could reach private IPv4 addresses
+        || (segments[0] == 0x0064 && segments[1] == 0xff9b))
+}
+
+/// Resolves hosts like the system does, leaving out any addresses that aren't public so
+/// imports can't be pointed at internal services
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(ImportError::BlockedAddress(host).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Checks that a URL can be imported from, hosts given by name are checked once they're resolved
+pub fn check_url(url: &str, allow_private_addresses: bool) -> Result<Url, ImportError> {
+    let url = Url::parse(url).map_err(|e| ImportError::InvalidUrl(e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ImportError::InvalidUrl(format!(
+            "{} URLs can't be imported from",
+            url.scheme()
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| ImportError::InvalidUrl("the URL has no host".to_string()))?;
+    // IPv6 hosts keep their brackets
+    let ip = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok();
+    if let Some(ip) = ip {
+        if !allow_private_addresses && !is_public_ip(ip) {
+            return Err(ImportError::BlockedAddress(ip.to_string()));
+        }
+    }
+    Ok(url)
+}
+
+/// Creates the client imports are fetched with, every redirect is checked like the original URL
+pub fn import_client(settings: &ImportSettings) -> Result<Client, reqwest::Error> {
+    let max_redirects = settings.max_redirects;
+    let allow_private_addresses = settings.allow_private_addresses;
+    let redirect_policy = Policy::custom(move |attempt| {
+        if attempt.previous().len() >= max_redirects {
+            let error = ImportError::InvalidUrl(format!("more than {} redirects", max_redirects));
+            return attempt.error(error);
+        }
+        match check_url(attempt.url().as_str(), allow_private_addresses) {
+            Ok(_) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    });
+    // Proxies would resolve hosts themselves, getting around the address checks
+    let builder = Client::builder()
+        .no_proxy()
+        .redirect(redirect_policy)
+        .connect_timeout(settings.connect_timeout)
+        .read_timeout(settings.read_timeout);
+    let builder = match allow_private_addresses {
+        true => builder,
+        false => builder.dns_resolver(Arc::new(PublicResolver)),
+    };
+    builder.build()
+}
+
+/// Turns a failed request into an import error, keeping the reason for blocked addresses and
+/// redirects that were refused
+fn request_error(error: reqwest::Error) -> ImportError {
+    let mut source = std::error::Error::source(&error);
+    while let Some(cause) = source {
+        match cause.downcast_ref::<ImportError>() {
+            Some(ImportError::BlockedAddress(host)) => {
+                return ImportError::BlockedAddress(host.clone())
+            }
+            Some(ImportError::InvalidUrl(reason)) => {
+                return ImportError::InvalidUrl(reason.clone())
+            }
+            _ => {}
+        }
+        source = cause.source();
+    }
+    ImportError::Request(error.to_string())
+}
+
+/// Gets how many bytes the owner of a video has left, not counting what the video itself holds
+async fn remaining_quota(db: &DBPool, video: &Video) -> Result<Option<u64>> {
+    let Some(quota) = storage_quota(video.user_id, db).await? else {
+        return Ok(None);
+    };
+    let usage = StorageUsage::by_user_id(video.user_id, db).await?;
+    let used: i64 = usage
+        .videos
+        .iter()
+        .filter(|usage| usage.video_id != video.id)
+        .map(|usage| usage.total_bytes())
+        .sum();
+    Ok(Some(quota.saturating_sub(used).max(0) as u64))
+}
+
+/// Downloads the raw source of an imported video into storage, returning its size
+/// The download is streamed straight into a multipart upload, and anything left of it is removed
+/// again if it fails
+pub async fn import_video(
+    db: &DBPool,
+    storage: &dyn Storage,
+    client: &Client,
+    settings: &ImportSettings,
+    video: &Video,
+) -> Result<u64> {
+    let url = video
+        .import_url
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("Video {} has no import URL", video.id))?;
+    let url = check_url(url, settings.allow_private_addresses)?;
+    let response = client.get(url).send().await.map_err(request_error)?;
+    if !response.status().is_success() {
+        return Err(ImportError::Status(response.status().as_u16()).into());
+    }
+
+    // Trust the content type when it names a format, and the URL when it's too generic to tell
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let format = match UploadFormat::from_content_type(&content_type) {
+        Some(format) => format,
+        None if GENERIC_CONTENT_TYPES.contains(&content_type_essence(&content_type).as_str()) => {
+            UploadFormat::from_key(response.url().path()).ok_or_else(|| {
+                ImportError::from(UploadError::UnsupportedExtension(
+                    response.url().path().to_string(),
+                ))
+            })?
+        }
+        None => {
+            return Err(ImportError::from(UploadError::UnsupportedContentType(content_type)).into())
+        }
+    };
+
+    // Refuse anything too big up front when the host says how big it is,
+    // otherwise the download is cut off once it gets there
+    let remaining = remaining_quota(db, video).await?;
+    if let Some(size) = response.content_length() {
+        settings
+            .limits
+            .check_size(size)
+            .map_err(ImportError::from)?;
+        if let Some(remaining) = remaining.filter(|remaining| size > *remaining) {
+            return Err(ImportError::OverQuota(remaining).into());
+        }
+    }
+    let max_bytes = remaining.map_or(settings.limits.max_size_bytes, |remaining| {
+        remaining.min(settings.limits.max_size_bytes)
+    });
+
+    // The raw video is stored with the extension of its format, whatever the URL was guessed as
+    let key = format!("{}/raw.{}", video.storage_prefix(), format.extension());
+    if key != video.raw_video_path {
+        Video::set_raw_video_path(db, &video.id, &key).await?;
+    }
+    let upload_id = storage
+        .create_multipart_upload(&key, Some(format.content_type()))
+        .await
+        .map_err(|e| ImportError::from(UploadError::from(e)))?;
+    // Hold the space the host said it needs, in case another upload took it since it was checked
+    let declared_size = response.content_length().unwrap_or_default() as i64;
+    if !reserve_upload(db, video.user_id, &video.id, &upload_id, declared_size).await? {
+        if let Err(e) = storage.abort_multipart_upload(&key, &upload_id).await {
+            tracing::warn!("Could not abort import upload {}: {}", upload_id, e);
+        }
+        return Err(ImportError::OverQuota(remaining.unwrap_or_default()).into());
+    }
+
+    let stored = async {
+        let size = upload_stream(
+            storage,
+            &key,
+            &upload_id,
+            response.bytes_stream(),
+            max_bytes,
+        )
+        .await?;
+        // Make sure the contents match the format it was accepted as
+        let head = read_head(storage, &key).await?;
+        check_contents(format, &head)?;
+        Ok::<u64, UploadError>(size)
+    }
+    .await;
+    let size = match stored {
+        Ok(size) => size,
+        Err(e) => {
+            match storage.abort_multipart_upload(&key, &upload_id).await {
+                Ok(()) | Err(StorageError::NotFound(_)) => {}
+                Err(e) => tracing::warn!("Could not abort import upload {}: {}", upload_id, e),
+            }
+            if let Err(e) = storage.delete_prefix(&key).await {
+                tracing::warn!("Could not delete failed import {}: {}", key, e);
+            }
+            Video::set_upload_id(db, &video.id, None).await?;
+            return Err(ImportError::from(e).into());
+        }
+    };
+    // Other uploads of the owner could have used the space up while this one was downloading
+    if !record_raw_size(db, video.user_id, &video.id, size as i64).await? {
+        if let Err(e) = storage.delete_prefix(&key).await {
+            tracing::warn!("Could not delete over quota import {}: {}", key, e);
+        }
+        Video::set_upload_id(db, &video.id, None).await?;
+        return Err(ImportError::OverQuota(remaining.unwrap_or_default()).into());
+    }
+    Ok(size)
+}
+
+pub struct ImportVideoRunner {
+    context: Arc<RunnerContext>,
+}
+
+impl ImportVideoRunner {
+    pub fn new(context: Arc<RunnerContext>) -> Self {
+        ImportVideoRunner { context }
+    }
+}
+
+impl Runner for ImportVideoRunner {
+    type Payload = ImportVideoPayload;
+
+    /// Fetches a video from another host, then hands it off to be processed
+    async fn process_job(&self, payload: Self::Payload) -> Result<()> {
+        tracing::debug!(
+            "Processing job with runner ImportVideoRunner for video ID {video_id}",
+            video_id = payload.video_id,
+        );
+        let db = &self.context.db;
+        let video = match Video::by_id(db, &payload.video_id).await {
+            Ok(video) => video,
+            Err(sqlx::Error::RowNotFound) => {
+                tracing::debug!("Video {} was deleted before importing", payload.video_id);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // A redelivered job can find the source already imported
+        if video.raw_size_bytes.is_none() {
+            Video::update_status(db, payload.video_id.clone(), ProcessingStatus::Pending).await?;
+            let settings = ImportSettings::from_env();
+            let client = import_client(&settings)?;
+            match import_video(
+                db,
+                self.context.storage.as_ref(),
+                &client,
+                &settings,
+                &video,
+            )
+            .await
+            {
+                Ok(size) => tracing::info!("Imported {} bytes for video {}", size, video.id),
+                Err(err) => {
+                    tracing::error!("Failed to import video {}: {:?}", video.id, err);
+                    // Classify the failure so the creator knows whether to try another link
+                    let (code, message, retryable) = match err.downcast_ref::<ImportError>() {
+                        Some(import_err) => (
+                            import_err.code(),
+                            import_err.user_message(),
+                            import_err.is_retryable(),
+                        ),
+                        None => (
+                            ProcessingError::Internal,
+                            "Something went wrong on our end while importing this video. The import will be retried.".to_string(),
+                            true,
+                        ),
+                    };
+                    Video::mark_failed(db, &video.id, code, &message).await?;
+                    return if retryable { Err(err) } else { Ok(()) };
+                }
+            }
+        }
+
+        // Process the imported source like any other upload
+        let job_queue = self
+            .context
+            .job_queue
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No job queue to hand video {} off to", video.id))?;
+        job_queue
+            .enqueue(Job::from(VideoToStreamPayload { video_id: video.id }))
+            .await?;
+        Ok(())
+    }
+}
@@ -1,16 +1,93 @@
+pub mod analytics;
+pub mod cleanup;
+pub mod gc;
 pub mod hls_stream;
+pub mod import;
 pub mod queue;
+pub mod tiering;
+pub mod workdir;
 
+use std::{sync::Arc, time::Duration};
+
+use analytics::{AggregateViewStatsPayload, AggregateViewStatsRunner};
 use anyhow::Result;
 use async_nats::Message;
-use hls_stream::HlsStreamRunner;
+use cleanup::{StorageCleanupPayload, StorageCleanupRunner};
+use gc::{GarbageCollectPayload, GarbageCollectRunner};
+use hls_stream::{HlsStreamRunner, VideoToStreamPayload};
+use import::{ImportVideoPayload, ImportVideoRunner};
 pub use queue::Queue;
 use serde::de::DeserializeOwned;
+use tiering::{TierRawSourcesPayload, TierRawSourcesRunner};
+use workdir::WorkDir;
+
+use crate::{
+    db::{connect_to_database, DBPool},
+    event::{JOB_PREFIX, MESSAGE_PREFIX},
+    storage::{storage_from_env, tier::ColdStorage, Storage},
+};
+
+/// Shared dependencies that runners need to process jobs
+pub struct RunnerContext {
+    pub db: DBPool,
+    pub storage: Arc<dyn Storage>,
+    pub cold_storage: ColdStorage,
+    pub work_dir: WorkDir,
+    /// Where runners queue follow up jobs, like processing a video once it's imported
+    pub job_queue: Option<Queue>,
+}
+
+impl RunnerContext {
+    /// Creates the runner context from environment configuration
+    pub async fn from_env() -> Result<Self> {
+        let db = connect_to_database().await?;
+        let storage = storage_from_env().await?;
+        let cold_storage = ColdStorage::from_env(storage.clone()).await?;
+        let work_dir = WorkDir::from_env()?;
+
+        Ok(RunnerContext {
+            db,
+            storage,
+            cold_storage,
+            work_dir,
+            job_queue: None,
+        })
+    }
+    /// Lets runners queue follow up jobs
+    pub fn with_job_queue(self, job_queue: Queue) -> Self {
+        RunnerContext {
+            job_queue: Some(job_queue),
+            ..self
+        }
+    }
+}
+
+/// Queues a job at a fixed interval, lined up with the wall clock so every job runner replica
+/// queues it at the same moment
+/// The jobs of one interval share a message id, so NATS only keeps the one queued first
+pub async fn schedule_job(queue: Queue, every: Duration, make_job: impl Fn() -> Job) {
+    let every_secs = every.as_secs().max(1);
+    loop {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        // Wait for the start of the next interval
+        let slot = now.as_secs() / every_secs + 1;
+        tokio::time::sleep(Duration::from_secs(slot * every_secs).saturating_sub(now)).await;
+        let job = make_job();
+        let subject = job.get_subject();
+        let message_id = format!("{}.{}", subject, slot);
+        match queue.enqueue_once(job, &message_id).await {
+            Ok(()) => tracing::debug!("Queued scheduled job {}", message_id),
+            Err(e) => tracing::error!("Failed to queue scheduled job {}: {}", subject, e),
+        }
+    }
+}
 
 /// Creates the appropriate runner based on the subject, then runs it
-pub async fn process_message(message: &Message) -> Result<()> {
+pub async fn process_message(message: &Message, context: Arc<RunnerContext>) -> Result<()> {
     let subject = message.subject.as_str();
-    let runner = RunnerType::from_subject(subject)?;
+    let runner = RunnerType::from_subject(subject, context)?;
     runner.run(message).await
 }
 
@@ -36,14 +113,36 @@ pub(crate) trait Runner: Send + Sync + 'static {
 /// Represents the different types of runners that can be used in the application
 pub enum RunnerType {
     TransformVideo(HlsStreamRunner),
+    CleanupStorage(StorageCleanupRunner),
+    GarbageCollect(GarbageCollectRunner),
+    TierRawSources(TierRawSourcesRunner),
+    ImportVideo(ImportVideoRunner),
+    AggregateViewStats(AggregateViewStatsRunner),
 }
 
 impl RunnerType {
     /// Creates a new runner from a subject
-    pub fn from_subject(subject: &str) -> Result<Self> {
+    pub fn from_subject(subject: &str, context: Arc<RunnerContext>) -> Result<Self> {
         tracing::debug!("Creating runner for subject: {}", subject);
         match subject {
-            "farmhand.jobs.video_to_stream" => Ok(RunnerType::TransformVideo(HlsStreamRunner)),
+            "farmhand.jobs.video_to_stream" => {
+                Ok(RunnerType::TransformVideo(HlsStreamRunner::new(context)))
+            }
+            "farmhand.jobs.storage_cleanup" => Ok(RunnerType::CleanupStorage(
+                StorageCleanupRunner::new(context),
+            )),
+            "farmhand.jobs.garbage_collect" => Ok(RunnerType::GarbageCollect(
+                GarbageCollectRunner::new(context),
+            )),
+            "farmhand.jobs.tier_raw_sources" => Ok(RunnerType::TierRawSources(
+                TierRawSourcesRunner::new(context),
+            )),
+            "farmhand.jobs.import_video" => {
+                Ok(RunnerType::ImportVideo(ImportVideoRunner::new(context)))
+            }
+            "farmhand.jobs.aggregate_view_stats" => Ok(RunnerType::AggregateViewStats(
+                AggregateViewStatsRunner::new(context),
+            )),
             _ => Err(anyhow::anyhow!("{} has no runner associated", subject)),
         }
     }
@@ -51,6 +150,92 @@ impl RunnerType {
     pub async fn run(&self, message: &Message) -> Result<()> {
         match self {
             RunnerType::TransformVideo(runner) => runner.run(message).await,
+            RunnerType::CleanupStorage(runner) => runner.run(message).await,
+            RunnerType::GarbageCollect(runner) => runner.run(message).await,
+            RunnerType::TierRawSources(runner) => runner.run(message).await,
+            RunnerType::ImportVideo(runner) => runner.run(message).await,
+            RunnerType::AggregateViewStats(runner) => runner.run(message).await,
+        }
+    }
+}
+
+/// Represents jobs we publish to the job queue
+/// Primarily used to get the appropriate subject name for a job
+pub enum Job {
+    VideoToStream(VideoToStreamPayload),
+    StorageCleanup(StorageCleanupPayload),
+    GarbageCollect(GarbageCollectPayload),
+    TierRawSources(TierRawSourcesPayload),
+    ImportVideo(ImportVideoPayload),
+    AggregateViewStats(AggregateViewStatsPayload),
+}
+
+impl Job {
+    pub fn get_subject(&self) -> String {
+        match self {
+            // farmhand.jobs.video_to_stream
+            Job::VideoToStream(_) => format!("{}.{}.video_to_stream", MESSAGE_PREFIX, JOB_PREFIX),
+            // farmhand.jobs.storage_cleanup
+            Job::StorageCleanup(_) => format!("{}.{}.storage_cleanup", MESSAGE_PREFIX, JOB_PREFIX),
+            // farmhand.jobs.garbage_collect
+            Job::GarbageCollect(_) => format!("{}.{}.garbage_collect", MESSAGE_PREFIX, JOB_PREFIX),
+            // farmhand.jobs.tier_raw_sources
+            Job::TierRawSources(_) => {
+                format!("{}.{}.tier_raw_sources", MESSAGE_PREFIX, JOB_PREFIX)
+            }
+            // farmhand.jobs.import_video
+            Job::ImportVideo(_) => format!("{}.{}.import_video", MESSAGE_PREFIX, JOB_PREFIX),
+            // farmhand.jobs.aggregate_view_stats
+            Job::AggregateViewStats(_) => {
+                format!("{}.{}.aggregate_view_stats", MESSAGE_PREFIX, JOB_PREFIX)
+            }
         }
     }
+    /// Serializes the job payload for publishing
+    pub fn get_payload(&self) -> Result<String, serde_json::Error> {
+        match self {
+            Job::VideoToStream(payload) => serde_json::to_string(payload),
+            Job::StorageCleanup(payload) => serde_json::to_string(payload),
+            Job::GarbageCollect(payload) => serde_json::to_string(payload),
+            Job::TierRawSources(payload) => serde_json::to_string(payload),
+            Job::ImportVideo(payload) => serde_json::to_string(payload),
+            Job::AggregateViewStats(payload) => serde_json::to_string(payload),
+        }
+    }
+}
+
+impl From<VideoToStreamPayload> for Job {
+    fn from(payload: VideoToStreamPayload) -> Self {
+        Job::VideoToStream(payload)
+    }
+}
+
+impl From<StorageCleanupPayload> for Job {
+    fn from(payload: StorageCleanupPayload) -> Self {
+        Job::StorageCleanup(payload)
+    }
+}
+
+impl From<GarbageCollectPayload> for Job {
+    fn from(payload: GarbageCollectPayload) -> Self {
+        Job::GarbageCollect(payload)
+    }
+}
+
+impl From<TierRawSourcesPayload> for Job {
+    fn from(payload: TierRawSourcesPayload) -> Self {
+        Job::TierRawSources(payload)
+    }
+}
+
+impl From<ImportVideoPayload> for Job {
+    fn from(payload: ImportVideoPayload) -> Self {
+        Job::ImportVideo(payload)
+    }
+}
+
+impl From<AggregateViewStatsPayload> for Job {
+    fn from(payload: AggregateViewStatsPayload) -> Self {
+        Job::AggregateViewStats(payload)
+    }
 }
@@ -2,12 +2,14 @@ use async_nats::{
     jetstream::{
         self,
         consumer::{pull::Config, Consumer},
+        context::Publish,
         stream::RetentionPolicy,
         Context,
     },
     Client,
 };
 
+use super::Job;
 use crate::{error::QueueError, event::JOB_STREAM};
 
 #[allow(dead_code)]
@@ -96,4 +98,31 @@ impl Queue {
 
         Ok(())
     }
+    /// Publishes a job to the queue under its subject
+    pub async fn enqueue(&self, job: Job) -> Result<(), QueueError> {
+        let payload = job
+            .get_payload()
+            .map_err(|e| QueueError::InvalidPayload(e.to_string()))?;
+        self.publish(job.get_subject(), payload).await
+    }
+    /// Publishes a job under a message id, which NATS drops if the same id was published within
+    /// the streams duplicate window
+    pub async fn enqueue_once(&self, job: Job, message_id: &str) -> Result<(), QueueError> {
+        let payload = job
+            .get_payload()
+            .map_err(|e| QueueError::InvalidPayload(e.to_string()))?;
+        let subject = job.get_subject();
+        tracing::debug!("Publishing message {} to subject {}", message_id, subject);
+        self.jetstream
+            .send_publish(
+                subject,
+                Publish::build()
+                    .payload(payload.into())
+                    .message_id(message_id),
+            )
+            .await
+            .map_err(|e| QueueError::InvalidConnection(e.to_string()))?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{workdir::WorkDir, Runner, RunnerContext};
+use crate::{
+    db::{DBPool, Video},
+    storage::{
+        tier::{copy_object, ColdStorage},
+        Storage,
+    },
+};
+
+/// Most raw sources moved to cold storage in a single run
+pub const DEFAULT_TIERING_BATCH_SIZE: i64 = 100;
+
+fn default_batch_size() -> i64 {
+    DEFAULT_TIERING_BATCH_SIZE
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TierRawSourcesPayload {
+    /// Most raw sources to move in this run, the rest are left for the next one
+    #[serde(default = "default_batch_size")]
+    pub batch_size: i64,
+}
+
+impl Default for TierRawSourcesPayload {
+    fn default() -> Self {
+        TierRawSourcesPayload {
+            batch_size: DEFAULT_TIERING_BATCH_SIZE,
+        }
+    }
+}
+
+/// Everything a tiering run moved, and what it couldn't
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TieringReport {
+    /// Videos whose raw source was moved to cold storage
+    pub moved: Vec<String>,
+    pub moved_bytes: u64,
+    /// Videos that started processing again while their source was being moved
+    pub skipped: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Moves a single raw source to cold storage, returning its size
+/// Returns None, leaving the source in hot storage, when it's needed again before the move is done
+async fn move_to_cold(
+    db: &DBPool,
+    storage: &dyn Storage,
+    cold_storage: &ColdStorage,
+    work_dir: &WorkDir,
+    video: &Video,
+) -> Result<Option<u64>> {
+    let hot_path = &video.raw_video_path;
+    let cold_path = cold_storage.key_for(hot_path);
+
+    // The source passes through the work directory on its way between storages
+    let raw_size = video.raw_size_bytes.unwrap_or_default().max(0) as u64;
+    work_dir.ensure_free_space(raw_size)?;
+    let scratch_dir = work_dir.scratch_dir(&format!("{}_cold", video.id))?;
+    let copied = copy_object(
+        storage,
+        hot_path,
+        cold_storage.storage.as_ref(),
+        &cold_path,
+        scratch_dir.path(),
+    )
+    .await;
+    scratch_dir.remove()?;
+    let size = copied?;
+
+    // Only drop the hot copy once the row points at the cold one
+    if !Video::set_raw_cold(db, &video.id, &cold_path).await? {
+        cold_storage.storage.delete_prefix(&cold_path).await?;
+        return Ok(None);
+    }
+    storage.delete_prefix(hot_path).await?;
+    Ok(Some(size))
+}
+
+/// Moves the raw sources that haven't been needed for as long as their uploaders role allows
+/// to cold storage
+pub async fn tier_raw_sources(
+    db: &DBPool,
+    storage: &dyn Storage,
+    cold_storage: &ColdStorage,
+    work_dir: &WorkDir,
+    payload: &TierRawSourcesPayload,
+) -> Result<TieringReport> {
+    let mut report = TieringReport::default();
+    let videos = Video::due_for_cold_storage(db, payload.batch_size).await?;
+    // Keep going when one fails, the next run picks it up again
+    for video in &videos {
+        match move_to_cold(db, storage, cold_storage, work_dir, video).await {
+            Ok(Some(size)) => {
+                report.moved.push(video.id.clone());
+                report.moved_bytes += size;
+            }
+            Ok(None) => report.skipped.push(video.id.clone()),
+            Err(e) => report.errors.push(format!(
+                "Failed to move raw video of {} to cold storage: {}",
+                video.id, e
+            )),
+        }
+    }
+    Ok(report)
+}
+
+/// Moves raw sources nobody has needed in a while out of hot storage
+pub struct TierRawSourcesRunner {
+    context: Arc<RunnerContext>,
+}
+
+impl TierRawSourcesRunner {
+    pub fn new(context: Arc<RunnerContext>) -> Self {
+        TierRawSourcesRunner { context }
+    }
+}
+
+impl Runner for TierRawSourcesRunner {
+    type Payload = TierRawSourcesPayload;
+
+    async fn process_job(&self, payload: Self::Payload) -> Result<()> {
+        tracing::debug!(
+            "Processing job with runner TierRawSourcesRunner, batch size: {batch_size}",
+            batch_size = payload.batch_size,
+        );
+        let report = tier_raw_sources(
+            &self.context.db,
+            self.context.storage.as_ref(),
+            &self.context.cold_storage,
+            &self.context.work_dir,
+            &payload,
+        )
+        .await?;
+        tracing::info!("Tiering report: {}", serde_json::to_string(&report)?);
+        for error in &report.errors {
+            tracing::warn!("{}", error);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use uuid::Uuid;
+
+use crate::{
+    error::WorkDirError,
+    vod::{DownloadSettings, Vod},
+};
+
+/// Directory each job gets its scratch directory in
+const JOBS_DIR: &str = "jobs";
+/// Directory raw sources are cached in
+const CACHE_DIR: &str = "cache";
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Where runners work and how much of the disk they may use
+#[derive(Debug, Clone)]
+pub struct WorkDirSettings {
+    pub root: PathBuf,
+    /// Space that always has to be left free on the disk, jobs aren't accepted below it
+    pub min_free_bytes: u64,
+    /// Most space cached raw sources may take up before the least recently used are evicted
+    pub cache_max_bytes: u64,
+}
+
+impl WorkDirSettings {
+    /// Reads settings from WORK_DIR, WORK_DIR_MIN_FREE_BYTES and RAW_CACHE_MAX_BYTES
+    pub fn from_env() -> Self {
+        let bytes = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .map(|value| {
+                    value
+                        .parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number", name))
+                })
+                .unwrap_or(default)
+        };
+        WorkDirSettings {
+            root: PathBuf::from(std::env::var("WORK_DIR").unwrap_or_else(|_| "work".to_string())),
+            min_free_bytes: bytes("WORK_DIR_MIN_FREE_BYTES", 10 * GIB),
+            cache_max_bytes: bytes("RAW_CACHE_MAX_BYTES", 50 * GIB),
+        }
+    }
+}
+
+/// A raw source in the cache
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+    /// How many jobs are using the source right now, pinned sources are never evicted
+    pins: usize,
+}
+
+type Cache = Arc<Mutex<HashMap<String, CacheEntry>>>;
+
+/// Picks the least recently used sources to evict until the cache fits in the cap
+/// Sources in use are skipped, so the cache can stay over the cap while they are
+fn select_evictions(entries: &HashMap<String, CacheEntry>, max_bytes: u64) -> Vec<String> {
+    let mut total: u64 = entries.values().map(|entry| entry.size).sum();
+    let mut candidates: Vec<(&String, &CacheEntry)> = entries
+        .iter()
+        .filter(|(_, entry)| entry.pins == 0)
+        .collect();
+    candidates.sort_by_key(|(_, entry)| entry.last_used);
+
+    let mut evictions = Vec::new();
+    for (video_id, entry) in candidates {
+        if total <= max_bytes {
+            break;
+        }
+        total -= entry.size;
+        evictions.push(video_id.clone());
+    }
+    evictions
+}
+
+/// Adds up the size of every file in a directory, along with when one was last changed
+fn dir_usage(path: &Path) -> (u64, SystemTime) {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .fold(
+            (0, SystemTime::UNIX_EPOCH),
+            |(size, last_used), metadata| {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                (size + metadata.len(), last_used.max(modified))
+            },
+        )
+}
+
+/// A directory a single job writes its output to
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Removes the directory and everything in it, once its output is safely uploaded
+    pub fn remove(self) -> std::io::Result<()> {
+        std::fs::remove_dir_all(&self.path)
+    }
+}
+
+impl Drop for ScratchDir {
+    /// Cleans up after jobs that failed before removing their directory themselves
+    fn drop(&mut self) {
+        match std::fs::remove_dir_all(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove scratch directory {:?}: {}", self.path, e),
+        }
+    }
+}
+
+/// A raw source in the cache, kept from being evicted for as long as this is held
+pub struct CachedRaw {
+    path: PathBuf,
+    video_id: String,
+    cache: Cache,
+}
+
+impl CachedRaw {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CachedRaw {
+    fn drop(&mut self) {
+        if let Ok(mut cache) = self.cache.lock() {
+            if let Some(entry) = cache.get_mut(&self.video_id) {
+                entry.pins = entry.pins.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Manages the local directory runners download sources to and write output in
+pub struct WorkDir {
+    settings: WorkDirSettings,
+    cache: Cache,
+}
+
+impl WorkDir {
+    /// Sets up the work directory, clearing out scratch directories left by earlier runs
+    /// and picking up raw sources that are already cached
+    pub fn new(settings: WorkDirSettings) -> Result<Self, WorkDirError> {
+        let jobs_dir = settings.root.join(JOBS_DIR);
+        let cache_dir = settings.root.join(CACHE_DIR);
+        if jobs_dir.is_dir() {
+            tracing::debug!("Clearing leftover scratch directories in {:?}", jobs_dir);
+            std::fs::remove_dir_all(&jobs_dir)?;
+        }
+        std::fs::create_dir_all(&jobs_dir)?;
+        std::fs::create_dir_all(&cache_dir)?;
+
+        let mut entries = HashMap::new();
+        for entry in std::fs::read_dir(&cache_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let (size, last_used) = dir_usage(&entry.path());
+            entries.insert(
+                entry.file_name().to_string_lossy().to_string(),
+                CacheEntry {
+                    size,
+                    last_used,
+                    pins: 0,
+                },
+            );
+        }
+        tracing::debug!("Found {} cached raw sources", entries.len());
+
+        Ok(WorkDir {
+            settings,
+            cache: Arc::new(Mutex::new(entries)),
+        })
+    }
+    /// Creates the work directory from environment configuration
+    pub fn from_env() -> Result<Self, WorkDirError> {
+        WorkDir::new(WorkDirSettings::from_env())
+    }
+    fn lock_cache(&self) -> MutexGuard<'_, HashMap<String, CacheEntry>> {
+        // A panic while holding the lock can't leave the map half updated, so keep using it
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn evict(&self, cache: &mut HashMap<String, CacheEntry>, video_id: &str) {
+        let path = self.settings.root.join(CACHE_DIR).join(video_id);
+        tracing::debug!("Evicting cached raw source {:?}", path);
+        match std::fs::remove_dir_all(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to evict {:?}: {}", path, e),
+        }
+        cache.remove(video_id);
+    }
+    /// Gets how much space is available on the disk the work directory is on
+    pub fn available_space(&self) -> Result<u64, WorkDirError> {
+        Ok(fs2::available_space(&self.settings.root)?)
+    }
+    /// Makes sure the disk has room for the given bytes on top of the space that's kept free,
+    /// evicting cached sources that aren't in use when it doesn't
+    pub fn ensure_free_space(&self, needed: u64) -> Result<(), WorkDirError> {
+        let needed = needed.saturating_add(self.settings.min_free_bytes);
+        let mut cache = self.lock_cache();
+        loop {
+            let available = self.available_space()?;
+            if available >= needed {
+                return Ok(());
+            }
+            // Evicting everything that isn't in use is the most that can be done
+            let Some(video_id) = select_evictions(&cache, 0).into_iter().next() else {
+                return Err(WorkDirError::InsufficientSpace { needed, available });
+            };
+            self.evict(&mut cache, &video_id);
+        }
+    }
+    /// Creates an empty scratch directory for a job on the given video
+    /// Every attempt gets a directory of its own, so a redelivered job can't write over one
+    /// that's still running
+    pub fn scratch_dir(&self, video_id: &str) -> Result<ScratchDir, WorkDirError> {
+        let path =
+            self.settings
+                .root
+                .join(JOBS_DIR)
+                .join(format!("{}-{}", video_id, Uuid::new_v4()));
+        std::fs::create_dir_all(&path)?;
+        Ok(ScratchDir { path })
+    }
+    /// Gets the raw source of a video from the cache, downloading it when it isn't there yet
+    /// The least recently used sources are evicted afterwards if the cache has grown too big
+    pub async fn raw_source(
+        &self,
+        vod: &Vod,
+        download_settings: DownloadSettings<'_>,
+    ) -> Result<CachedRaw, anyhow::Error> {
+        let video_id = vod.video.id.clone();
+        {
+            let mut cache = self.lock_cache();
+            let entry = cache.entry(video_id.clone()).or_insert(CacheEntry {
+                size: 0,
+                last_used: SystemTime::now(),
+                pins: 0,
+            });
+            entry.pins += 1;
+        }
+        // Unpins the source again if anything below fails
+        let mut cached = CachedRaw {
+            path: PathBuf::new(),
+            video_id: video_id.clone(),
+            cache: self.cache.clone(),
+        };
+
+        let cache_dir = self.settings.root.join(CACHE_DIR);
+        cached.path = vod
+            .get_raw_video(cache_dir, Some(download_settings))
+            .await?
+            .ok_or_else(|| anyhow!("No raw video path found"))?;
+
+        // Mark the source as used, the modified time is what recency is rebuilt from on restart
+        let now = SystemTime::now();
+        let file = std::fs::File::options().write(true).open(&cached.path)?;
+        file.set_modified(now)?;
+        let size = file.metadata()?.len();
+
+        let mut cache = self.lock_cache();
+        if let Some(entry) = cache.get_mut(&video_id) {
+            entry.size = size;
+            entry.last_used = now;
+        }
+        for evicted in select_evictions(&cache, self.settings.cache_max_bytes) {
+            self.evict(&mut cache, &evicted);
+        }
+        drop(cache);
+
+        Ok(cached)
+    }
+}
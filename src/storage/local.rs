@@ -0,0 +1,423 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use super::{MultipartUpload, ObjectMeta, ObjectReader, PutOptions, Storage, UploadedPart};
+use crate::error::StorageError;
+
+/// Directory under the root holding object metadata, mirroring the object keys
+const META_DIR: &str = ".meta";
+/// Directory under the root holding in progress multipart uploads
+const UPLOADS_DIR: &str = ".uploads";
+
+/// Metadata stored next to an object, since the filesystem can't hold it
+#[derive(Serialize, Deserialize, Default)]
+struct StoredMeta {
+    content_type: Option<String>,
+    cache_control: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredUpload {
+    key: String,
+    content_type: Option<String>,
+    initiated: DateTime<Utc>,
+}
+
+/// Storage backed by a directory on the local filesystem
+/// Presigned URLs aren't supported, so clients can't upload or download directly
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(LocalStorage { root })
+    }
+
+    /// Resolves a key to a path under the root, rejecting keys that could escape it
+    fn object_path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let path = Path::new(key);
+        let is_safe = !key.is_empty()
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            && !key.starts_with('.');
+        if !is_safe {
+            return Err(StorageError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(path))
+    }
+
+    fn meta_path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        self.object_path(key)?;
+        Ok(self.root.join(META_DIR).join(format!("{}.json", key)))
+    }
+
+    fn upload_dir(&self, upload_id: &str) -> Result<PathBuf, StorageError> {
+        if upload_id.is_empty()
+            || !upload_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(StorageError::InvalidKey(upload_id.to_string()));
+        }
+        Ok(self.root.join(UPLOADS_DIR).join(upload_id))
+    }
+
+    async fn write_meta(&self, key: &str, options: &PutOptions) -> Result<(), StorageError> {
+        let meta_path = self.meta_path(key)?;
+        if let Some(parent) = meta_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let meta = StoredMeta {
+            content_type: options.content_type.clone(),
+            cache_control: options.cache_control.clone(),
+            metadata: options.metadata.clone(),
+        };
+        let json = serde_json::to_vec(&meta).map_err(|e| StorageError::Backend(e.to_string()))?;
+        tokio::fs::write(meta_path, json).await?;
+        Ok(())
+    }
+
+    async fn read_meta(&self, key: &str) -> StoredMeta {
+        let Ok(meta_path) = self.meta_path(key) else {
+            return StoredMeta::default();
+        };
+        tokio::fs::read(meta_path)
+            .await
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes to a temporary file first, so readers never see half written objects
+    async fn write_object(&self, key: &str, body: &[u8]) -> Result<(), StorageError> {
+        let path = self.object_path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp_path = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        tokio::fs::write(&temp_path, body).await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+        Ok(())
+    }
+
+    /// Removes directories left empty after deleting a file, up to the given root
+    async fn prune_empty_dirs(&self, path: &Path, root: &Path) {
+        let mut current = path.parent();
+        while let Some(dir) = current {
+            if dir == root || tokio::fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+            current = dir.parent();
+        }
+    }
+
+    async fn read_upload(&self, upload_id: &str) -> Result<StoredUpload, StorageError> {
+        let json = tokio::fs::read(self.upload_dir(upload_id)?.join("upload.json"))
+            .await
+            .map_err(|_| StorageError::NotFound(upload_id.to_string()))?;
+        serde_json::from_slice(&json).map_err(|e| StorageError::Backend(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, body: Bytes, options: &PutOptions) -> Result<(), StorageError> {
+        self.write_object(key, &body).await?;
+        self.write_meta(key, options).await
+    }
+
+    async fn put_file(
+        &self,
+        key: &str,
+        path: &Path,
+        options: &PutOptions,
+    ) -> Result<(), StorageError> {
+        let target = self.object_path(key)?;
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(path, &target).await?;
+        self.write_meta(key, options).await
+    }
+
+    async fn get(&self, key: &str) -> Result<ObjectReader, StorageError> {
+        let file = tokio::fs::File::open(self.object_path(key)?)
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => StorageError::NotFound(key.to_string()),
+                _ => StorageError::Io(e),
+            })?;
+        Ok(Box::pin(file))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let metadata = tokio::fs::metadata(self.object_path(key)?)
+            .await
+            .map_err(|_| StorageError::NotFound(key.to_string()))?;
+        if !metadata.is_file() {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+        let meta = self.read_meta(key).await;
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: metadata.len(),
+            content_type: meta.content_type,
+            etag: None,
+            last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+            metadata: meta.metadata,
+        })
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64, StorageError> {
+        let objects = self.list(prefix).await?;
+        for object in &objects {
+            let path = self.object_path(&object.key)?;
+            tokio::fs::remove_file(&path).await?;
+            self.prune_empty_dirs(&path, &self.root).await;
+            let meta_path = self.meta_path(&object.key)?;
+            if tokio::fs::remove_file(&meta_path).await.is_ok() {
+                self.prune_empty_dirs(&meta_path, &self.root.join(META_DIR))
+                    .await;
+            }
+        }
+        Ok(objects.len() as u64)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        // Only walk the deepest directory the prefix is sure to be in
+        let search_dir = match prefix.rfind('/') {
+            Some(index) => self.object_path(&prefix[..index])?,
+            None => self.root.clone(),
+        };
+        if !search_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut objects = Vec::new();
+        for entry in walkdir::WalkDir::new(&search_dir)
+            .into_iter()
+            .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|e| e.ok())
+        {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(&self.root) else {
+                continue;
+            };
+            let key = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            let metadata = entry
+                .metadata()
+                .map_err(|e| StorageError::Backend(e.to_string()))?;
+            objects.push(ObjectMeta {
+                key,
+                size: metadata.len(),
+                last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+                ..Default::default()
+            });
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    async fn presign_get(&self, _key: &str, _expires_in: Duration) -> Result<String, StorageError> {
+        Err(StorageError::Unsupported(
+            "presigned downloads from local storage",
+        ))
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+    ) -> Result<String, StorageError> {
+        self.object_path(key)?;
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let upload_dir = self.upload_dir(&upload_id)?;
+        tokio::fs::create_dir_all(&upload_dir).await?;
+        let upload = StoredUpload {
+            key: key.to_string(),
+            content_type: content_type.map(str::to_string),
+            initiated: Utc::now(),
+        };
+        let json = serde_json::to_vec(&upload).map_err(|e| StorageError::Backend(e.to_string()))?;
+        tokio::fs::write(upload_dir.join("upload.json"), json).await?;
+        Ok(upload_id)
+    }
+
+    async fn presign_upload_part(
+        &self,
+        _key: &str,
+        _upload_id: &str,
+        _part_number: i32,
+        _expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        Err(StorageError::Unsupported(
+            "presigned uploads to local storage",
+        ))
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<String, StorageError> {
+        let upload = self.read_upload(upload_id).await?;
+        if upload.key != key {
+            return Err(StorageError::NotFound(upload_id.to_string()));
+        }
+        let upload_dir = self.upload_dir(upload_id)?;
+        let etag = hex::encode(Sha256::digest(&body));
+        tokio::fs::write(upload_dir.join(format!("{}.part", part_number)), &body).await?;
+        tokio::fs::write(upload_dir.join(format!("{}.etag", part_number)), &etag).await?;
+        Ok(etag)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<(), StorageError> {
+        let upload = self.read_upload(upload_id).await?;
+        if upload.key != key {
+            return Err(StorageError::NotFound(upload_id.to_string()));
+        }
+        let upload_dir = self.upload_dir(upload_id)?;
+        let target = self.object_path(key)?;
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Parts are appended to a temporary file one at a time to keep memory use flat
+        let temp_path = target.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        let mut output = tokio::fs::File::create(&temp_path).await?;
+        for part in parts {
+            let etag =
+                tokio::fs::read_to_string(upload_dir.join(format!("{}.etag", part.part_number)))
+                    .await
+                    .map_err(|_| {
+                        StorageError::InvalidKey(format!(
+                            "Part {} was never uploaded",
+                            part.part_number
+                        ))
+                    })?;
+            if etag != part.etag.trim_matches('"') {
+                drop(output);
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                return Err(StorageError::InvalidKey(format!(
+                    "ETag mismatch for part {}",
+                    part.part_number
+                )));
+            }
+            let mut part_file =
+                tokio::fs::File::open(upload_dir.join(format!("{}.part", part.part_number)))
+                    .await?;
+            tokio::io::copy(&mut part_file, &mut output).await?;
+        }
+        output.flush().await?;
+        drop(output);
+        tokio::fs::rename(&temp_path, &target).await?;
+
+        let options = PutOptions {
+            content_type: upload.content_type,
+            ..Default::default()
+        };
+        self.write_meta(key, &options).await?;
+        tokio::fs::remove_dir_all(upload_dir).await?;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        _key: &str,
+        upload_id: &str,
+    ) -> Result<(), StorageError> {
+        self.read_upload(upload_id).await?;
+        tokio::fs::remove_dir_all(self.upload_dir(upload_id)?).await?;
+        Ok(())
+    }
+
+    async fn list_parts(
+        &self,
+        _key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<UploadedPart>, StorageError> {
+        self.read_upload(upload_id).await?;
+        let upload_dir = self.upload_dir(upload_id)?;
+        let mut parts = Vec::new();
+        let mut entries = tokio::fs::read_dir(&upload_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(part_number) = file_name
+                .strip_suffix(".part")
+                .and_then(|number| number.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            let Ok(etag) =
+                tokio::fs::read_to_string(upload_dir.join(format!("{}.etag", part_number))).await
+            else {
+                continue;
+            };
+            parts.push(UploadedPart {
+                part_number,
+                etag,
+                size: entry.metadata().await.ok().map(|metadata| metadata.len()),
+            });
+        }
+        parts.sort_by_key(|part| part.part_number);
+        Ok(parts)
+    }
+
+    async fn list_multipart_uploads(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<MultipartUpload>, StorageError> {
+        let uploads_dir = self.root.join(UPLOADS_DIR);
+        if !uploads_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut uploads = Vec::new();
+        let mut entries = tokio::fs::read_dir(&uploads_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let upload_id = entry.file_name().to_string_lossy().to_string();
+            let Ok(upload) = self.read_upload(&upload_id).await else {
+                continue;
+            };
+            if upload.key.starts_with(prefix) {
+                uploads.push(MultipartUpload {
+                    key: upload.key,
+                    upload_id,
+                    initiated: Some(upload.initiated),
+                });
+            }
+        }
+        Ok(uploads)
+    }
+}
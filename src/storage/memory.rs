@@ -0,0 +1,252 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::RwLock,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+
+use super::{MultipartUpload, ObjectMeta, ObjectReader, PutOptions, Storage, UploadedPart};
+use crate::error::StorageError;
+
+struct StoredObject {
+    data: Bytes,
+    meta: ObjectMeta,
+}
+
+struct PendingUpload {
+    upload: MultipartUpload,
+    content_type: Option<String>,
+    parts: BTreeMap<i32, (String, Bytes)>,
+}
+
+/// Storage that keeps everything in memory, for tests and local experiments
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: RwLock<BTreeMap<String, StoredObject>>,
+    uploads: RwLock<HashMap<String, PendingUpload>>,
+}
+
+/// Hashes contents into an ETag, the same way for objects and parts
+fn etag(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn lock_error<T>(_: T) -> StorageError {
+    StorageError::Backend("Memory storage lock poisoned".to_string())
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn put(&self, key: &str, body: Bytes, options: &PutOptions) -> Result<(), StorageError> {
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            size: body.len() as u64,
+            content_type: options.content_type.clone(),
+            etag: Some(etag(&body)),
+            last_modified: Some(Utc::now()),
+            metadata: options.metadata.clone(),
+        };
+        self.objects
+            .write()
+            .map_err(lock_error)?
+            .insert(key.to_string(), StoredObject { data: body, meta });
+        Ok(())
+    }
+
+    async fn put_file(
+        &self,
+        key: &str,
+        path: &Path,
+        options: &PutOptions,
+    ) -> Result<(), StorageError> {
+        let body = tokio::fs::read(path).await?;
+        self.put(key, Bytes::from(body), options).await
+    }
+
+    async fn get(&self, key: &str) -> Result<ObjectReader, StorageError> {
+        let objects = self.objects.read().map_err(lock_error)?;
+        let object = objects
+            .get(key)
+            .ok_or_else(|| StorageError::NotFound(key.to_string()))?;
+        Ok(Box::pin(std::io::Cursor::new(object.data.clone())))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let objects = self.objects.read().map_err(lock_error)?;
+        objects
+            .get(key)
+            .map(|object| object.meta.clone())
+            .ok_or_else(|| StorageError::NotFound(key.to_string()))
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64, StorageError> {
+        let mut objects = self.objects.write().map_err(lock_error)?;
+        let before = objects.len();
+        objects.retain(|key, _| !key.starts_with(prefix));
+        Ok((before - objects.len()) as u64)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        let objects = self.objects.read().map_err(lock_error)?;
+        Ok(objects
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(_, object)| object.meta.clone())
+            .collect())
+    }
+
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, StorageError> {
+        self.head(key).await?;
+        Ok(format!(
+            "memory:///{}?expires_in={}",
+            key,
+            expires_in.as_secs()
+        ))
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+    ) -> Result<String, StorageError> {
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        self.uploads.write().map_err(lock_error)?.insert(
+            upload_id.clone(),
+            PendingUpload {
+                upload: MultipartUpload {
+                    key: key.to_string(),
+                    upload_id: upload_id.clone(),
+                    initiated: Some(Utc::now()),
+                },
+                content_type: content_type.map(str::to_string),
+                parts: BTreeMap::new(),
+            },
+        );
+        Ok(upload_id)
+    }
+
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        Ok(format!(
+            "memory:///{}?upload_id={}&part_number={}&expires_in={}",
+            key,
+            upload_id,
+            part_number,
+            expires_in.as_secs()
+        ))
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<String, StorageError> {
+        let mut uploads = self.uploads.write().map_err(lock_error)?;
+        let pending = uploads
+            .get_mut(upload_id)
+            .filter(|pending| pending.upload.key == key)
+            .ok_or_else(|| StorageError::NotFound(upload_id.to_string()))?;
+        let part_etag = etag(&body);
+        pending.parts.insert(part_number, (part_etag.clone(), body));
+        Ok(part_etag)
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<(), StorageError> {
+        let pending = {
+            let mut uploads = self.uploads.write().map_err(lock_error)?;
+            match uploads.get(upload_id) {
+                Some(pending) if pending.upload.key == key => uploads.remove(upload_id),
+                _ => None,
+            }
+            .ok_or_else(|| StorageError::NotFound(upload_id.to_string()))?
+        };
+
+        let mut body = Vec::new();
+        for part in parts {
+            let (part_etag, data) = pending.parts.get(&part.part_number).ok_or_else(|| {
+                StorageError::InvalidKey(format!("Part {} was never uploaded", part.part_number))
+            })?;
+            if *part_etag != part.etag.trim_matches('"') {
+                return Err(StorageError::InvalidKey(format!(
+                    "ETag mismatch for part {}",
+                    part.part_number
+                )));
+            }
+            body.extend_from_slice(data);
+        }
+
+        let options = PutOptions {
+            content_type: pending.content_type,
+            ..Default::default()
+        };
+        self.put(key, Bytes::from(body), &options).await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        _key: &str,
+        upload_id: &str,
+    ) -> Result<(), StorageError> {
+        self.uploads
+            .write()
+            .map_err(lock_error)?
+            .remove(upload_id)
+            .map(|_| ())
+            .ok_or_else(|| StorageError::NotFound(upload_id.to_string()))
+    }
+
+    async fn list_parts(
+        &self,
+        _key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<UploadedPart>, StorageError> {
+        let uploads = self.uploads.read().map_err(lock_error)?;
+        let pending = uploads
+            .get(upload_id)
+            .ok_or_else(|| StorageError::NotFound(upload_id.to_string()))?;
+        Ok(pending
+            .parts
+            .iter()
+            .map(|(part_number, (etag, data))| UploadedPart {
+                part_number: *part_number,
+                etag: etag.clone(),
+                size: Some(data.len() as u64),
+            })
+            .collect())
+    }
+
+    async fn list_multipart_uploads(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<MultipartUpload>, StorageError> {
+        let uploads = self.uploads.read().map_err(lock_error)?;
+        Ok(uploads
+            .values()
+            .filter(|pending| pending.upload.key.starts_with(prefix))
+            .map(|pending| pending.upload.clone())
+            .collect())
+    }
+}
@@ -1 +1,177 @@
+pub mod local;
+pub mod memory;
 pub mod s3;
+pub mod sync;
+pub mod tier;
+
+use std::{collections::HashMap, path::Path, pin::Pin, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncRead;
+
+use crate::error::StorageError;
+pub use local::LocalStorage;
+pub use memory::MemoryStorage;
+pub use s3::S3Storage;
+
+/// A stream of an object's contents
+pub type ObjectReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Information about a stored object
+#[derive(Debug, Clone, Default)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+    /// User defined metadata stored with the object
+    pub metadata: HashMap<String, String>,
+}
+
+/// Optional headers and metadata to store with an object
+#[derive(Debug, Clone, Default)]
+pub struct PutOptions {
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>,
+    pub metadata: HashMap<String, String>,
+}
+
+impl PutOptions {
+    /// Picks the content type and cache headers for an object based on its extension
+    /// Segments never change once written, while playlists, chapters and data can be rewritten
+    pub fn for_key(key: &str) -> Self {
+        const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+        const SHORT: &str = "public, max-age=60";
+        const DAY: &str = "public, max-age=86400";
+
+        let extension = Path::new(key)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_default();
+        let (content_type, cache_control) = match extension.as_str() {
+            "m3u8" => ("application/vnd.apple.mpegurl", Some(SHORT)),
+            "ts" => ("video/mp2t", Some(IMMUTABLE)),
+            "m4s" => ("video/iso.segment", Some(IMMUTABLE)),
+            "mp4" => ("video/mp4", Some(DAY)),
+            "mov" => ("video/quicktime", None),
+            "vtt" => ("text/vtt", Some(SHORT)),
+            "json" => ("application/json", Some(SHORT)),
+            "jpg" | "jpeg" => ("image/jpeg", Some(DAY)),
+            "png" => ("image/png", Some(DAY)),
+            "webp" => ("image/webp", Some(DAY)),
+            "gif" => ("image/gif", Some(DAY)),
+            _ => ("application/octet-stream", None),
+        };
+        PutOptions {
+            content_type: Some(content_type.to_string()),
+            cache_control: cache_control.map(str::to_string),
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+/// A part of a multipart upload that has been uploaded
+#[derive(Debug, Clone)]
+pub struct UploadedPart {
+    pub part_number: i32,
+    pub etag: String,
+    pub size: Option<u64>,
+}
+
+/// A multipart upload that was started but not completed or aborted
+#[derive(Debug, Clone)]
+pub struct MultipartUpload {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: Option<DateTime<Utc>>,
+}
+
+/// Object storage the API and runners keep videos in
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores an object from memory
+    async fn put(&self, key: &str, body: Bytes, options: &PutOptions) -> Result<(), StorageError>;
+    /// Stores an object from a local file, without reading it all into memory
+    async fn put_file(
+        &self,
+        key: &str,
+        path: &Path,
+        options: &PutOptions,
+    ) -> Result<(), StorageError>;
+    /// Streams the contents of an object
+    async fn get(&self, key: &str) -> Result<ObjectReader, StorageError>;
+    /// Gets information about an object without its contents
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError>;
+    /// Deletes every object under the prefix, returning how many were deleted
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64, StorageError>;
+    /// Lists every object under the prefix
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError>;
+    /// Creates a URL that can download the object until it expires
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, StorageError>;
+
+    /// Starts a multipart upload, returning its upload ID
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+    ) -> Result<String, StorageError>;
+    /// Creates a URL a client can upload a part to directly until it expires
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        expires_in: Duration,
+    ) -> Result<String, StorageError>;
+    /// Uploads a part through the server, returning its ETag
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<String, StorageError>;
+    /// Assembles the uploaded parts into the final object
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<(), StorageError>;
+    /// Abandons a multipart upload and frees its parts
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), StorageError>;
+    /// Lists the parts uploaded so far
+    async fn list_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<UploadedPart>, StorageError>;
+    /// Lists multipart uploads under the prefix that are still in progress
+    async fn list_multipart_uploads(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<MultipartUpload>, StorageError>;
+}
+
+/// Creates the storage backend selected by the STORAGE_BACKEND environment variable
+/// Supports "s3" (the default, also used for R2 and MinIO), "local" and "memory"
+pub async fn storage_from_env() -> Result<Arc<dyn Storage>, StorageError> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "s3".to_string());
+    tracing::debug!("Using {} storage backend", backend);
+    match backend.as_str() {
+        "s3" => Ok(Arc::new(S3Storage::from_env().await?)),
+        "local" => {
+            let root = std::env::var("LOCAL_STORAGE_ROOT").unwrap_or_else(|_| "bucket".to_string());
+            Ok(Arc::new(LocalStorage::new(root)?))
+        }
+        "memory" => Ok(Arc::new(MemoryStorage::new())),
+        other => Err(StorageError::Backend(format!(
+            "Unknown storage backend: {}",
+            other
+        ))),
+    }
+}
@@ -1,65 +1,407 @@
+use std::{path::Path, time::Duration};
+
+use async_trait::async_trait;
 use aws_config::Region;
-use aws_sdk_s3::Client;
+use aws_sdk_s3::{
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier},
+    Client,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+
+use super::{MultipartUpload, ObjectMeta, ObjectReader, PutOptions, Storage, UploadedPart};
+use crate::error::StorageError;
+
+/// Most keys S3 accepts in a single delete request
+const MAX_DELETE_BATCH: usize = 1000;
 
-/// Create an S3 Client configured against Cloudflare R2
-pub async fn create_s3_client() -> Client {
-    let region = Region::new("auto");
-    let r2_account_id = std::env::var("R2_ACCOUNT_ID").expect("R2_ACCOUNT_ID required");
-    let endpoint_url = format!("https://{}.r2.cloudflarestorage.com", r2_account_id);
+/// Create an S3 Client, configured against Cloudflare R2 unless S3_ENDPOINT is set
+/// S3_FORCE_PATH_STYLE can be set for endpoints like MinIO that don't support virtual hosts
+pub async fn create_s3_client() -> Result<Client, StorageError> {
+    let region = Region::new(std::env::var("S3_REGION").unwrap_or_else(|_| "auto".to_string()));
+    let endpoint_url = match std::env::var("S3_ENDPOINT") {
+        Ok(endpoint) => endpoint,
+        Err(_) => {
+            let r2_account_id = std::env::var("R2_ACCOUNT_ID").map_err(|_| {
+                StorageError::Backend("S3_ENDPOINT or R2_ACCOUNT_ID required".to_string())
+            })?;
+            format!("https://{}.r2.cloudflarestorage.com", r2_account_id)
+        }
+    };
+    let force_path_style = std::env::var("S3_FORCE_PATH_STYLE")
+        .map(|v| v == "true")
+        .unwrap_or(false);
 
     let config = aws_config::from_env()
         .region(region)
         .endpoint_url(endpoint_url)
         .load()
         .await;
+    let s3_config = aws_sdk_s3::config::Builder::from(&config)
+        .force_path_style(force_path_style)
+        .build();
 
-    Client::new(&config)
+    Ok(Client::from_conf(s3_config))
 }
 
-pub async fn sync_directory_to_bucket<P: AsRef<std::path::Path>>(
-    client: &Client,
-    local_dir: P,
-    bucket: &str,
-    target_prefix: &str,
-    ignore_patterns: &[&str],
-) -> Result<(), Box<dyn std::error::Error>> {
-    let local_dir = local_dir.as_ref();
-    if !local_dir.is_dir() {
-        return Err("Source path is not a directory".into());
+/// Converts an SDK error into a storage error, keeping track of missing objects
+fn backend_error<E: std::fmt::Debug>(
+    key: &str,
+    error: aws_sdk_s3::error::SdkError<E>,
+) -> StorageError {
+    if error
+        .raw_response()
+        .is_some_and(|response| response.status().as_u16() == 404)
+    {
+        StorageError::NotFound(key.to_string())
+    } else {
+        StorageError::Backend(format!("{:?}", error))
     }
+}
+
+fn to_chrono(time: &aws_sdk_s3::primitives::DateTime) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(time.secs(), time.subsec_nanos())
+}
 
-    let walk_dir = walkdir::WalkDir::new(local_dir);
+fn presigning_config(expires_in: Duration) -> Result<PresigningConfig, StorageError> {
+    PresigningConfig::expires_in(expires_in)
+        .map_err(|e| StorageError::Backend(format!("Could not construct presigning config {}", e)))
+}
 
-    for entry in walk_dir.into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if ignore_patterns
-            .iter()
-            .any(|pattern| path.to_string_lossy().contains(pattern))
-        {
-            tracing::debug!("Skipping {:?}", path);
-            continue;
+/// Storage backed by an S3 compatible bucket, like R2 or MinIO
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    pub fn new(client: Client, bucket: impl Into<String>) -> Self {
+        S3Storage {
+            client,
+            bucket: bucket.into(),
         }
-        if path.is_file() {
-            let relative_path = path
-                .strip_prefix(local_dir)
-                .map_err(|e| format!("Failed to strip prefix: {}", e))?;
-            let key = if target_prefix.is_empty() {
-                relative_path.to_string_lossy().to_string()
-            } else {
-                format!("{}/{}", target_prefix, relative_path.to_string_lossy())
-            };
-
-            let body = aws_sdk_s3::primitives::ByteStream::from_path(path).await?;
-            tracing::debug!("full key from walkdir {key}");
-            client
-                .put_object()
-                .bucket(bucket)
-                .key(&key)
-                .body(body)
+    }
+
+    /// Creates the storage from environment configuration, the bucket comes from UPLOAD_BUCKET
+    pub async fn from_env() -> Result<Self, StorageError> {
+        let bucket = std::env::var("UPLOAD_BUCKET")
+            .map_err(|_| StorageError::Backend("UPLOAD_BUCKET must be set".to_string()))?;
+        Ok(S3Storage::new(create_s3_client().await?, bucket))
+    }
+
+    async fn put_body(
+        &self,
+        key: &str,
+        body: ByteStream,
+        options: &PutOptions,
+    ) -> Result<(), StorageError> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(body)
+            .set_content_type(options.content_type.clone())
+            .set_cache_control(options.cache_control.clone())
+            .set_metadata(Some(options.metadata.clone()).filter(|m| !m.is_empty()))
+            .send()
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, body: Bytes, options: &PutOptions) -> Result<(), StorageError> {
+        self.put_body(key, ByteStream::from(body), options).await
+    }
+
+    async fn put_file(
+        &self,
+        key: &str,
+        path: &Path,
+        options: &PutOptions,
+    ) -> Result<(), StorageError> {
+        let body = ByteStream::from_path(path)
+            .await
+            .map_err(|e| StorageError::Backend(e.to_string()))?;
+        self.put_body(key, body, options).await
+    }
+
+    async fn get(&self, key: &str) -> Result<ObjectReader, StorageError> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        Ok(Box::pin(output.body.into_async_read()))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let output = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: output.content_length().unwrap_or_default().max(0) as u64,
+            content_type: output.content_type().map(str::to_string),
+            etag: output.e_tag().map(str::to_string),
+            last_modified: output.last_modified().and_then(to_chrono),
+            metadata: output.metadata().cloned().unwrap_or_default(),
+        })
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<u64, StorageError> {
+        let objects = self.list(prefix).await?;
+        let mut deleted = 0;
+        for batch in objects.chunks(MAX_DELETE_BATCH) {
+            let identifiers = batch
+                .iter()
+                .map(|object| ObjectIdentifier::builder().key(&object.key).build())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| StorageError::Backend(e.to_string()))?;
+            let delete = Delete::builder()
+                .set_objects(Some(identifiers))
+                .quiet(true)
+                .build()
+                .map_err(|e| StorageError::Backend(e.to_string()))?;
+            let output = self
+                .client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
                 .send()
-                .await?;
+                .await
+                .map_err(|e| backend_error(prefix, e))?;
+            if let Some(error) = output.errors().first() {
+                return Err(StorageError::Backend(format!(
+                    "Failed to delete {}: {}",
+                    error.key().unwrap_or_default(),
+                    error.message().unwrap_or_default()
+                )));
+            }
+            deleted += batch.len() as u64;
+        }
+        Ok(deleted)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        let mut objects = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| backend_error(prefix, e))?;
+            for object in page.contents() {
+                let Some(key) = object.key() else {
+                    continue;
+                };
+                objects.push(ObjectMeta {
+                    key: key.to_string(),
+                    size: object.size().unwrap_or_default().max(0) as u64,
+                    etag: object.e_tag().map(str::to_string),
+                    last_modified: object.last_modified().and_then(to_chrono),
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(objects)
+    }
+
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, StorageError> {
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(presigning_config(expires_in)?)
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        Ok(request.uri().to_string())
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+    ) -> Result<String, StorageError> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_content_type(content_type.map(str::to_string))
+            .send()
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        output
+            .upload_id()
+            .map(str::to_string)
+            .ok_or_else(|| StorageError::Backend("No upload ID returned".to_string()))
+    }
+
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        let request = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .presigned(presigning_config(expires_in)?)
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        Ok(request.uri().to_string())
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<String, StorageError> {
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        output
+            .e_tag()
+            .map(str::to_string)
+            .ok_or_else(|| StorageError::Backend("No ETag returned for part".to_string()))
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<(), StorageError> {
+        let parts = parts
+            .iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .e_tag(&part.etag)
+                    .part_number(part.part_number)
+                    .build()
+            })
+            .collect();
+        let completed_upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(parts))
+            .build();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(completed_upload)
+            .send()
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), StorageError> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| backend_error(key, e))?;
+        Ok(())
+    }
+
+    async fn list_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<UploadedPart>, StorageError> {
+        let mut parts = Vec::new();
+        let mut pages = self
+            .client
+            .list_parts()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| backend_error(key, e))?;
+            for part in page.parts() {
+                if let (Some(part_number), Some(etag)) = (part.part_number(), part.e_tag()) {
+                    parts.push(UploadedPart {
+                        part_number,
+                        etag: etag.to_string(),
+                        size: part.size().map(|size| size.max(0) as u64),
+                    });
+                }
+            }
         }
+        Ok(parts)
     }
 
-    Ok(())
+    async fn list_multipart_uploads(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<MultipartUpload>, StorageError> {
+        let mut uploads = Vec::new();
+        let mut key_marker: Option<String> = None;
+        let mut upload_id_marker: Option<String> = None;
+        loop {
+            let output = self
+                .client
+                .list_multipart_uploads()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_key_marker(key_marker.take())
+                .set_upload_id_marker(upload_id_marker.take())
+                .send()
+                .await
+                .map_err(|e| backend_error(prefix, e))?;
+            for upload in output.uploads() {
+                if let (Some(key), Some(upload_id)) = (upload.key(), upload.upload_id()) {
+                    uploads.push(MultipartUpload {
+                        key: key.to_string(),
+                        upload_id: upload_id.to_string(),
+                        initiated: upload.initiated().and_then(to_chrono),
+                    });
+                }
+            }
+            if !output.is_truncated().unwrap_or(false) {
+                break;
+            }
+            key_marker = output.next_key_marker().map(str::to_string);
+            upload_id_marker = output.next_upload_id_marker().map(str::to_string);
+        }
+        Ok(uploads)
+    }
 }
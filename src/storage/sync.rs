@@ -0,0 +1,185 @@
+use std::path::{Path, PathBuf};
+
+use futures::{stream, StreamExt, TryStreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use super::{PutOptions, Storage};
+use crate::error::StorageError;
+
+/// Metadata key the checksum of synced files is stored under
+pub const CHECKSUM_METADATA_KEY: &str = "sha256";
+
+/// Settings for syncing a directory to storage
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// How many files are uploaded at the same time
+    pub concurrency: usize,
+    /// Glob patterns, relative to the synced directory, of files to skip
+    pub ignore: Vec<String>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            concurrency: 8,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// A file that was synced, whether it had to be uploaded or was already there
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncedObject {
+    pub key: String,
+    pub size: u64,
+    pub sha256: String,
+    /// False when an identical copy was already in storage
+    pub uploaded: bool,
+}
+
+/// Everything a sync put in storage
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncManifest {
+    pub objects: Vec<SyncedObject>,
+}
+
+impl SyncManifest {
+    /// Gets the keys of every synced object, including ones that were already up to date
+    pub fn keys(&self) -> Vec<&str> {
+        self.objects
+            .iter()
+            .map(|object| object.key.as_str())
+            .collect()
+    }
+    /// Gets the combined size of every synced object
+    pub fn total_size(&self) -> u64 {
+        self.objects.iter().map(|object| object.size).sum()
+    }
+    /// Gets how many objects actually had to be uploaded
+    pub fn uploaded_count(&self) -> usize {
+        self.objects.iter().filter(|object| object.uploaded).count()
+    }
+}
+
+fn build_ignore_set(patterns: &[String]) -> Result<GlobSet, StorageError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| StorageError::InvalidKey(format!("Invalid ignore pattern: {}", e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| StorageError::InvalidKey(format!("Invalid ignore patterns: {}", e)))
+}
+
+/// Hashes a file without reading it all into memory, hex encoded
+pub async fn sha256_file(path: &Path) -> Result<String, StorageError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Uploads a file unless storage already has a copy with the same checksum
+async fn sync_file(
+    storage: &dyn Storage,
+    path: PathBuf,
+    key: String,
+) -> Result<SyncedObject, StorageError> {
+    let size = tokio::fs::metadata(&path).await?.len();
+    let sha256 = sha256_file(&path).await?;
+
+    let existing = match storage.head(&key).await {
+        Ok(meta) => Some(meta),
+        Err(StorageError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+    let up_to_date = existing.is_some_and(|meta| {
+        meta.size == size && meta.metadata.get(CHECKSUM_METADATA_KEY) == Some(&sha256)
+    });
+    if up_to_date {
+        tracing::debug!("Skipping unchanged {}", key);
+    } else {
+        let mut options = PutOptions::for_key(&key);
+        options
+            .metadata
+            .insert(CHECKSUM_METADATA_KEY.to_string(), sha256.clone());
+        tracing::debug!("Uploading {:?} to {}", path, key);
+        storage.put_file(&key, &path, &options).await?;
+    }
+
+    Ok(SyncedObject {
+        key,
+        size,
+        sha256,
+        uploaded: !up_to_date,
+    })
+}
+
+/// Uploads every file in a local directory to storage under the target prefix
+/// Files that are already in storage with a matching checksum are skipped
+pub async fn sync_directory<P: AsRef<Path>>(
+    storage: &dyn Storage,
+    local_dir: P,
+    target_prefix: &str,
+    options: &SyncOptions,
+) -> Result<SyncManifest, StorageError> {
+    let local_dir = local_dir.as_ref();
+    if !local_dir.is_dir() {
+        return Err(StorageError::InvalidKey(format!(
+            "Source path {:?} is not a directory",
+            local_dir
+        )));
+    }
+    let ignore = build_ignore_set(&options.ignore)?;
+
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(local_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let relative_path = path
+            .strip_prefix(local_dir)
+            .map_err(|e| StorageError::InvalidKey(format!("Failed to strip prefix: {}", e)))?;
+        if ignore.is_match(relative_path) {
+            tracing::debug!("Skipping {:?}", path);
+            continue;
+        }
+        // Keys always use forward slashes, whatever the platform
+        let relative_key = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let key = if target_prefix.is_empty() {
+            relative_key
+        } else {
+            format!("{}/{}", target_prefix, relative_key)
+        };
+        files.push((path.to_path_buf(), key));
+    }
+
+    let mut objects: Vec<SyncedObject> = stream::iter(files)
+        .map(|(path, key)| sync_file(storage, path, key))
+        .buffer_unordered(options.concurrency.max(1))
+        .try_collect()
+        .await?;
+    objects.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Ok(SyncManifest { objects })
+}
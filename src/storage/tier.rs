@@ -0,0 +1,86 @@
+use std::{path::Path, sync::Arc};
+
+use super::{s3::create_s3_client, PutOptions, S3Storage, Storage};
+use crate::error::StorageError;
+
+/// Storage raw sources are moved to once they haven't been needed for a while
+/// This is either a bucket of its own, or a prefix in the same storage as everything else
+pub struct ColdStorage {
+    pub storage: Arc<dyn Storage>,
+    /// Put in front of hot keys, keeps cold objects apart when they share the hot storage
+    prefix: String,
+}
+
+impl ColdStorage {
+    pub fn new(storage: Arc<dyn Storage>, prefix: impl Into<String>) -> Self {
+        ColdStorage {
+            storage,
+            prefix: prefix.into().trim_matches('/').to_string(),
+        }
+    }
+    /// Uses the bucket in COLD_STORAGE_BUCKET when set, otherwise a prefix in the hot storage
+    /// from COLD_STORAGE_PREFIX, which defaults to "cold"
+    pub async fn from_env(hot: Arc<dyn Storage>) -> Result<Self, StorageError> {
+        match std::env::var("COLD_STORAGE_BUCKET") {
+            Ok(bucket) => {
+                tracing::debug!("Using cold storage bucket {}", bucket);
+                let storage = S3Storage::new(create_s3_client().await?, bucket);
+                Ok(ColdStorage::new(Arc::new(storage), ""))
+            }
+            Err(_) => {
+                let prefix =
+                    std::env::var("COLD_STORAGE_PREFIX").unwrap_or_else(|_| "cold".to_string());
+                // Without a prefix cold keys would be the hot ones, and moving would delete them
+                if prefix.trim_matches('/').is_empty() {
+                    return Err(StorageError::Backend(
+                        "COLD_STORAGE_PREFIX can't be empty without COLD_STORAGE_BUCKET"
+                            .to_string(),
+                    ));
+                }
+                tracing::debug!("Using cold storage prefix {}", prefix);
+                Ok(ColdStorage::new(hot, prefix))
+            }
+        }
+    }
+    /// Gets the key an object in hot storage is kept under in cold storage
+    pub fn key_for(&self, hot_key: &str) -> String {
+        if self.prefix.is_empty() {
+            hot_key.to_string()
+        } else {
+            format!("{}/{}", self.prefix, hot_key)
+        }
+    }
+}
+
+/// Copies an object from one storage to another through a file in the given directory,
+/// raw sources can be too big to hold in memory
+/// Returns the size of the object
+pub async fn copy_object(
+    from: &dyn Storage,
+    from_key: &str,
+    to: &dyn Storage,
+    to_key: &str,
+    tmp_dir: &Path,
+) -> Result<u64, StorageError> {
+    let meta = from.head(from_key).await?;
+    let file_name = Path::new(from_key)
+        .file_name()
+        .ok_or_else(|| StorageError::InvalidKey(from_key.to_string()))?;
+    let path = tmp_dir.join(file_name);
+
+    let mut reader = from.get(from_key).await?;
+    let mut file = tokio::fs::File::create(&path).await?;
+    let size = tokio::io::copy(&mut reader, &mut file).await?;
+    drop(file);
+
+    let defaults = PutOptions::for_key(to_key);
+    let options = PutOptions {
+        content_type: meta.content_type.or(defaults.content_type),
+        metadata: meta.metadata,
+        ..defaults
+    };
+    let stored = to.put_file(to_key, &path, &options).await;
+    tokio::fs::remove_file(&path).await?;
+    stored?;
+    Ok(size)
+}
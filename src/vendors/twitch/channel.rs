@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::db::accounts::Account;
+
+/// Payload of a channel.update notification, sent when the title or category changes
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChannelUpdatePayload {
+    pub broadcaster_user_id: String,
+    pub broadcaster_user_login: String,
+    pub broadcaster_user_name: String,
+    pub title: String,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub category_id: Option<String>,
+    #[serde(default)]
+    pub category_name: Option<String>,
+}
+
+impl ChannelUpdatePayload {
+    /// Find the associated user account based on the broadcaster ID
+    pub async fn find_broadcaster_account(&self, pool: &PgPool) -> Result<Account, sqlx::Error> {
+        Account::find_by_provider("twitch", &self.broadcaster_user_id, pool).await
+    }
+}
+
+/// Payload of a channel.raid notification
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RaidPayload {
+    pub from_broadcaster_user_id: String,
+    pub from_broadcaster_user_login: String,
+    pub from_broadcaster_user_name: String,
+    pub to_broadcaster_user_id: String,
+    pub to_broadcaster_user_login: String,
+    pub to_broadcaster_user_name: String,
+    pub viewers: i64,
+}
+
+impl RaidPayload {
+    /// Find the account of the broadcaster being raided
+    pub async fn find_broadcaster_account(&self, pool: &PgPool) -> Result<Account, sqlx::Error> {
+        Account::find_by_provider("twitch", &self.to_broadcaster_user_id, pool).await
+    }
+}
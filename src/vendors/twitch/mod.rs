@@ -1,6 +1,8 @@
+pub mod channel;
 pub mod chat;
 pub mod stream;
 pub mod subscription;
 
+pub use channel::{ChannelUpdatePayload, RaidPayload};
 pub use chat::ChatMessagePayload;
 pub use stream::StreamStatusPayload;
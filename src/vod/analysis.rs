@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+use super::chapters::Chapter;
+use super::stream::{summarize_ffmpeg_output, HLSConverter};
+
+/// Settings for detecting chapter boundaries in a video
+#[derive(Debug, Clone)]
+pub struct AnalysisSettings {
+    /// Scene change score (0.0 - 1.0) a frame needs to count as a new scene
+    pub scene_threshold: f64,
+    /// Whether to look for black frames, which usually separate segments
+    pub detect_black: bool,
+    /// Whether to look for silence, which usually separates segments
+    pub detect_silence: bool,
+    /// Shortest chapter that will be proposed
+    pub min_chapter_secs: f64,
+    /// Most chapters that will be proposed for a single video
+    pub max_chapters: usize,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        AnalysisSettings {
+            scene_threshold: 0.4,
+            detect_black: true,
+            detect_silence: true,
+            min_chapter_secs: 120.0,
+            max_chapters: 30,
+        }
+    }
+}
+
+impl AnalysisSettings {
+    /// Creates the settings from environment variables, falling back to the defaults
+    pub fn from_env() -> Self {
+        let defaults = AnalysisSettings::default();
+        let parse_bool = |key: &str, default: bool| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse::<bool>().ok())
+                .unwrap_or(default)
+        };
+        AnalysisSettings {
+            scene_threshold: std::env::var("CHAPTER_SCENE_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.scene_threshold),
+            detect_black: parse_bool("CHAPTER_DETECT_BLACK", defaults.detect_black),
+            detect_silence: parse_bool("CHAPTER_DETECT_SILENCE", defaults.detect_silence),
+            ..defaults
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundaryKind {
+    Scene,
+    Black,
+    Silence,
+}
+
+impl BoundaryKind {
+    /// How strongly this kind of boundary suggests a new chapter
+    fn weight(&self) -> u32 {
+        match self {
+            BoundaryKind::Black => 3,
+            BoundaryKind::Silence => 2,
+            BoundaryKind::Scene => 1,
+        }
+    }
+}
+
+/// A point in the video where the content likely changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boundary {
+    pub time: f64,
+    pub kind: BoundaryKind,
+}
+
+/// Parses the value following `key` in an ffmpeg log line
+fn parse_log_value(line: &str, key: &str) -> Option<f64> {
+    let start = line.find(key)? + key.len();
+    line[start..]
+        .trim_start()
+        .split(|c: char| c.is_whitespace() || c == '|')
+        .next()
+        .and_then(|value| value.parse::<f64>().ok())
+}
+
+/// Parses scene, black and silence detection output from ffmpeg's log
+pub fn parse_boundaries(log: &str) -> Vec<Boundary> {
+    let mut boundaries = Vec::new();
+    for line in log.lines() {
+        if line.contains("Parsed_showinfo") {
+            if let Some(time) = parse_log_value(line, "pts_time:") {
+                boundaries.push(Boundary {
+                    time,
+                    kind: BoundaryKind::Scene,
+                });
+            }
+        } else if line.contains("blackdetect") {
+            // Content resumes where the black frames end
+            if let Some(time) = parse_log_value(line, "black_end:") {
+                boundaries.push(Boundary {
+                    time,
+                    kind: BoundaryKind::Black,
+                });
+            }
+        } else if line.contains("silencedetect") {
+            if let Some(time) = parse_log_value(line, "silence_end:") {
+                boundaries.push(Boundary {
+                    time,
+                    kind: BoundaryKind::Silence,
+                });
+            }
+        }
+    }
+    boundaries.sort_by(|a, b| a.time.total_cmp(&b.time));
+    boundaries
+}
+
+/// Proposes chapters from detected boundaries
+/// Boundaries that coincide reinforce each other, and the strongest ones are picked first
+/// while keeping every chapter at least the minimum length
+pub fn propose_chapters(
+    boundaries: &[Boundary],
+    duration: f64,
+    settings: &AnalysisSettings,
+) -> Vec<Chapter> {
+    // Boundaries this close together are treated as the same moment
+    const COINCIDENCE_SECS: f64 = 2.0;
+
+    let mut candidates: Vec<(f64, u32)> = boundaries
+        .iter()
+        .map(|boundary| {
+            let score = boundaries
+                .iter()
+                .filter(|other| (other.time - boundary.time).abs() <= COINCIDENCE_SECS)
+                .map(|other| other.kind.weight())
+                .sum();
+            (boundary.time, score)
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.total_cmp(&b.0)));
+
+    let mut starts: Vec<f64> = vec![0.0];
+    for (time, _score) in candidates {
+        if starts.len() >= settings.max_chapters {
+            break;
+        }
+        let too_close = time < settings.min_chapter_secs
+            || duration - time < settings.min_chapter_secs
+            || starts
+                .iter()
+                .any(|start| (start - time).abs() < settings.min_chapter_secs);
+        if !too_close {
+            starts.push(time);
+        }
+    }
+    starts.sort_by(|a, b| a.total_cmp(b));
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, start)| Chapter {
+            start: *start,
+            end: starts.get(index + 1).copied().unwrap_or(duration),
+            title: format!("Chapter {}", index + 1),
+        })
+        .collect()
+}
+
+impl HLSConverter {
+    /// Runs scene change, and optionally black frame and silence detection over the video
+    pub fn detect_boundaries(
+        &self,
+        input_path: &Path,
+        settings: &AnalysisSettings,
+    ) -> Result<Vec<Boundary>> {
+        // Scene scores don't need full resolution, so downscale first to speed things up
+        let mut video_filters = vec!["scale=320:-2".to_string()];
+        if settings.detect_black {
+            video_filters.push("blackdetect=d=0.5:pix_th=0.10".to_string());
+        }
+        video_filters.push(format!("select='gt(scene,{})'", settings.scene_threshold));
+        video_filters.push("showinfo".to_string());
+
+        let mut command = Command::new(&self.ffmpeg_path);
+        command
+            .arg("-hide_banner")
+            .arg("-nostats")
+            .arg("-i")
+            .arg(input_path)
+            .arg("-map")
+            .arg("0:v:0")
+            .arg("-vf")
+            .arg(video_filters.join(","));
+        if settings.detect_silence {
+            command
+                .arg("-map")
+                .arg("0:a:0?")
+                .arg("-af")
+                .arg("silencedetect=n=-50dB:d=1");
+        }
+        command.arg("-f").arg("null").arg("-");
+
+        debug!("FFmpeg analysis command: {:?}", command);
+        let output = command
+            .output()
+            .context("Failed to execute FFmpeg analysis command")?;
+
+        let log = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            anyhow::bail!("FFmpeg analysis failed: {}", summarize_ffmpeg_output(&log));
+        }
+
+        Ok(parse_boundaries(&log))
+    }
+
+    /// Proposes chapters for a video based on its content
+    pub fn analyze_chapters(
+        &self,
+        input_path: &Path,
+        duration: f64,
+        settings: &AnalysisSettings,
+    ) -> Result<Vec<Chapter>> {
+        let boundaries = self.detect_boundaries(input_path, settings)?;
+        debug!(
+            "Detected {} chapter boundaries in {:?}",
+            boundaries.len(),
+            input_path
+        );
+        Ok(propose_chapters(&boundaries, duration, settings))
+    }
+}
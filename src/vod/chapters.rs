@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::debug;
+
+use super::stream::HLSConverter;
+use crate::{
+    db::stream_events::StreamEvent,
+    twitch::{ChannelUpdatePayload, RaidPayload},
+};
+
+/// File name of the WebVTT chapters track, stored next to the master playlist
+pub const CHAPTERS_FILE: &str = "chapters.vtt";
+/// Class used on the EXT-X-DATERANGE tags we generate, so they can be replaced later
+pub const DATERANGE_CLASS: &str = "com.farmhand.chapter";
+/// Chapters closer together than this are collapsed into one
+pub const MIN_CHAPTER_SECS: f64 = 10.0;
+
+/// A navigable segment of a VOD, in seconds from the start of the video
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: String,
+}
+
+/// Builds chapters from the events of a stream, aligned to the start of the stream
+/// Title and category changes and incoming raids each start a new chapter
+pub fn chapters_from_stream_events(
+    stream_start: DateTime<Utc>,
+    events: &[StreamEvent],
+    duration: f64,
+) -> Vec<Chapter> {
+    let mut markers: Vec<(f64, String)> = vec![(0.0, "Start of stream".to_string())];
+    let mut current_info: Option<(String, Option<String>)> = None;
+
+    for event in events {
+        let offset =
+            ((event.occurred_at - stream_start).num_milliseconds() as f64 / 1000.0).max(0.0);
+        if offset >= duration {
+            debug!(
+                "Skipping {} event past the end of the video",
+                event.event_type
+            );
+            continue;
+        }
+
+        let title = match event.event_type.as_str() {
+            "channel.update" => {
+                let Ok(update) =
+                    serde_json::from_value::<ChannelUpdatePayload>(event.payload.clone())
+                else {
+                    continue;
+                };
+                let info = (update.title.clone(), update.category_name.clone());
+                // Updates that only change things like the language don't get a chapter
+                if current_info.as_ref() == Some(&info) {
+                    continue;
+                }
+                current_info = Some(info);
+                match update.category_name {
+                    Some(category) if !category.is_empty() => {
+                        format!("{} ({})", update.title, category)
+                    }
+                    _ => update.title,
+                }
+            }
+            "channel.raid" => {
+                let Ok(raid) = serde_json::from_value::<RaidPayload>(event.payload.clone()) else {
+                    continue;
+                };
+                format!(
+                    "Raid from {} with {} viewers",
+                    raid.from_broadcaster_user_name, raid.viewers
+                )
+            }
+            _ => continue,
+        };
+
+        match markers.last_mut() {
+            // Collapse events that happen in quick succession, the latest one wins
+            Some((last_offset, last_title)) if offset - *last_offset < MIN_CHAPTER_SECS => {
+                *last_title = title;
+            }
+            _ => markers.push((offset, title)),
+        }
+    }
+
+    markers
+        .iter()
+        .enumerate()
+        .map(|(index, (start, title))| Chapter {
+            start: *start,
+            end: markers
+                .get(index + 1)
+                .map(|(next_start, _)| *next_start)
+                .unwrap_or(duration),
+            title: title.clone(),
+        })
+        .collect()
+}
+
+/// Formats seconds as a WebVTT timestamp (HH:MM:SS.mmm)
+fn format_vtt_timestamp(seconds: f64) -> String {
+    let total_millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    let hours = total_millis / 3_600_000;
+    let minutes = (total_millis % 3_600_000) / 60_000;
+    let secs = (total_millis % 60_000) / 1000;
+    let millis = total_millis % 1000;
+    format!("{:02}:{:02}:{:02}.{:03}", hours, minutes, secs, millis)
+}
+
+/// Renders chapters as a WebVTT chapters track
+pub fn to_webvtt(chapters: &[Chapter]) -> String {
+    let mut vtt = String::from("WEBVTT\n");
+    for (index, chapter) in chapters.iter().enumerate() {
+        // Cue text can't contain markup or blank lines
+        let title = chapter
+            .title
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace(['\r', '\n'], " ");
+        vtt.push_str(&format!(
+            "\n{}\n{} --> {}\n{}\n",
+            index + 1,
+            format_vtt_timestamp(chapter.start),
+            format_vtt_timestamp(chapter.end),
+            title
+        ));
+    }
+    vtt
+}
+
+/// Renders a chapter as an EXT-X-DATERANGE tag relative to the start of the program
+fn daterange_tag(index: usize, chapter: &Chapter, program_start: DateTime<Utc>) -> String {
+    let start_date =
+        program_start + chrono::Duration::milliseconds((chapter.start * 1000.0) as i64);
+    // Quoted attribute values can't contain quotes or line breaks
+    let title = chapter.title.replace('"', "'").replace(['\r', '\n'], " ");
+    format!(
+        "#EXT-X-DATERANGE:ID=\"chapter-{}\",CLASS=\"{}\",START-DATE=\"{}\",DURATION={:.3},X-TITLE=\"{}\"",
+        index + 1,
+        DATERANGE_CLASS,
+        start_date.to_rfc3339_opts(SecondsFormat::Millis, true),
+        (chapter.end - chapter.start).max(0.0),
+        title
+    )
+}
+
+/// Adds chapter EXT-X-DATERANGE tags to a media playlist, replacing any we added before
+/// DATERANGE requires a program date time, so one is added before the first segment if missing
+pub fn embed_in_playlist(
+    playlist: &str,
+    chapters: &[Chapter],
+    program_start: DateTime<Utc>,
+) -> String {
+    let has_program_date_time = playlist
+        .lines()
+        .any(|line| line.starts_with("#EXT-X-PROGRAM-DATE-TIME"));
+    let mut output = String::new();
+    let mut inserted = false;
+
+    for line in playlist.lines() {
+        if line.starts_with("#EXT-X-DATERANGE") && line.contains(DATERANGE_CLASS) {
+            continue;
+        }
+        if !inserted && line.starts_with("#EXTINF") {
+            if !has_program_date_time {
+                output.push_str(&format!(
+                    "#EXT-X-PROGRAM-DATE-TIME:{}\n",
+                    program_start.to_rfc3339_opts(SecondsFormat::Millis, true)
+                ));
+            }
+            for (index, chapter) in chapters.iter().enumerate() {
+                output.push_str(&daterange_tag(index, chapter, program_start));
+                output.push('\n');
+            }
+            inserted = true;
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    output
+}
+
+impl HLSConverter {
+    /// Writes the chapters track and tags every media playlist in the output directory
+    /// Returns the path of the written WebVTT file
+    pub fn embed_chapters(
+        &self,
+        chapters: &[Chapter],
+        program_start: DateTime<Utc>,
+    ) -> Result<PathBuf> {
+        let chapters_path = self.output_dir.join(CHAPTERS_FILE);
+        std::fs::write(&chapters_path, to_webvtt(chapters))
+            .context("Failed to write chapters track")?;
+
+        for entry in walkdir::WalkDir::new(&self.output_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let is_media_playlist = path.extension().and_then(|ext| ext.to_str()) == Some("m3u8")
+                && path.file_name().and_then(|name| name.to_str()) != Some("master.m3u8");
+            if !is_media_playlist {
+                continue;
+            }
+            debug!("Embedding chapters into {:?}", path);
+            let playlist = std::fs::read_to_string(path).context("Failed to read playlist")?;
+            std::fs::write(path, embed_in_playlist(&playlist, chapters, program_start))
+                .context("Failed to write playlist")?;
+        }
+
+        Ok(chapters_path)
+    }
+}
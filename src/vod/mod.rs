@@ -1,14 +1,19 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::{
-    db::{DBPool, Video},
-    prelude::get_storage_dir,
-};
+use crate::db::{DBPool, StorageTier, Video};
+use crate::storage::{tier::ColdStorage, PutOptions, Storage};
 use anyhow::anyhow;
-use aws_sdk_s3::Client;
 use stream::{get_ffmpeg_location, HLSConverter};
+use uuid::Uuid;
 
+pub mod analysis;
+pub mod chapters;
+pub mod playlist;
+pub mod preview;
+pub mod probe;
 pub mod stream;
+pub mod upload;
+pub mod validate;
 
 #[derive(Clone)]
 pub struct Vod {
@@ -17,8 +22,10 @@ pub struct Vod {
 }
 
 pub struct DownloadSettings<'a> {
-    pub client: &'a Client,
-    pub bucket: &'a str,
+    pub db: &'a DBPool,
+    pub storage: &'a dyn Storage,
+    /// Where raw sources are downloaded from when they've been moved out of hot storage
+    pub cold_storage: &'a ColdStorage,
 }
 
 impl Vod {
@@ -38,8 +45,7 @@ impl Vod {
     }
     /// Gets the prefix for the relative vod in storage
     pub fn get_remote_storage_prefix(&self) -> String {
-        let storage_root = get_storage_dir();
-        format!("{}/{}", storage_root, self.video.id)
+        self.video.storage_prefix()
     }
     /// Gets the raw video locally, and optionally downloads it if missing
     pub async fn get_raw_video<'a>(
@@ -74,33 +80,89 @@ impl Vod {
         tracing::debug!("Local video available at {:?}", local_file_path);
         Ok(Some(local_file_path))
     }
-    /// Downloads the raw video from S3 to the target path
+    /// Downloads the raw video from storage to the target path
+    /// A source in cold storage is downloaded from there, then restored to hot storage
     pub async fn download_raw<'a>(
         &self,
         settings: DownloadSettings<'a>,
         target_path: &PathBuf,
     ) -> Result<(), anyhow::Error> {
-        // The source video is expected to be in R2
         let folder = target_path.parent().unwrap();
         std::fs::create_dir_all(folder).map_err(|e| anyhow!("Failed to create folders: {}", e))?;
 
-        tracing::debug!(
-            "Downloading raw video from path: {}",
-            &self.video.raw_video_path
-        );
-        let req = settings
-            .client
-            .get_object()
-            .bucket(settings.bucket)
-            .key(&self.video.raw_video_path)
-            .send()
+        let cold_path = match self.video.raw_storage_tier {
+            StorageTier::Cold => self.video.raw_cold_path.as_deref(),
+            StorageTier::Hot => None,
+        };
+        let (storage, key) = match cold_path {
+            Some(cold_path) => (settings.cold_storage.storage.as_ref(), cold_path),
+            None => (settings.storage, self.video.raw_video_path.as_str()),
+        };
+        tracing::debug!("Downloading raw video from path: {}", key);
+        let mut reader = storage
+            .get(key)
             .await
-            .map_err(|e| anyhow!("Failed to download from R2: {}", e))?;
+            .map_err(|e| anyhow!("Failed to download from storage: {}", e))?;
 
-        let bytes = req.body.collect().await?;
-        std::fs::write(target_path, bytes.into_bytes())
-            .map_err(|e| anyhow!("Failed to save file: {}", e))?;
+        // Stream straight to disk, raw videos can be too big to hold in memory
+        // Downloads land next to the target first, so an interrupted one is never mistaken for it
+        // Each gets a name of its own, jobs filling the cache at once each move a whole file in
+        let partial_path = target_path.with_extension(format!("{}.part", Uuid::new_v4()));
+        let downloaded = async {
+            let mut file = tokio::fs::File::create(&partial_path)
+                .await
+                .map_err(|e| anyhow!("Failed to create file: {}", e))?;
+            tokio::io::copy(&mut reader, &mut file)
+                .await
+                .map_err(|e| anyhow!("Failed to save file: {}", e))?;
+            tokio::fs::rename(&partial_path, target_path)
+                .await
+                .map_err(|e| anyhow!("Failed to move downloaded file: {}", e))
+        }
+        .await;
+        if downloaded.is_err() {
+            if let Err(e) = tokio::fs::remove_file(&partial_path).await {
+                tracing::warn!(
+                    "Failed to remove partial download {:?}: {}",
+                    partial_path,
+                    e
+                );
+            }
+        }
+        downloaded?;
+
+        if let Some(cold_path) = cold_path {
+            self.restore_raw(&settings, target_path, cold_path).await;
+        }
 
         Ok(())
     }
+    /// Moves a raw source that was downloaded from cold storage back to hot storage,
+    /// since it's being worked on again
+    /// The download is already usable, so failing here only leaves the source in cold storage
+    async fn restore_raw(
+        &self,
+        settings: &DownloadSettings<'_>,
+        local_path: &Path,
+        cold_path: &str,
+    ) {
+        let storage_id = self.video.storage_id();
+        tracing::debug!("Restoring raw video of {} from cold storage", storage_id);
+        let hot_path = &self.video.raw_video_path;
+        if let Err(e) = settings
+            .storage
+            .put_file(hot_path, local_path, &PutOptions::for_key(hot_path))
+            .await
+        {
+            tracing::warn!("Failed to restore raw video {}: {}", hot_path, e);
+            return;
+        }
+        if let Err(e) = Video::set_raw_hot(settings.db, storage_id).await {
+            tracing::warn!("Failed to mark raw video of {} as hot: {}", storage_id, e);
+            return;
+        }
+        if let Err(e) = settings.cold_storage.storage.delete_prefix(cold_path).await {
+            tracing::warn!("Failed to delete cold raw video {}: {}", cold_path, e);
+        }
+    }
 }
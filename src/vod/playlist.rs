@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+/// Content type HLS playlists are served with
+pub const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+
+/// Checks whether a URI inside a playlist points at another playlist rather than media
+pub fn is_playlist_uri(uri: &str) -> bool {
+    uri.split(['?', '#'])
+        .next()
+        .is_some_and(|path| path.ends_with(".m3u8"))
+}
+
+/// Checks whether a URI points at a media segment of a rendition
+pub fn is_segment_uri(uri: &str) -> bool {
+    uri.split(['?', '#'])
+        .next()
+        .is_some_and(|path| path.ends_with(".ts") || path.ends_with(".m4s"))
+}
+
+/// Finds the value of a URI="..." attribute in a tag line
+fn attribute_uri(line: &str) -> Option<(usize, usize)> {
+    let start = line.find("URI=\"")? + "URI=\"".len();
+    let end = start + line[start..].find('"')?;
+    Some((start, end))
+}
+
+/// Gets every URI an HLS playlist references, in order, including ones in tag attributes
+pub fn playlist_uris(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            if line.is_empty() {
+                None
+            } else if line.starts_with('#') {
+                attribute_uri(line).map(|(start, end)| &line[start..end])
+            } else {
+                Some(line)
+            }
+        })
+        .collect()
+}
+
+/// Replaces URIs in an HLS playlist with the ones they map to, URIs without a mapping are kept
+pub fn rewrite_playlist(contents: &str, replacements: &HashMap<String, String>) -> String {
+    let mut rewritten = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            rewritten.push_str(line);
+        } else if trimmed.starts_with('#') {
+            match attribute_uri(trimmed).and_then(|(start, end)| {
+                replacements
+                    .get(&trimmed[start..end])
+                    .map(|uri| format!("{}{}{}", &trimmed[..start], uri, &trimmed[end..]))
+            }) {
+                Some(tag) => rewritten.push_str(&tag),
+                None => rewritten.push_str(line),
+            }
+        } else {
+            match replacements.get(trimmed) {
+                Some(uri) => rewritten.push_str(uri),
+                None => rewritten.push_str(line),
+            }
+        }
+        rewritten.push('\n');
+    }
+    rewritten
+}
+
+/// Resolves a URI relative to the playlist that referenced it, both relative to the video root
+/// Returns None for absolute URIs and ones that would escape the video root
+pub fn resolve_relative(playlist_path: &str, uri: &str) -> Option<String> {
+    if uri.contains("://") || uri.starts_with('/') {
+        return None;
+    }
+    let mut segments: Vec<&str> = playlist_path.split('/').collect();
+    // Drop the playlist file name itself
+    segments.pop();
+    for part in uri.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => {
+                segments.pop()?;
+            }
+            part => segments.push(part),
+        }
+    }
+    Some(segments.join("/"))
+}
@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::debug;
+
+use super::stream::{summarize_ffmpeg_output, HLSConverter};
+
+/// File name of the audio waveform peaks, stored next to the master playlist
+pub const WAVEFORM_FILE: &str = "waveform.json";
+/// Sample rate audio is decoded at to find peaks, plenty for a scrubber
+const WAVEFORM_SAMPLE_RATE: u32 = 8000;
+
+/// Settings for the animated hover previews
+#[derive(Debug, Clone)]
+pub struct PreviewSettings {
+    /// How many clips are taken from across the video
+    pub clip_count: u32,
+    /// Length of each clip in seconds
+    pub clip_secs: f64,
+    /// Width of the preview, the height follows the aspect ratio
+    pub width: u32,
+    pub fps: u32,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        PreviewSettings {
+            clip_count: 6,
+            clip_secs: 1.0,
+            width: 320,
+            fps: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PreviewFormat {
+    WebP,
+    MP4,
+}
+
+impl PreviewFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            PreviewFormat::WebP => "preview.webp",
+            PreviewFormat::MP4 => "preview.mp4",
+        }
+    }
+
+    fn get_ffmpeg_args(&self) -> Vec<&'static str> {
+        match self {
+            PreviewFormat::WebP => vec!["-c:v", "libwebp", "-loop", "0", "-q:v", "60"],
+            PreviewFormat::MP4 => vec![
+                "-c:v",
+                "libx264",
+                "-pix_fmt",
+                "yuv420p",
+                "-crf",
+                "28",
+                "-movflags",
+                "+faststart",
+            ],
+        }
+    }
+}
+
+/// Picks where each preview clip starts, spread evenly and skipping the very start and end
+pub fn preview_clip_starts(duration: f64, settings: &PreviewSettings) -> Vec<f64> {
+    let total_secs = settings.clip_secs * settings.clip_count as f64;
+    if duration <= total_secs {
+        // Short videos just get one clip from the start
+        return vec![0.0];
+    }
+    let spacing = duration / (settings.clip_count + 1) as f64;
+    (1..=settings.clip_count)
+        .map(|index| (spacing * index as f64).min(duration - settings.clip_secs))
+        .collect()
+}
+
+/// Audio peaks for drawing a waveform, normalized between 0 and 1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waveform {
+    pub duration: f64,
+    /// How many peaks there are per second of audio
+    pub peaks_per_second: f64,
+    pub peaks: Vec<f32>,
+}
+
+impl Waveform {
+    /// Most peaks a waveform will have, keeps the JSON small for long videos
+    pub const MAX_PEAKS: usize = 4000;
+    /// Peaks per second for videos short enough to not hit the maximum
+    pub const PEAKS_PER_SECOND: f64 = 10.0;
+
+    /// How many samples are reduced into each peak for audio of this duration
+    fn samples_per_peak(duration: f64) -> usize {
+        let peak_count = (duration * Self::PEAKS_PER_SECOND)
+            .ceil()
+            .clamp(1.0, Self::MAX_PEAKS as f64);
+        ((duration * WAVEFORM_SAMPLE_RATE as f64) / peak_count)
+            .ceil()
+            .max(1.0) as usize
+    }
+
+    /// Reduces signed 16 bit mono samples into peaks
+    pub fn from_samples(
+        samples: impl Iterator<Item = i16>,
+        duration: f64,
+        samples_per_peak: usize,
+    ) -> Self {
+        let mut peaks = Vec::new();
+        let mut current: i32 = 0;
+        let mut count = 0;
+        for sample in samples {
+            current = current.max((sample as i32).abs());
+            count += 1;
+            if count == samples_per_peak {
+                peaks.push(current);
+                current = 0;
+                count = 0;
+            }
+        }
+        if count > 0 {
+            peaks.push(current);
+        }
+
+        // Three decimals are plenty for drawing, and keeps the file small
+        let peaks = peaks
+            .into_iter()
+            .map(|peak| ((peak as f32 / i16::MAX as f32).min(1.0) * 1000.0).round() / 1000.0)
+            .collect();
+        Waveform {
+            duration,
+            peaks_per_second: WAVEFORM_SAMPLE_RATE as f64 / samples_per_peak as f64,
+            peaks,
+        }
+    }
+}
+
+impl HLSConverter {
+    /// Creates a short animated preview made of clips from across the video
+    /// Returns the path of the written preview
+    pub fn generate_preview(
+        &self,
+        input_path: &Path,
+        duration: f64,
+        format: PreviewFormat,
+        settings: &PreviewSettings,
+    ) -> Result<PathBuf> {
+        let output_path = self.output_dir.join(format.file_name());
+        let starts = preview_clip_starts(duration, settings);
+
+        // Seeking each clip as its own input avoids decoding the whole video
+        let mut command = Command::new(&self.ffmpeg_path);
+        command.arg("-hide_banner").arg("-y");
+        for start in &starts {
+            command
+                .arg("-ss")
+                .arg(format!("{:.3}", start))
+                .arg("-t")
+                .arg(format!("{:.3}", settings.clip_secs))
+                .arg("-i")
+                .arg(input_path);
+        }
+
+        let mut filter = String::new();
+        for index in 0..starts.len() {
+            filter.push_str(&format!(
+                "[{}:v:0]fps={},scale={}:-2,setsar=1[v{}];",
+                index, settings.fps, settings.width, index
+            ));
+        }
+        for index in 0..starts.len() {
+            filter.push_str(&format!("[v{}]", index));
+        }
+        filter.push_str(&format!("concat=n={}:v=1:a=0[preview]", starts.len()));
+
+        command
+            .arg("-filter_complex")
+            .arg(filter)
+            .arg("-map")
+            .arg("[preview]")
+            .arg("-an")
+            .args(format.get_ffmpeg_args())
+            .arg(&output_path);
+
+        debug!("FFmpeg preview command: {:?}", command);
+        let output = command
+            .output()
+            .context("Failed to execute FFmpeg preview command")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "FFmpeg preview failed: {}",
+                summarize_ffmpeg_output(&String::from_utf8_lossy(&output.stderr))
+            );
+        }
+
+        Ok(output_path)
+    }
+
+    /// Decodes the audio of the video and writes its waveform peaks as JSON
+    /// Returns the path of the written waveform
+    pub fn generate_waveform(&self, input_path: &Path, duration: f64) -> Result<PathBuf> {
+        let output_path = self.output_dir.join(WAVEFORM_FILE);
+
+        let mut command = Command::new(&self.ffmpeg_path);
+        command
+            .arg("-hide_banner")
+            .arg("-v")
+            .arg("error")
+            .arg("-i")
+            .arg(input_path)
+            .arg("-map")
+            .arg("0:a:0")
+            .arg("-ac")
+            .arg("1")
+            .arg("-ar")
+            .arg(WAVEFORM_SAMPLE_RATE.to_string())
+            .arg("-f")
+            .arg("s16le")
+            .arg("-")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        debug!("FFmpeg waveform command: {:?}", command);
+        let mut child = command
+            .spawn()
+            .context("Failed to execute FFmpeg waveform command")?;
+
+        // Read errors as they come, so a chatty stream can't fill the pipe and stall FFmpeg
+        let mut stderr = child.stderr.take().context("FFmpeg stderr unavailable")?;
+        let stderr_reader = std::thread::spawn(move || {
+            let mut errors = String::new();
+            stderr.read_to_string(&mut errors).map(|_| errors)
+        });
+
+        // Long videos produce a lot of samples, so reduce them as they are decoded
+        let mut stdout = BufReader::new(child.stdout.take().context("FFmpeg stdout unavailable")?);
+        let mut buffer = [0u8; 2];
+        let samples = std::iter::from_fn(|| {
+            stdout
+                .read_exact(&mut buffer)
+                .ok()
+                .map(|_| i16::from_le_bytes(buffer))
+        });
+        let waveform =
+            Waveform::from_samples(samples, duration, Waveform::samples_per_peak(duration));
+
+        let status = child
+            .wait()
+            .context("Failed to wait for FFmpeg waveform command")?;
+        let errors = stderr_reader
+            .join()
+            .map_err(|_| anyhow::anyhow!("FFmpeg stderr reader panicked"))?
+            .context("Failed to read FFmpeg waveform errors")?;
+        if !status.success() {
+            anyhow::bail!(
+                "FFmpeg waveform failed: {}",
+                summarize_ffmpeg_output(&errors)
+            );
+        }
+
+        std::fs::write(&output_path, serde_json::to_vec(&waveform)?)
+            .context("Failed to write waveform")?;
+        Ok(output_path)
+    }
+}
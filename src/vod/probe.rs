@@ -0,0 +1,124 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+use super::stream::summarize_ffmpeg_output;
+use crate::error::VodError;
+
+/// Container and stream metadata reported by ffprobe
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProbeInfo {
+    #[serde(default)]
+    pub streams: Vec<ProbeStream>,
+    pub format: Option<ProbeFormat>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProbeStream {
+    pub index: u32,
+    pub codec_type: Option<String>,
+    pub codec_name: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub pix_fmt: Option<String>,
+    pub color_transfer: Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_space: Option<String>,
+    pub r_frame_rate: Option<String>,
+    pub avg_frame_rate: Option<String>,
+    pub duration: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProbeFormat {
+    pub format_name: Option<String>,
+    pub duration: Option<String>,
+    pub size: Option<String>,
+    pub bit_rate: Option<String>,
+}
+
+impl ProbeInfo {
+    /// Gets the first video stream, ignoring attached pictures like cover art
+    pub fn video_stream(&self) -> Option<&ProbeStream> {
+        self.streams.iter().find(|s| {
+            s.codec_type.as_deref() == Some("video")
+                && !matches!(s.codec_name.as_deref(), Some("mjpeg") | Some("png"))
+        })
+    }
+    /// Gets the first audio stream
+    pub fn audio_stream(&self) -> Option<&ProbeStream> {
+        self.streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some("audio"))
+    }
+    /// Gets the duration in seconds, preferring the container over the video stream
+    pub fn duration_secs(&self) -> Option<f64> {
+        self.format
+            .as_ref()
+            .and_then(|f| f.duration.as_deref())
+            .or_else(|| self.video_stream().and_then(|s| s.duration.as_deref()))
+            .and_then(|d| d.parse::<f64>().ok())
+    }
+}
+
+impl ProbeStream {
+    /// Gets the average frame rate, falling back to the nominal rate
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.avg_frame_rate
+            .as_deref()
+            .and_then(parse_frame_rate)
+            .or_else(|| self.r_frame_rate.as_deref().and_then(parse_frame_rate))
+    }
+    /// Whether the frame rate changes throughout the stream, which phones commonly do
+    /// The nominal rate of these streams differs from the average rate
+    pub fn is_variable_frame_rate(&self) -> bool {
+        match (
+            self.r_frame_rate.as_deref().and_then(parse_frame_rate),
+            self.avg_frame_rate.as_deref().and_then(parse_frame_rate),
+        ) {
+            (Some(nominal), Some(average)) => (nominal - average).abs() > 0.01 * nominal,
+            _ => false,
+        }
+    }
+}
+
+/// Parses an ffprobe rational like "30000/1001" into frames per second
+fn parse_frame_rate(rate: &str) -> Option<f64> {
+    let (numerator, denominator) = match rate.split_once('/') {
+        Some((numerator, denominator)) => (
+            numerator.parse::<f64>().ok()?,
+            denominator.parse::<f64>().ok()?,
+        ),
+        None => (rate.parse::<f64>().ok()?, 1.0),
+    };
+    // ffprobe reports "0/0" when it doesn't know
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return None;
+    }
+    Some(numerator / denominator)
+}
+
+/// Runs ffprobe against the input and parses its JSON output
+pub fn probe(ffprobe_path: &Path, input_path: &Path) -> Result<ProbeInfo, VodError> {
+    debug!("Probing {:?}", input_path);
+    let output = Command::new(ffprobe_path)
+        .arg("-v")
+        .arg("error")
+        .arg("-print_format")
+        .arg("json")
+        .arg("-show_format")
+        .arg("-show_streams")
+        .arg(input_path)
+        .output()
+        .map_err(|e| VodError::UnreadableSource(format!("Failed to execute ffprobe: {}", e)))?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        debug!("ffprobe error output: {}", error);
+        return Err(VodError::UnreadableSource(summarize_ffmpeg_output(&error)));
+    }
+
+    serde_json::from_slice::<ProbeInfo>(&output.stdout)
+        .map_err(|e| VodError::UnreadableSource(format!("Invalid ffprobe output: {}", e)))
+}
@@ -3,6 +3,9 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 use tracing::{debug, warn};
 
+use super::probe::ProbeInfo;
+use crate::error::VodError;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum VideoFormat {
     MP4,
@@ -10,7 +13,7 @@ pub enum VideoFormat {
 }
 
 impl VideoFormat {
-    fn from_path(path: &Path) -> Result<Self> {
+    pub(crate) fn from_path(path: &Path) -> Result<Self> {
         let extension = path
             .extension()
             .and_then(|ext| ext.to_str())
@@ -64,6 +67,8 @@ pub struct Quality {
     pub height: u32,
     pub bitrate: String,
     pub name: String,
+    /// Highest frame rate of the rendition, sources with less keep their own rate
+    pub fps: u32,
 }
 
 impl Quality {
@@ -78,8 +83,120 @@ impl Quality {
             height,
             bitrate: bitrate.into(),
             name: name.into(),
+            fps: 30,
+        }
+    }
+
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// Gets the lowest H.264 level that fits the resolution and frame rate
+    fn h264_level(&self, fps: f64) -> &'static str {
+        match (self.height, fps > 30.0) {
+            (h, true) if h > 720 => "4.2",
+            (h, false) if h > 720 => "4.0",
+            (_, true) => "3.2",
+            (_, false) => "3.1",
+        }
+    }
+}
+
+/// HDR transfer characteristics, which need tone mapping to look right as SDR
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HdrTransfer {
+    /// Perceptual quantizer, used by HDR10 and Dolby Vision
+    Pq,
+    /// Hybrid log-gamma, used by broadcast HDR and most phones
+    Hlg,
+}
+
+impl HdrTransfer {
+    pub fn from_color_transfer(color_transfer: &str) -> Option<Self> {
+        match color_transfer {
+            "smpte2084" => Some(HdrTransfer::Pq),
+            "arib-std-b67" => Some(HdrTransfer::Hlg),
+            _ => None,
+        }
+    }
+
+    /// Filters converting HDR frames to BT.709 SDR
+    /// The input transfer, primaries and matrix are given outright, many files don't tag their frames
+    fn tone_map_filter(&self) -> &'static str {
+        match self {
+            HdrTransfer::Pq => {
+                "zscale=tin=smpte2084:pin=bt2020:min=bt2020nc:t=linear:npl=100,format=gbrpf32le,\
+                zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv"
+            }
+            HdrTransfer::Hlg => {
+                "zscale=tin=arib-std-b67:pin=bt2020:min=bt2020nc:t=linear:npl=100,format=gbrpf32le,\
+                zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv"
+            }
+        }
+    }
+}
+
+/// What the conversion needs to know about the source beyond its dimensions
+#[derive(Debug, Clone, Default)]
+pub struct SourceProfile {
+    pub hdr: Option<HdrTransfer>,
+    pub frame_rate: Option<f64>,
+    pub variable_frame_rate: bool,
+}
+
+impl SourceProfile {
+    pub fn from_probe(info: &ProbeInfo) -> Self {
+        let Some(stream) = info.video_stream() else {
+            return SourceProfile::default();
+        };
+        SourceProfile {
+            hdr: stream
+                .color_transfer
+                .as_deref()
+                .and_then(HdrTransfer::from_color_transfer),
+            frame_rate: stream.frame_rate(),
+            variable_frame_rate: stream.is_variable_frame_rate(),
         }
     }
+
+    /// Whether the source has enough frames for a 60 fps rendition
+    pub fn supports_high_frame_rate(&self) -> bool {
+        self.frame_rate.is_some_and(|fps| fps >= 48.0)
+    }
+}
+
+/// Frame rates renditions get normalized to, with the rational ffmpeg expects
+const STANDARD_FRAME_RATES: [(f64, &str); 9] = [
+    (23.976, "24000/1001"),
+    (24.0, "24"),
+    (25.0, "25"),
+    (29.97, "30000/1001"),
+    (30.0, "30"),
+    (48.0, "48"),
+    (50.0, "50"),
+    (59.94, "60000/1001"),
+    (60.0, "60"),
+];
+
+/// Picks the constant frame rate for a rendition
+/// Sources faster than the rendition allows are divided down evenly, so frames are dropped
+/// at a steady cadence instead of stuttering, and then snapped to the nearest standard rate
+pub fn output_frame_rate(source_fps: Option<f64>, max_fps: u32) -> (f64, &'static str) {
+    let max_fps = max_fps as f64;
+    let source = source_fps.unwrap_or(max_fps);
+    let mut fps = source;
+    let mut divisor = 2.0;
+    while fps > max_fps + 0.5 {
+        fps = source / divisor;
+        divisor += 1.0;
+    }
+    STANDARD_FRAME_RATES
+        .iter()
+        .filter(|(rate, _)| *rate <= max_fps)
+        .min_by(|a, b| (a.0 - fps).abs().total_cmp(&(b.0 - fps).abs()))
+        .copied()
+        .unwrap_or((30.0, "30"))
 }
 
 impl HLSConverter {
@@ -132,7 +249,7 @@ impl HLSConverter {
         }
 
         // If the above fails, try using ffprobe
-        let probe_output = Command::new(&self.ffmpeg_path.with_file_name("ffprobe"))
+        let probe_output = Command::new(self.ffprobe_path())
             .arg("-v")
             .arg("error")
             .arg("-select_streams")
@@ -159,7 +276,7 @@ impl HLSConverter {
         anyhow::bail!("Could not determine video dimensions")
     }
 
-    fn verify_dimensions(&self, width: u32, height: u32) -> Result<()> {
+    pub(crate) fn verify_dimensions(&self, width: u32, height: u32) -> Result<()> {
         if width == 0 || height == 0 {
             anyhow::bail!("Invalid dimensions: {}x{}", width, height);
         }
@@ -186,6 +303,11 @@ impl HLSConverter {
         })
     }
 
+    /// Gets the path to ffprobe, which is expected to live next to ffmpeg
+    pub fn ffprobe_path(&self) -> PathBuf {
+        self.ffmpeg_path.with_file_name("ffprobe")
+    }
+
     fn validate_input_format(&self, input_path: &Path) -> Result<VideoFormat> {
         VideoFormat::from_path(input_path)
     }
@@ -194,6 +316,7 @@ impl HLSConverter {
         &self,
         input_path: P,
         mut qualities: Vec<Quality>,
+        profile: &SourceProfile,
     ) -> Result<()> {
         let input_path = input_path.as_ref();
         if !input_path.exists() {
@@ -227,6 +350,16 @@ impl HLSConverter {
             );
         }
 
+        if let Some(hdr) = profile.hdr {
+            debug!("Tone mapping {:?} HDR source to SDR", hdr);
+        }
+        if profile.variable_frame_rate {
+            debug!(
+                "Normalizing variable frame rate source ({:?} fps average)",
+                profile.frame_rate
+            );
+        }
+
         // Create variant playlist
         let mut master_playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
 
@@ -235,13 +368,15 @@ impl HLSConverter {
             let output_name = format!("stream_{}", quality.name);
             let playlist_name = format!("{}.m3u8", output_name);
             let segment_pattern = format!("{}_segment_%03d.ts", output_name);
+            let frame_rate = output_frame_rate(profile.frame_rate, quality.fps);
 
             // Add to variant playlist with updated path that includes quality directory
             master_playlist.push_str(&format!(
-                "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},NAME=\"{}\"\n{}/{}\n",
+                "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},FRAME-RATE={:.3},NAME=\"{}\"\n{}/{}\n",
                 quality.bitrate.replace("k", "000"),
                 quality.width,
                 quality.height,
+                frame_rate.0,
                 quality.name,
                 quality.name,
                 playlist_name
@@ -250,11 +385,11 @@ impl HLSConverter {
             // Convert for this quality
             self.convert_quality(
                 input_path,
-                &output_name,
                 quality,
                 &playlist_name,
                 &segment_pattern,
                 &format,
+                profile,
             )
             .with_context(|| {
                 format!(
@@ -274,12 +409,13 @@ impl HLSConverter {
     fn convert_quality(
         &self,
         input_path: &Path,
-        _output_name: &str,
         quality: &Quality,
         playlist_name: &str,
         segment_pattern: &str,
         format: &VideoFormat,
+        profile: &SourceProfile,
     ) -> Result<()> {
+        let (fps, fps_arg) = output_frame_rate(profile.frame_rate, quality.fps);
         // Create quality-specific directory
         let quality_dir = self.output_dir.join(&quality.name);
         std::fs::create_dir_all(&quality_dir)
@@ -294,9 +430,32 @@ impl HLSConverter {
             command.arg(arg);
         }
 
+        // Tone map HDR sources before scaling, then make sure the output is 8 bit SDR
+        let mut filters = Vec::new();
+        if let Some(hdr) = profile.hdr {
+            filters.push(hdr.tone_map_filter().to_string());
+        }
+        filters.push(format!("scale={}:{}", quality.width, quality.height));
+        filters.push("format=yuv420p".to_string());
+        command.arg("-vf").arg(filters.join(","));
+        if profile.hdr.is_some() {
+            command
+                .arg("-color_primaries")
+                .arg("bt709")
+                .arg("-color_trc")
+                .arg("bt709")
+                .arg("-colorspace")
+                .arg("bt709");
+        }
+
+        // Keyframes every two seconds
+        let gop_size = ((fps * 2.0).round() as u32).to_string();
         command
-            .arg("-vsync")
-            .arg("0")
+            // Constant frame rate, variable rate sources stutter otherwise
+            .arg("-fps_mode")
+            .arg("cfr")
+            .arg("-r")
+            .arg(fps_arg)
             // Video encoding settings
             .arg("-c:v")
             .arg("libx264")
@@ -321,18 +480,15 @@ impl HLSConverter {
             .arg("-profile:v")
             .arg("main")
             .arg("-level")
-            .arg("3.1")
+            .arg(quality.h264_level(fps))
             .arg("-g")
-            .arg("60")
+            .arg(&gop_size)
             .arg("-keyint_min")
-            .arg("60")
+            .arg(&gop_size)
             .arg("-sc_threshold")
             .arg("0")
             .arg("-force_key_frames")
             .arg("expr:gte(t,n_forced*6)")
-            // Resolution
-            .arg("-s")
-            .arg(format!("{}x{}", quality.width, quality.height))
             // Audio settings
             .arg("-ar")
             .arg("48000")
@@ -360,7 +516,7 @@ impl HLSConverter {
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
             debug!("FFmpeg error output: {}", error);
-            anyhow::bail!("FFmpeg failed: {}", error);
+            return Err(VodError::EncodingFailed(summarize_ffmpeg_output(&error)).into());
         }
 
         Ok(())
@@ -384,6 +540,20 @@ impl HLSConverter {
     }
 }
 
+/// Condenses ffmpeg's stderr down to its last few meaningful lines
+pub(crate) fn summarize_ffmpeg_output(stderr: &str) -> String {
+    const MAX_LINES: usize = 5;
+    const MAX_CHARS: usize = 1000;
+
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let summary = lines[lines.len().saturating_sub(MAX_LINES)..].join("\n");
+    summary.chars().take(MAX_CHARS).collect()
+}
+
 /// Get the path to ffmpeg
 pub fn get_ffmpeg_location() -> PathBuf {
     let env_ffmpeg_path = PathBuf::from(
@@ -396,3 +566,17 @@ pub fn get_ffmpeg_location() -> PathBuf {
 
     env_ffmpeg_path
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_mapping_names_the_input_transfer() {
+        let pq = HdrTransfer::Pq.tone_map_filter();
+        let hlg = HdrTransfer::Hlg.tone_map_filter();
+        assert!(pq.starts_with("zscale=tin=smpte2084:pin=bt2020:min=bt2020nc:"));
+        assert!(hlg.starts_with("zscale=tin=arib-std-b67:pin=bt2020:min=bt2020nc:"));
+        assert!(!pq.contains(' ') && !hlg.contains(' '));
+    }
+}
@@ -0,0 +1,262 @@
+use std::path::Path;
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    error::{StorageError, UploadError},
+    storage::{Storage, UploadedPart},
+};
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+
+/// Smallest part storage accepts in a multipart upload, except for the last one
+pub const MIN_PART_SIZE: u64 = 5 * MIB;
+/// Biggest part storage accepts in a multipart upload
+pub const MAX_PART_SIZE: u64 = 5 * GIB;
+/// How many bytes from the start of an upload are read to check what it contains
+const SNIFF_BYTES: u64 = 16;
+/// Size of the parts streamed uploads are sent to storage in, only one is held in memory at a time
+pub const STREAM_PART_SIZE: usize = 8 * MIB as usize;
+
+/// Gets the type and subtype of a content type, without any parameters
+pub fn content_type_essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Containers that can be uploaded, limited to what processing can handle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadFormat {
+    Mp4,
+    Mov,
+}
+
+impl UploadFormat {
+    /// Gets the format from a file extension, ignoring case
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.trim().to_ascii_lowercase().as_str() {
+            "mp4" | "m4v" => Some(UploadFormat::Mp4),
+            "mov" | "qt" => Some(UploadFormat::Mov),
+            _ => None,
+        }
+    }
+    /// Gets the format from the extension of a file name or key
+    pub fn from_key(key: &str) -> Option<Self> {
+        Path::new(key)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_extension)
+    }
+    /// The extension the raw video is stored with, whatever the uploaded file was called
+    pub fn extension(&self) -> &'static str {
+        match self {
+            UploadFormat::Mp4 => "mp4",
+            UploadFormat::Mov => "mov",
+        }
+    }
+    /// The content type the raw video is stored with
+    pub fn content_type(&self) -> &'static str {
+        self.content_types()[0]
+    }
+    /// MIME types clients send for the format
+    fn content_types(&self) -> &'static [&'static str] {
+        match self {
+            UploadFormat::Mp4 => &["video/mp4", "video/x-m4v"],
+            UploadFormat::Mov => &["video/quicktime"],
+        }
+    }
+    /// Checks whether a content type, with or without parameters, is one the format is sent as
+    pub fn accepts_content_type(&self, content_type: &str) -> bool {
+        self.content_types()
+            .contains(&content_type_essence(content_type).as_str())
+    }
+    /// Gets the format a content type is for, if it's one that can be uploaded
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        [UploadFormat::Mp4, UploadFormat::Mov]
+            .into_iter()
+            .find(|format| format.accepts_content_type(content_type))
+    }
+    /// Works out the format from the first bytes of a file
+    /// Both are ISO base media files, QuickTime is told apart by its brand or its older atoms
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        match head.get(4..8)? {
+            b"ftyp" => match head.get(8..12)? {
+                b"qt  " => Some(UploadFormat::Mov),
+                _ => Some(UploadFormat::Mp4),
+            },
+            b"moov" | b"mdat" | b"wide" | b"free" | b"skip" | b"pnot" => Some(UploadFormat::Mov),
+            _ => None,
+        }
+    }
+}
+
+/// Limits on what a single upload can be
+#[derive(Debug, Clone)]
+pub struct UploadLimits {
+    pub max_size_bytes: u64,
+    pub max_parts: i32,
+}
+
+impl UploadLimits {
+    /// Reads limits from UPLOAD_MAX_SIZE_BYTES and UPLOAD_MAX_PARTS,
+    /// defaulting to 50 GiB and the 10,000 parts storage allows
+    pub fn from_env() -> Self {
+        UploadLimits {
+            max_size_bytes: std::env::var("UPLOAD_MAX_SIZE_BYTES")
+                .ok()
+                .map(|size| {
+                    size.parse()
+                        .expect("UPLOAD_MAX_SIZE_BYTES must be a number")
+                })
+                .unwrap_or(50 * GIB),
+            max_parts: std::env::var("UPLOAD_MAX_PARTS")
+                .ok()
+                .map(|parts| parts.parse().expect("UPLOAD_MAX_PARTS must be a number"))
+                .unwrap_or(10_000),
+        }
+    }
+    /// Checks the declared size of an upload
+    pub fn check_size(&self, size: u64) -> Result<(), UploadError> {
+        if size == 0 {
+            return Err(UploadError::InvalidSize("the file is empty".to_string()));
+        }
+        if size > self.max_size_bytes {
+            return Err(UploadError::TooLarge {
+                size,
+                max: self.max_size_bytes,
+            });
+        }
+        Ok(())
+    }
+    /// Checks that a file of the given size can be uploaded in the given number of parts
+    /// Every part but the last has to be at least the minimum part size, and none over the maximum
+    pub fn check_parts(&self, size: u64, parts: i32) -> Result<(), UploadError> {
+        if !(1..=self.max_parts).contains(&parts) {
+            return Err(UploadError::InvalidParts(format!(
+                "uploads need between 1 and {} parts",
+                self.max_parts
+            )));
+        }
+        let parts = parts as u64;
+        if parts > size.div_ceil(MIN_PART_SIZE).max(1) {
+            return Err(UploadError::InvalidParts(format!(
+                "{} parts would be smaller than {} bytes",
+                parts, MIN_PART_SIZE
+            )));
+        }
+        if size.div_ceil(parts) > MAX_PART_SIZE {
+            return Err(UploadError::InvalidParts(format!(
+                "{} parts would be bigger than {} bytes",
+                parts, MAX_PART_SIZE
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the file name and content type of an upload, returning the format it will be stored as
+pub fn check_format(key: &str, content_type: &str) -> Result<UploadFormat, UploadError> {
+    let format = UploadFormat::from_key(key)
+        .ok_or_else(|| UploadError::UnsupportedExtension(key.to_string()))?;
+    if !format.accepts_content_type(content_type) {
+        return Err(UploadError::UnsupportedContentType(
+            content_type.to_string(),
+        ));
+    }
+    Ok(format)
+}
+
+/// Checks that the first bytes of an upload are what its format says they should be
+pub fn check_contents(format: UploadFormat, head: &[u8]) -> Result<(), UploadError> {
+    match UploadFormat::sniff(head) {
+        Some(sniffed) if sniffed == format => Ok(()),
+        Some(sniffed) => Err(UploadError::ContentMismatch(format!(
+            "expected {} but found {}",
+            format.extension(),
+            sniffed.extension()
+        ))),
+        None => Err(UploadError::ContentMismatch(format!(
+            "not a {} file",
+            format.extension()
+        ))),
+    }
+}
+
+/// Reads the first bytes of an object, enough to sniff what it contains
+pub async fn read_head(storage: &dyn Storage, key: &str) -> Result<Vec<u8>, StorageError> {
+    let reader = storage.get(key).await?;
+    let mut head = Vec::new();
+    reader.take(SNIFF_BYTES).read_to_end(&mut head).await?;
+    Ok(head)
+}
+
+/// Sends a stream of bytes to storage as parts of a multipart upload, then completes it
+/// The stream is cut off once it's bigger than the maximum, which is all that keeps uploads of
+/// unknown size in check
+/// Returns how many bytes were uploaded
+pub async fn upload_stream<E: std::fmt::Display>(
+    storage: &dyn Storage,
+    key: &str,
+    upload_id: &str,
+    body: impl Stream<Item = Result<Bytes, E>>,
+    max_bytes: u64,
+) -> Result<u64, UploadError> {
+    let mut body = std::pin::pin!(body);
+    let mut buffer = BytesMut::new();
+    let mut parts: Vec<UploadedPart> = Vec::new();
+    let mut received: u64 = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| UploadError::Interrupted(e.to_string()))?;
+        received += chunk.len() as u64;
+        if received > max_bytes {
+            return Err(UploadError::TooLarge {
+                size: received,
+                max: max_bytes,
+            });
+        }
+        buffer.extend_from_slice(&chunk);
+        while buffer.len() >= STREAM_PART_SIZE {
+            let part = buffer.split_to(STREAM_PART_SIZE).freeze();
+            let part_number = parts.len() as i32 + 1;
+            parts.push(upload_part(storage, key, upload_id, part_number, part).await?);
+        }
+    }
+    if received == 0 {
+        return Err(UploadError::InvalidSize("the file is empty".to_string()));
+    }
+    if !buffer.is_empty() {
+        let part_number = parts.len() as i32 + 1;
+        parts.push(upload_part(storage, key, upload_id, part_number, buffer.freeze()).await?);
+    }
+
+    storage
+        .complete_multipart_upload(key, upload_id, &parts)
+        .await?;
+    Ok(received)
+}
+
+/// Uploads a single part of a streamed upload
+pub async fn upload_part(
+    storage: &dyn Storage,
+    key: &str,
+    upload_id: &str,
+    part_number: i32,
+    body: Bytes,
+) -> Result<UploadedPart, StorageError> {
+    let size = body.len() as u64;
+    let etag = storage
+        .upload_part(key, upload_id, part_number, body)
+        .await?;
+    Ok(UploadedPart {
+        part_number,
+        etag,
+        size: Some(size),
+    })
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+use super::probe::{probe, ProbeInfo};
+use super::stream::{summarize_ffmpeg_output, HLSConverter, VideoFormat};
+use crate::error::VodError;
+
+/// Longest source we accept, in seconds (12 hours)
+pub const MAX_DURATION_SECS: f64 = 12.0 * 60.0 * 60.0;
+/// How many seconds of the start and end of the source get test decoded
+pub const DECODE_SAMPLE_SECS: u32 = 10;
+
+impl HLSConverter {
+    /// Runs pre-flight checks against a source before any transcoding happens
+    /// Checks the container, stream sanity, duration, and that the start and end of the video decode
+    pub fn validate_source(&self, input_path: &Path) -> Result<ProbeInfo, VodError> {
+        VideoFormat::from_path(input_path).map_err(|e| {
+            let extension = input_path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("unknown")
+                .to_uppercase();
+            debug!("Rejecting source format: {}", e);
+            VodError::UnsupportedFormat(extension)
+        })?;
+
+        let info = probe(&self.ffprobe_path(), input_path)?;
+
+        let video_stream = info.video_stream().ok_or(VodError::NoVideoStream)?;
+        let (width, height) = (
+            video_stream.width.unwrap_or(0),
+            video_stream.height.unwrap_or(0),
+        );
+        self.verify_dimensions(width, height)
+            .map_err(|_| VodError::InvalidDimensions(width, height))?;
+
+        match info.duration_secs() {
+            None => {
+                return Err(VodError::InvalidDuration(
+                    "the duration could not be determined".to_string(),
+                ))
+            }
+            Some(duration) if duration <= 0.0 => {
+                return Err(VodError::InvalidDuration("the video is empty".to_string()))
+            }
+            Some(duration) if duration > MAX_DURATION_SECS => {
+                return Err(VodError::InvalidDuration(
+                    "videos must be shorter than 12 hours".to_string(),
+                ))
+            }
+            Some(_) => {}
+        }
+
+        // Decode the head and the tail, truncated uploads usually only fail near the end
+        let sample_secs = DECODE_SAMPLE_SECS.to_string();
+        self.decode_check(input_path, &[], &["-t", &sample_secs])?;
+        self.decode_check(input_path, &["-sseof", &format!("-{}", sample_secs)], &[])?;
+
+        Ok(info)
+    }
+
+    /// Decodes part of the video to a null muxer, failing on the first decoding error
+    fn decode_check(
+        &self,
+        input_path: &Path,
+        input_args: &[&str],
+        output_args: &[&str],
+    ) -> Result<(), VodError> {
+        let mut command = Command::new(&self.ffmpeg_path);
+        command.arg("-v").arg("error").arg("-xerror");
+        command.args(input_args);
+        command
+            .arg("-i")
+            .arg(input_path)
+            .args(output_args)
+            .arg("-map")
+            .arg("0:v:0")
+            .arg("-f")
+            .arg("null")
+            .arg("-");
+
+        debug!("FFmpeg decode check command: {:?}", command);
+        let output = command
+            .output()
+            .map_err(|e| VodError::UnreadableSource(format!("Failed to execute FFmpeg: {}", e)))?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            debug!("FFmpeg decode check output: {}", error);
+            return Err(VodError::CorruptSource(summarize_ffmpeg_output(&error)));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+//! Checks how view sessions are counted against the viewers starting them
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{create_user, create_video};
+use farmhand::db::{
+    analytics::{NewViewSession, ViewSession},
+    users::UserRole,
+    Video,
+};
+use sqlx::{types::Uuid, PgPool};
+
+async fn start_anonymous_session(pool: &PgPool, video: &Video, client_key: &str) {
+    let id = Uuid::new_v4();
+    ViewSession::create(
+        pool,
+        NewViewSession {
+            id,
+            video_id: &video.id,
+            viewer_id: None,
+            viewer_key: &format!("session:{}", id),
+            client_key: Some(client_key),
+            at: Utc::now(),
+            position_seconds: 0.0,
+            buckets: Vec::new(),
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[sqlx::test]
+async fn anonymous_sessions_are_counted_by_client(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Viewer, None).await;
+    let video = create_video(&pool, user_id, "Video").await;
+    let other_video = create_video(&pool, user_id, "Other video").await;
+    // Fresh sessions each time still add up for the same client, on any video
+    for _ in 0..3 {
+        start_anonymous_session(&pool, &video, "client").await;
+    }
+    start_anonymous_session(&pool, &other_video, "client").await;
+    start_anonymous_session(&pool, &video, "other client").await;
+
+    let hour_ago = Utc::now() - Duration::hours(1);
+    let count = |client_key: &'static str, since| {
+        let pool = pool.clone();
+        async move {
+            ViewSession::count_started_by_client_since(&pool, client_key, since)
+                .await
+                .unwrap()
+        }
+    };
+    assert_eq!(count("client", hour_ago).await, 4);
+    assert_eq!(count("other client", hour_ago).await, 1);
+    assert_eq!(count("client", Utc::now() + Duration::minutes(1)).await, 0);
+}
@@ -0,0 +1,32 @@
+//! Fixtures shared between the integration tests
+// Each test file only uses some of these
+#![allow(dead_code)]
+
+use farmhand::db::{users::UserRole, Video};
+use sqlx::{types::Uuid, PgPool};
+
+/// Creates a user with the given role and, if any, a quota of their own
+pub async fn create_user(pool: &PgPool, role: UserRole, quota: Option<i64>) -> Uuid {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO users (username, email, password_hash, role, storage_quota_bytes)
+        VALUES ($1, $1 || '@example.com', 'hash', $2, $3)
+        RETURNING id
+        "#,
+    )
+    .bind(format!("user{}", Uuid::new_v4().simple()))
+    .bind(role)
+    .bind(quota)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+/// Creates a video waiting for its raw source to be uploaded
+pub async fn create_video(pool: &PgPool, user_id: Uuid, title: &str) -> Video {
+    let id = Video::gen_id();
+    let path = format!("videos/{}/raw.mp4", id);
+    Video::create(pool, Some(id), user_id, title.to_string(), Some(path), None)
+        .await
+        .unwrap()
+}
@@ -0,0 +1,182 @@
+//! Imports videos from a local server into memory storage
+
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use bytes::Bytes;
+use common::{create_user, create_video};
+use farmhand::{
+    db::{users::UserRole, Video},
+    error::{ImportError, UploadError},
+    queue::import::{import_client, import_video, ImportSettings},
+    storage::{MemoryStorage, Storage},
+    vod::upload::UploadLimits,
+};
+use sqlx::PgPool;
+
+const MAX_SIZE_BYTES: usize = 4096;
+
+/// The start of an MP4 file, enough for it to be recognized as one
+fn mp4_bytes(size: usize) -> Vec<u8> {
+    let mut bytes = b"\x00\x00\x00\x18ftypisom\x00\x00\x02\x00isomiso2".to_vec();
+    bytes.resize(size, 0);
+    bytes
+}
+
+fn mp4_response(body: Body) -> Response {
+    ([(header::CONTENT_TYPE, "video/mp4")], body).into_response()
+}
+
+/// Serves files to import on a random local port
+async fn serve() -> SocketAddr {
+    let app = Router::new()
+        .route(
+            "/video.mp4",
+            get(|| async { mp4_response(mp4_bytes(1000).into()) }),
+        )
+        .route(
+            "/large.mp4",
+            get(|| async { mp4_response(mp4_bytes(MAX_SIZE_BYTES + 1).into()) }),
+        )
+        // Streamed without a length, so it has to be cut off on the way in
+        .route(
+            "/streamed.mp4",
+            get(|| async {
+                let chunks = mp4_bytes(MAX_SIZE_BYTES * 2)
+                    .chunks(1024)
+                    .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+                    .collect::<Vec<_>>();
+                mp4_response(Body::from_stream(futures::stream::iter(chunks)))
+            }),
+        )
+        .route(
+            "/fake.mp4",
+            get(|| async { mp4_response("<html>not a video</html>".into()) }),
+        )
+        .route(
+            "/redirect.mp4",
+            get(|| async { Redirect::temporary("http://10.0.0.1/video.mp4") }),
+        )
+        .route(
+            "/missing.mp4",
+            get(|| async { StatusCode::NOT_FOUND.into_response() }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn settings(allow_private_addresses: bool) -> ImportSettings {
+    ImportSettings {
+        limits: UploadLimits {
+            max_size_bytes: MAX_SIZE_BYTES as u64,
+            max_parts: 10_000,
+        },
+        max_redirects: 5,
+        connect_timeout: Duration::from_secs(5),
+        read_timeout: Duration::from_secs(5),
+        allow_private_addresses,
+    }
+}
+
+/// Creates a video waiting to be imported from the URL
+async fn create_import(pool: &PgPool, url: &str) -> Video {
+    let user_id = create_user(pool, UserRole::Creator, None).await;
+    let video = create_video(pool, user_id, "Import").await;
+    Video::set_import_url(pool, &video.id, url).await.unwrap();
+    Video::by_id(pool, &video.id).await.unwrap()
+}
+
+/// Imports a file from the local server, returning what's left in storage afterwards
+async fn import(
+    pool: &PgPool,
+    path: &str,
+    client_settings: &ImportSettings,
+) -> (Result<u64, anyhow::Error>, Video, MemoryStorage) {
+    let addr = serve().await;
+    let video = create_import(pool, &format!("http://{}{}", addr, path)).await;
+    let storage = MemoryStorage::new();
+    let client = import_client(client_settings).unwrap();
+    let result = import_video(pool, &storage, &client, &settings(true), &video).await;
+    let video = Video::by_id(pool, &video.id).await.unwrap();
+    (result, video, storage)
+}
+
+fn import_error(result: Result<u64, anyhow::Error>) -> ImportError {
+    result.unwrap_err().downcast::<ImportError>().unwrap()
+}
+
+#[sqlx::test]
+async fn imports_a_video(pool: PgPool) {
+    let (result, video, storage) = import(&pool, "/video.mp4", &settings(true)).await;
+    assert_eq!(result.unwrap(), 1000);
+    assert_eq!(video.raw_size_bytes, Some(1000));
+    assert_eq!(video.upload_id, None);
+    let meta = storage.head(&video.raw_video_path).await.unwrap();
+    assert_eq!(meta.size, 1000);
+    assert_eq!(meta.content_type.as_deref(), Some("video/mp4"));
+}
+
+#[sqlx::test]
+async fn refuses_videos_bigger_than_the_limit(pool: PgPool) {
+    let (result, video, storage) = import(&pool, "/large.mp4", &settings(true)).await;
+    assert!(matches!(
+        import_error(result),
+        ImportError::Upload(UploadError::TooLarge { .. })
+    ));
+    assert_eq!(video.raw_size_bytes, None);
+    assert!(storage.list("").await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn cuts_off_streamed_videos_bigger_than_the_limit(pool: PgPool) {
+    let (result, video, storage) = import(&pool, "/streamed.mp4", &settings(true)).await;
+    assert!(matches!(
+        import_error(result),
+        ImportError::Upload(UploadError::TooLarge { .. })
+    ));
+    assert_eq!(video.raw_size_bytes, None);
+    assert_eq!(video.upload_id, None);
+    assert!(storage.list("").await.unwrap().is_empty());
+    assert!(storage.list_multipart_uploads("").await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn refuses_videos_that_are_not_what_they_claim(pool: PgPool) {
+    let (result, video, storage) = import(&pool, "/fake.mp4", &settings(true)).await;
+    assert!(matches!(
+        import_error(result),
+        ImportError::Upload(UploadError::ContentMismatch(_))
+    ));
+    assert_eq!(video.raw_size_bytes, None);
+    assert_eq!(video.upload_id, None);
+    assert!(storage.list("").await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn refuses_redirects_to_private_addresses(pool: PgPool) {
+    // The import itself is let through to the local server, so it's the client that has to
+    // refuse where it's sent next
+    let (result, video, storage) = import(&pool, "/redirect.mp4", &settings(false)).await;
+    assert!(matches!(
+        import_error(result),
+        ImportError::BlockedAddress(host) if host == "10.0.0.1"
+    ));
+    assert_eq!(video.raw_size_bytes, None);
+    assert!(storage.list("").await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn reports_unexpected_statuses(pool: PgPool) {
+    let (result, _, _) = import(&pool, "/missing.mp4", &settings(true)).await;
+    assert!(matches!(import_error(result), ImportError::Status(404)));
+}
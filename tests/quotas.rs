@@ -0,0 +1,153 @@
+//! Checks that uploads are held against the quota of their owner, even when they start at once
+
+mod common;
+
+use common::{create_user, create_video};
+use farmhand::db::{
+    quotas::{record_raw_size, reserve_upload, StorageUsage},
+    users::UserRole,
+    Video,
+};
+use sqlx::{types::Uuid, PgPool};
+
+#[sqlx::test]
+async fn reserves_uploads_within_the_quota(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Creator, Some(100)).await;
+    let first = create_video(&pool, user_id, "Upload").await;
+    let second = create_video(&pool, user_id, "Upload").await;
+
+    assert!(reserve_upload(&pool, user_id, &first.id, "a", 60)
+        .await
+        .unwrap());
+    assert!(!reserve_upload(&pool, user_id, &second.id, "b", 60)
+        .await
+        .unwrap());
+    assert!(reserve_upload(&pool, user_id, &second.id, "b", 40)
+        .await
+        .unwrap());
+    let usage = StorageUsage::by_user_id(user_id, &pool).await.unwrap();
+    assert_eq!(usage.reserved_bytes(), 100);
+}
+
+#[sqlx::test]
+async fn uploads_started_at_once_cannot_share_the_last_of_the_quota(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Creator, Some(100)).await;
+    let mut videos = Vec::new();
+    for _ in 0..8 {
+        videos.push(create_video(&pool, user_id, "Upload").await);
+    }
+
+    let reservations = videos.iter().map(|video| {
+        let pool = pool.clone();
+        let video_id = video.id.clone();
+        tokio::spawn(async move { reserve_upload(&pool, user_id, &video_id, "upload", 60).await })
+    });
+    let mut reserved = 0;
+    for reservation in reservations.collect::<Vec<_>>() {
+        if reservation.await.unwrap().unwrap() {
+            reserved += 1;
+        }
+    }
+    assert_eq!(reserved, 1);
+    let usage = StorageUsage::by_user_id(user_id, &pool).await.unwrap();
+    assert_eq!(usage.total_bytes(), 60);
+}
+
+#[sqlx::test]
+async fn records_raw_sizes_bigger_than_declared_only_within_the_quota(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Creator, Some(100)).await;
+    let first = create_video(&pool, user_id, "Upload").await;
+    let second = create_video(&pool, user_id, "Upload").await;
+    assert!(reserve_upload(&pool, user_id, &first.id, "a", 50)
+        .await
+        .unwrap());
+    assert!(reserve_upload(&pool, user_id, &second.id, "b", 50)
+        .await
+        .unwrap());
+
+    // Smaller than declared always fits, and frees what wasn't needed
+    assert!(record_raw_size(&pool, user_id, &first.id, 30)
+        .await
+        .unwrap());
+    assert!(!record_raw_size(&pool, user_id, &second.id, 80)
+        .await
+        .unwrap());
+    assert!(record_raw_size(&pool, user_id, &second.id, 70)
+        .await
+        .unwrap());
+
+    let usage = StorageUsage::by_user_id(user_id, &pool).await.unwrap();
+    assert_eq!(usage.raw_bytes(), 100);
+    assert_eq!(usage.reserved_bytes(), 0);
+}
+
+#[sqlx::test]
+async fn users_without_a_quota_can_reserve_anything(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Admin, None).await;
+    let video = create_video(&pool, user_id, "Upload").await;
+    assert!(reserve_upload(&pool, user_id, &video.id, "a", i64::MAX)
+        .await
+        .unwrap());
+}
+
+/// Creates a processed video taking up 100 raw and 50 processed bytes
+async fn create_processed_video(pool: &PgPool, user_id: Uuid) -> Video {
+    let video = create_video(pool, user_id, "Upload").await;
+    assert!(reserve_upload(pool, user_id, &video.id, "upload", 100)
+        .await
+        .unwrap());
+    assert!(record_raw_size(pool, user_id, &video.id, 100)
+        .await
+        .unwrap());
+    Video::mark_completed(pool, &video.id, "master.m3u8", 50)
+        .await
+        .unwrap();
+    Video::by_id(pool, &video.id).await.unwrap()
+}
+
+#[sqlx::test]
+async fn copies_are_charged_once_with_their_original(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Creator, Some(1000)).await;
+    let source = create_processed_video(&pool, user_id).await;
+    let copy = Video::create_copy(&pool, &source, "Copy".to_string(), None)
+        .await
+        .unwrap();
+    Video::create_copy(&pool, &copy, "Copy of copy".to_string(), None)
+        .await
+        .unwrap();
+
+    let usage = StorageUsage::by_user_id(user_id, &pool).await.unwrap();
+    assert_eq!(usage.videos.len(), 3);
+    assert_eq!(usage.total_bytes(), 150);
+    let charged = usage
+        .videos
+        .iter()
+        .find(|usage| usage.total_bytes() > 0)
+        .unwrap();
+    assert_eq!(charged.video_id, source.id);
+}
+
+#[sqlx::test]
+async fn copies_are_charged_once_their_original_is_deleted(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Creator, Some(1000)).await;
+    let source = create_processed_video(&pool, user_id).await;
+    let first = Video::create_copy(&pool, &source, "First".to_string(), None)
+        .await
+        .unwrap();
+    Video::create_copy(&pool, &source, "Second".to_string(), None)
+        .await
+        .unwrap();
+
+    Video::delete(&pool, user_id, vec![source.id.clone()])
+        .await
+        .unwrap();
+    let usage = StorageUsage::by_user_id(user_id, &pool).await.unwrap();
+    assert_eq!(usage.videos.len(), 2);
+    assert_eq!(usage.total_bytes(), 150);
+    let charged = usage
+        .videos
+        .iter()
+        .find(|usage| usage.total_bytes() > 0)
+        .unwrap();
+    assert_eq!(charged.video_id, first.id);
+}
@@ -0,0 +1,303 @@
+//! Checks that every storage backend behaves the same way, so the API and runners can't tell
+//! which one they're using
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use farmhand::{
+    error::StorageError,
+    storage::{LocalStorage, MemoryStorage, PutOptions, Storage, UploadedPart},
+};
+use tokio::io::AsyncReadExt;
+
+const MIB: usize = 1024 * 1024;
+
+async fn read(storage: &dyn Storage, key: &str) -> Result<Vec<u8>, StorageError> {
+    let mut contents = Vec::new();
+    storage.get(key).await?.read_to_end(&mut contents).await?;
+    Ok(contents)
+}
+
+async fn keys(storage: &dyn Storage, prefix: &str) -> Vec<String> {
+    let mut keys: Vec<String> = storage
+        .list(prefix)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|object| object.key)
+        .collect();
+    keys.sort();
+    keys
+}
+
+async fn put_get_head(storage: &dyn Storage) {
+    let options = PutOptions {
+        metadata: HashMap::from([("sha256".to_string(), "abc".to_string())]),
+        ..PutOptions::for_key("videos/a/index.m3u8")
+    };
+    storage
+        .put(
+            "videos/a/index.m3u8",
+            Bytes::from_static(b"#EXTM3U"),
+            &options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        read(storage, "videos/a/index.m3u8").await.unwrap(),
+        b"#EXTM3U"
+    );
+
+    let meta = storage.head("videos/a/index.m3u8").await.unwrap();
+    assert_eq!(meta.key, "videos/a/index.m3u8");
+    assert_eq!(meta.size, 7);
+    assert_eq!(
+        meta.content_type.as_deref(),
+        Some("application/vnd.apple.mpegurl")
+    );
+    assert_eq!(meta.metadata.get("sha256").map(String::as_str), Some("abc"));
+
+    // Putting again replaces the object
+    storage
+        .put(
+            "videos/a/index.m3u8",
+            Bytes::from_static(b"#EXTM3U\n"),
+            &options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(storage.head("videos/a/index.m3u8").await.unwrap().size, 8);
+}
+
+async fn missing_objects(storage: &dyn Storage) {
+    assert!(matches!(
+        storage.get("videos/missing.mp4").await.err(),
+        Some(StorageError::NotFound(_))
+    ));
+    assert!(matches!(
+        storage.head("videos/missing.mp4").await.err(),
+        Some(StorageError::NotFound(_))
+    ));
+    assert!(storage.list("videos/missing/").await.unwrap().is_empty());
+    assert_eq!(storage.delete_prefix("videos/missing/").await.unwrap(), 0);
+}
+
+async fn put_file(storage: &dyn Storage) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("raw.mp4");
+    tokio::fs::write(&path, b"file contents").await.unwrap();
+    storage
+        .put_file("videos/b/raw.mp4", &path, &PutOptions::for_key("raw.mp4"))
+        .await
+        .unwrap();
+    assert_eq!(
+        read(storage, "videos/b/raw.mp4").await.unwrap(),
+        b"file contents"
+    );
+    assert_eq!(
+        storage
+            .head("videos/b/raw.mp4")
+            .await
+            .unwrap()
+            .content_type
+            .as_deref(),
+        Some("video/mp4")
+    );
+}
+
+async fn list_and_delete_prefix(storage: &dyn Storage) {
+    for key in [
+        "videos/c/raw.mp4",
+        "videos/c/720p/index.m3u8",
+        "videos/c/720p/segment_0.ts",
+        "videos/cd/raw.mp4",
+        "other/c/raw.mp4",
+    ] {
+        storage
+            .put(key, Bytes::from_static(b"data"), &PutOptions::for_key(key))
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        keys(storage, "videos/c/").await,
+        vec![
+            "videos/c/720p/index.m3u8",
+            "videos/c/720p/segment_0.ts",
+            "videos/c/raw.mp4"
+        ]
+    );
+    assert_eq!(keys(storage, "videos/").await.len(), 4);
+    let listed = storage.list("videos/c/raw.mp4").await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].size, 4);
+
+    assert_eq!(storage.delete_prefix("videos/c/").await.unwrap(), 3);
+    assert!(keys(storage, "videos/c/").await.is_empty());
+    // Only whole segments of the prefix are matched by a trailing slash
+    assert_eq!(keys(storage, "videos/cd/").await, vec!["videos/cd/raw.mp4"]);
+    assert_eq!(keys(storage, "other/").await, vec!["other/c/raw.mp4"]);
+}
+
+async fn multipart_upload(storage: &dyn Storage) {
+    let key = "videos/d/raw.mp4";
+    let upload_id = storage
+        .create_multipart_upload(key, Some("video/mp4"))
+        .await
+        .unwrap();
+    let uploads = storage.list_multipart_uploads("videos/").await.unwrap();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].key, key);
+    assert_eq!(uploads[0].upload_id, upload_id);
+
+    let first = Bytes::from(vec![1u8; 5 * MIB]);
+    let second = Bytes::from_static(b"end");
+    // Parts can arrive in any order
+    let second_etag = storage
+        .upload_part(key, &upload_id, 2, second.clone())
+        .await
+        .unwrap();
+    let first_etag = storage
+        .upload_part(key, &upload_id, 1, first.clone())
+        .await
+        .unwrap();
+
+    let mut parts = storage.list_parts(key, &upload_id).await.unwrap();
+    parts.sort_by_key(|part| part.part_number);
+    assert_eq!(
+        parts
+            .iter()
+            .map(|part| (part.part_number, part.etag.clone(), part.size))
+            .collect::<Vec<_>>(),
+        vec![
+            (1, first_etag.clone(), Some(5 * MIB as u64)),
+            (2, second_etag.clone(), Some(3)),
+        ]
+    );
+    // Nothing is visible until the upload is completed
+    assert!(storage.head(key).await.is_err());
+
+    storage
+        .complete_multipart_upload(
+            key,
+            &upload_id,
+            &[
+                UploadedPart {
+                    part_number: 1,
+                    etag: first_etag,
+                    size: None,
+                },
+                UploadedPart {
+                    part_number: 2,
+                    etag: second_etag,
+                    size: None,
+                },
+            ],
+        )
+        .await
+        .unwrap();
+    let contents = read(storage, key).await.unwrap();
+    assert_eq!(contents.len(), 5 * MIB + 3);
+    assert_eq!(&contents[..5 * MIB], &first[..]);
+    assert_eq!(&contents[5 * MIB..], b"end");
+    let meta = storage.head(key).await.unwrap();
+    assert_eq!(meta.size, 5 * MIB as u64 + 3);
+    assert_eq!(meta.content_type.as_deref(), Some("video/mp4"));
+    assert!(storage
+        .list_multipart_uploads("videos/")
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn multipart_abort(storage: &dyn Storage) {
+    let key = "videos/e/raw.mp4";
+    let upload_id = storage.create_multipart_upload(key, None).await.unwrap();
+    storage
+        .upload_part(key, &upload_id, 1, Bytes::from_static(b"part"))
+        .await
+        .unwrap();
+    storage
+        .abort_multipart_upload(key, &upload_id)
+        .await
+        .unwrap();
+
+    assert!(storage
+        .list_multipart_uploads("videos/")
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(storage.list_parts(key, &upload_id).await.is_err());
+    assert!(storage
+        .upload_part(key, &upload_id, 2, Bytes::from_static(b"late"))
+        .await
+        .is_err());
+    assert!(storage.head(key).await.is_err());
+}
+
+async fn multipart_unknown_upload(storage: &dyn Storage) {
+    let key = "videos/f/raw.mp4";
+    assert!(storage
+        .upload_part(key, "unknown-upload", 1, Bytes::from_static(b"part"))
+        .await
+        .is_err());
+    assert!(storage
+        .complete_multipart_upload(key, "unknown-upload", &[])
+        .await
+        .is_err());
+}
+
+macro_rules! conformance_tests {
+    ($backend:ident, $storage:expr) => {
+        mod $backend {
+            use super::*;
+
+            #[tokio::test]
+            async fn put_get_head() {
+                let (storage, _dir) = $storage;
+                super::put_get_head(&storage).await;
+            }
+
+            #[tokio::test]
+            async fn missing_objects() {
+                let (storage, _dir) = $storage;
+                super::missing_objects(&storage).await;
+            }
+
+            #[tokio::test]
+            async fn put_file() {
+                let (storage, _dir) = $storage;
+                super::put_file(&storage).await;
+            }
+
+            #[tokio::test]
+            async fn list_and_delete_prefix() {
+                let (storage, _dir) = $storage;
+                super::list_and_delete_prefix(&storage).await;
+            }
+
+            #[tokio::test]
+            async fn multipart_upload() {
+                let (storage, _dir) = $storage;
+                super::multipart_upload(&storage).await;
+            }
+
+            #[tokio::test]
+            async fn multipart_abort() {
+                let (storage, _dir) = $storage;
+                super::multipart_abort(&storage).await;
+            }
+
+            #[tokio::test]
+            async fn multipart_unknown_upload() {
+                let (storage, _dir) = $storage;
+                super::multipart_unknown_upload(&storage).await;
+            }
+        }
+    };
+}
+
+conformance_tests!(memory, (MemoryStorage::new(), ()));
+conformance_tests!(local, {
+    let dir = tempfile::tempdir().unwrap();
+    (LocalStorage::new(dir.path()).unwrap(), dir)
+});
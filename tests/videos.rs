@@ -0,0 +1,262 @@
+//! Checks how videos are kept in the database and who can find them
+
+mod common;
+
+use std::collections::HashSet;
+
+use axum::{extract::Query, http::StatusCode};
+use common::{create_user, create_video};
+use farmhand::{
+    api::routes::video::{videos_for, ListVideosQuery, VideoByID, VideoResponse},
+    db::{users::UserRole, PrivacyStatus, ProcessingStatus, User, Video},
+};
+use serde::de::DeserializeOwned;
+use sqlx::PgPool;
+
+/// Two users, the first with a video of every privacy and the second with a public and a
+/// private one
+struct Fixture {
+    owner: User,
+    other: User,
+    public: String,
+    unlisted: String,
+    private: String,
+    other_public: String,
+    other_private: String,
+}
+
+async fn create_viewer(pool: &PgPool) -> User {
+    let id = create_user(pool, UserRole::Viewer, None).await;
+    User::by_id(id, pool).await.unwrap()
+}
+
+async fn create_video_as(pool: &PgPool, user: &User, privacy: PrivacyStatus) -> String {
+    let video = create_video(pool, user.id, "Video").await;
+    Video::update_metadata(pool, &video.id, None, None, None, Some(privacy))
+        .await
+        .unwrap();
+    video.id
+}
+
+async fn fixture(pool: &PgPool) -> Fixture {
+    let owner = create_viewer(pool).await;
+    let other = create_viewer(pool).await;
+    Fixture {
+        public: create_video_as(pool, &owner, PrivacyStatus::Public).await,
+        unlisted: create_video_as(pool, &owner, PrivacyStatus::Unlisted).await,
+        private: create_video_as(pool, &owner, PrivacyStatus::Private).await,
+        other_public: create_video_as(pool, &other, PrivacyStatus::Public).await,
+        other_private: create_video_as(pool, &other, PrivacyStatus::Private).await,
+        owner,
+        other,
+    }
+}
+
+/// Parses a query string the way the route does
+fn query<T: DeserializeOwned>(params: &str) -> T {
+    let uri = format!("/video?{}", params).parse().unwrap();
+    Query::<T>::try_from_uri(&uri).unwrap().0
+}
+
+/// Gets the ids of the videos in a response and the cursor of the page after it
+fn page(response: VideoResponse) -> (HashSet<String>, Option<String>) {
+    let response = serde_json::to_value(response).unwrap();
+    let ids = response["videos"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|video| video["id"].as_str().unwrap().to_string())
+        .collect();
+    let next_cursor = response["next_cursor"].as_str().map(str::to_string);
+    (ids, next_cursor)
+}
+
+fn set<const N: usize>(ids: [&String; N]) -> HashSet<String> {
+    ids.into_iter().cloned().collect()
+}
+
+async fn by_id(
+    pool: &PgPool,
+    user: Option<&User>,
+    id: &str,
+) -> Result<HashSet<String>, StatusCode> {
+    let video_query = query(&format!("id={}", id));
+    let response = videos_for(pool, user, Some(video_query), query("")).await?;
+    Ok(page(response).0)
+}
+
+async fn list(pool: &PgPool, user: Option<&User>, params: &str) -> HashSet<String> {
+    page(videos_for(pool, user, None, query(params)).await.unwrap()).0
+}
+
+#[sqlx::test]
+async fn only_one_request_holds_an_upload_at_a_time(pool: PgPool) {
+    let user_id = create_user(&pool, UserRole::Viewer, None).await;
+    let video = create_video(&pool, user_id, "Upload").await;
+    let other = create_video(&pool, user_id, "Other upload").await;
+
+    let mut first = pool.begin().await.unwrap();
+    assert!(Video::lock_upload(&mut first, &video.id).await.unwrap());
+    let mut second = pool.begin().await.unwrap();
+    assert!(!Video::lock_upload(&mut second, &video.id).await.unwrap());
+    second.rollback().await.unwrap();
+    // Other uploads aren't held up
+    let mut third = pool.begin().await.unwrap();
+    assert!(Video::lock_upload(&mut third, &other.id).await.unwrap());
+    third.rollback().await.unwrap();
+
+    first.rollback().await.unwrap();
+    let mut second = pool.begin().await.unwrap();
+    assert!(Video::lock_upload(&mut second, &video.id).await.unwrap());
+}
+
+#[sqlx::test]
+async fn videos_by_id_are_only_found_by_those_who_can_watch_them(pool: PgPool) {
+    let f = fixture(&pool).await;
+    for (user, private_visible) in [
+        (None, false),
+        (Some(&f.owner), true),
+        (Some(&f.other), false),
+    ] {
+        for id in [&f.public, &f.unlisted] {
+            assert_eq!(by_id(&pool, user, id).await.unwrap(), set([id]));
+        }
+        match by_id(&pool, user, &f.private).await {
+            Ok(ids) => {
+                assert!(private_visible);
+                assert_eq!(ids, set([&f.private]));
+            }
+            Err(status) => {
+                assert!(!private_visible);
+                assert_eq!(status, StatusCode::NOT_FOUND);
+            }
+        }
+    }
+    assert_eq!(
+        by_id(&pool, None, "missing").await.err(),
+        Some(StatusCode::NOT_FOUND)
+    );
+}
+
+#[sqlx::test]
+async fn videos_by_id_cannot_be_combined_with_an_owner(pool: PgPool) {
+    let f = fixture(&pool).await;
+    let video_query: VideoByID = query(&format!("id={}", f.public));
+    let list_query: ListVideosQuery = query(&format!("owner={}", f.owner.username));
+    assert_eq!(
+        videos_for(&pool, Some(&f.owner), Some(video_query), list_query)
+            .await
+            .err(),
+        Some(StatusCode::NOT_IMPLEMENTED)
+    );
+}
+
+#[sqlx::test]
+async fn listings_only_include_public_videos_and_the_callers_own(pool: PgPool) {
+    let f = fixture(&pool).await;
+    assert_eq!(
+        list(&pool, None, "").await,
+        set([&f.public, &f.other_public])
+    );
+    assert_eq!(
+        list(&pool, Some(&f.owner), "").await,
+        set([&f.public, &f.unlisted, &f.private, &f.other_public])
+    );
+    assert_eq!(
+        list(&pool, Some(&f.other), "").await,
+        set([&f.public, &f.other_public, &f.other_private])
+    );
+}
+
+#[sqlx::test]
+async fn listings_by_owner_only_include_what_the_caller_can_see(pool: PgPool) {
+    let f = fixture(&pool).await;
+    let by_owner = format!("owner={}", f.owner.username);
+    assert_eq!(list(&pool, None, &by_owner).await, set([&f.public]));
+    assert_eq!(
+        list(&pool, Some(&f.owner), &by_owner).await,
+        set([&f.public, &f.unlisted, &f.private])
+    );
+    assert_eq!(
+        list(&pool, Some(&f.other), &by_owner).await,
+        set([&f.public])
+    );
+}
+
+#[sqlx::test]
+async fn filters_cannot_reveal_other_peoples_videos(pool: PgPool) {
+    let f = fixture(&pool).await;
+    let unlisted = "privacy_status=unlisted";
+    assert!(list(&pool, None, unlisted).await.is_empty());
+    assert_eq!(
+        list(&pool, Some(&f.owner), unlisted).await,
+        set([&f.unlisted])
+    );
+    assert!(list(&pool, Some(&f.other), unlisted).await.is_empty());
+    let private = "privacy_status=private";
+    assert!(list(&pool, None, private).await.is_empty());
+    assert_eq!(
+        list(&pool, Some(&f.owner), private).await,
+        set([&f.private])
+    );
+    assert_eq!(
+        list(&pool, Some(&f.other), private).await,
+        set([&f.other_private])
+    );
+    // Asking for the owner on top doesn't get around it either
+    for privacy in [unlisted, private] {
+        let by_owner = format!("{}&owner={}", privacy, f.owner.username);
+        assert!(list(&pool, Some(&f.other), &by_owner).await.is_empty());
+    }
+
+    Video::update_status(&pool, f.private.clone(), ProcessingStatus::Completed)
+        .await
+        .unwrap();
+    let completed = "processing_status=Completed";
+    assert!(list(&pool, None, completed).await.is_empty());
+    assert!(list(&pool, Some(&f.other), completed).await.is_empty());
+    assert_eq!(
+        list(&pool, Some(&f.owner), completed).await,
+        set([&f.private])
+    );
+}
+
+#[sqlx::test]
+async fn pages_follow_on_without_revealing_private_videos(pool: PgPool) {
+    let f = fixture(&pool).await;
+    for (user, expected) in [
+        (None, set([&f.public, &f.other_public])),
+        (
+            Some(&f.owner),
+            set([&f.public, &f.unlisted, &f.private, &f.other_public]),
+        ),
+    ] {
+        for sort in ["newest", "oldest", "title", "duration"] {
+            let mut seen = HashSet::new();
+            let mut cursor: Option<String> = None;
+            loop {
+                let mut params = format!("limit=1&sort={}", sort);
+                if let Some(cursor) = &cursor {
+                    params.push_str(&format!("&cursor={}", cursor));
+                }
+                let response = videos_for(&pool, user, None, query(&params)).await.unwrap();
+                let (ids, next_cursor) = page(response);
+                assert!(ids.len() <= 1);
+                for id in ids {
+                    assert!(seen.insert(id), "a video was listed twice");
+                }
+                cursor = next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+            assert_eq!(seen, expected);
+        }
+    }
+    assert_eq!(
+        videos_for(&pool, None, None, query("cursor=not-a-cursor"))
+            .await
+            .err(),
+        Some(StatusCode::BAD_REQUEST)
+    );
+}
@@ -0,0 +1,42 @@
+//! Checks that jobs working on the same video at once don't get in each others way
+
+use farmhand::queue::workdir::{WorkDir, WorkDirSettings};
+
+fn work_dir(root: &std::path::Path) -> WorkDir {
+    WorkDir::new(WorkDirSettings {
+        root: root.to_path_buf(),
+        min_free_bytes: 0,
+        cache_max_bytes: u64::MAX,
+    })
+    .unwrap()
+}
+
+#[test]
+fn attempts_on_the_same_video_get_their_own_scratch_dirs() {
+    let root = tempfile::tempdir().unwrap();
+    let work_dir = work_dir(root.path());
+    let first = work_dir.scratch_dir("video").unwrap();
+    std::fs::write(first.path().join("master.m3u8"), "#EXTM3U").unwrap();
+
+    let second = work_dir.scratch_dir("video").unwrap();
+    assert_ne!(first.path(), second.path());
+    assert!(second.path().read_dir().unwrap().next().is_none());
+    assert!(first.path().join("master.m3u8").exists());
+}
+
+#[test]
+fn scratch_dirs_are_removed_when_dropped() {
+    let root = tempfile::tempdir().unwrap();
+    let work_dir = work_dir(root.path());
+    let scratch_dir = work_dir.scratch_dir("video").unwrap();
+    let path = scratch_dir.path().to_path_buf();
+    std::fs::write(path.join("segment_0.ts"), "data").unwrap();
+
+    drop(scratch_dir);
+    assert!(!path.exists());
+    // Removing it explicitly leaves nothing for the drop to do
+    let scratch_dir = work_dir.scratch_dir("video").unwrap();
+    let path = scratch_dir.path().to_path_buf();
+    scratch_dir.remove().unwrap();
+    assert!(!path.exists());
+}